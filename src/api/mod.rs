@@ -0,0 +1,468 @@
+//Local REST API so other programs (ERP etc.) can push invoices in and read them back.
+//Every route except /openapi.json needs the `Authorization: Bearer <token>` header.
+use axum::{
+    body::Body,
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{Local, NaiveDate};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{fs, sync::Arc};
+use tokio::{net::TcpListener, sync::Mutex};
+
+use crate::error::InvoiceError;
+use crate::invoicer::{init, Racun};
+use crate::schema;
+use crate::storage;
+use crate::validation;
+
+mod openapi;
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+pub const TOKEN_VARIABLE: &str = "INVOICE_API_TOKEN";
+
+#[derive(Clone)]
+struct AppState {
+    token: Arc<String>,
+    //Creating invoices is serialized so two requests can't take the same number
+    create_lock: Arc<Mutex<()>>,
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<InvoiceError> for ApiError {
+    fn from(err: InvoiceError) -> Self {
+        let status = match err {
+            InvoiceError::Validation(_) | InvoiceError::Serialization(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            InvoiceError::DuplicateNumber(_) => StatusCode::CONFLICT,
+            InvoiceError::NotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, err)
+    }
+}
+
+impl From<tokio::task::JoinError> for ApiError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InvoiceSummary {
+    invoice_number: i32,
+    invoice_date: NaiveDate,
+    due_date: NaiveDate,
+    company: String,
+    partner: String,
+    status: String,
+    paid_date: Option<NaiveDate>,
+    net: f64,
+    vat: f64,
+    gross: f64,
+    currency: String,
+}
+
+impl From<&Racun> for InvoiceSummary {
+    fn from(racun: &Racun) -> Self {
+        let invoice = &racun.invoice;
+        let totals = invoice.totals();
+        Self {
+            invoice_number: invoice.invoice_number,
+            invoice_date: invoice.invoice_date,
+            due_date: invoice.due_date,
+            company: invoice.company.company_name.clone(),
+            partner: invoice.partner.partner_name.clone(),
+            status: invoice.status.to_string(),
+            paid_date: invoice.paid_date,
+            net: totals.net,
+            vat: totals.vat,
+            gross: totals.gross,
+            currency: invoice.invoice_currency.clone(),
+        }
+    }
+}
+
+//Query parameters of GET /invoices, dates are YYYY-MM-DD
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct InvoiceFilter {
+    status: Option<String>,
+    partner: Option<String>,
+    company: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl InvoiceFilter {
+    fn matches(&self, racun: &Racun) -> bool {
+        let invoice = &racun.invoice;
+        let contains = |value: &str, query: &Option<String>| {
+            query
+                .as_ref()
+                .is_none_or(|query| value.to_lowercase().contains(&query.to_lowercase()))
+        };
+        let date = invoice.invoice_date;
+        let in_range = |bound: &Option<NaiveDate>, after: bool| {
+            bound.is_none_or(|bound| if after { date >= bound } else { date <= bound })
+        };
+        self.status
+            .as_ref()
+            .is_none_or(|status| invoice.status.to_string().eq_ignore_ascii_case(status))
+            && contains(&invoice.partner.partner_name, &self.partner)
+            && contains(&invoice.company.company_name, &self.company)
+            && in_range(&self.from, true)
+            && in_range(&self.to, false)
+    }
+}
+
+//Compared in constant time so the token can't be guessed from how long a refusal takes
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && openssl::memcmp::eq(given.as_bytes(), token.as_bytes())
+}
+
+async fn require_token(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| same_token(token, &state.token));
+    if !authorized {
+        return ApiError::new(StatusCode::UNAUTHORIZED, "Missing or wrong API token")
+            .into_response();
+    }
+    next.run(request).await
+}
+
+async fn load(invoice_number: i32) -> Result<Racun, ApiError> {
+    Ok(tokio::task::spawn_blocking(move || storage::load(invoice_number)).await??)
+}
+
+async fn list_invoices(Query(filter): Query<InvoiceFilter>) -> Json<Vec<InvoiceSummary>> {
+    let invoices = tokio::task::spawn_blocking(storage::load_all)
+        .await
+        .unwrap_or_default();
+    Json(
+        invoices
+            .iter()
+            .filter(|racun| filter.matches(racun))
+            .map(InvoiceSummary::from)
+            .collect(),
+    )
+}
+
+async fn create_invoice(
+    State(state): State<AppState>,
+    Json(value): Json<Value>,
+) -> Result<Response, ApiError> {
+    //Clients may still send older documents
+    let mut racun = schema::from_value(value)?;
+    racun.prepare()?;
+    validation::validate(&racun).check()?;
+    let invoice_number = racun.invoice.invoice_number;
+    let _guard = state.create_lock.lock().await;
+    let warnings = tokio::task::spawn_blocking(move || init(racun)).await??;
+    let racun = load(invoice_number).await?;
+    let mut response = (
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/invoices/{}", invoice_number))],
+        Json(InvoiceSummary::from(&racun)),
+    )
+        .into_response();
+    //Miscellaneous warning (199), like an invoice queued while FURS is offline
+    for warning in warnings {
+        if let Ok(value) = HeaderValue::from_str(&format!("199 - \"{}\"", warning.escape_default()))
+        {
+            response.headers_mut().append(header::WARNING, value);
+        }
+    }
+    Ok(response)
+}
+
+async fn get_invoice(Path(invoice_number): Path<i32>) -> Result<Json<Racun>, ApiError> {
+    Ok(Json(load(invoice_number).await?))
+}
+
+async fn get_pdf(Path(invoice_number): Path<i32>) -> Result<Response, ApiError> {
+    let pdf = storage::pdf_path(invoice_number);
+    let data = tokio::fs::read(&pdf)
+        .await
+        .map_err(|_| InvoiceError::NotFound(invoice_number))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"racun {}.pdf\"", invoice_number),
+            ),
+        ],
+        Body::from(data),
+    )
+        .into_response())
+}
+
+async fn mark_paid(Path(invoice_number): Path<i32>) -> Result<Json<InvoiceSummary>, ApiError> {
+    let mut racun = load(invoice_number).await?;
+    racun.invoice.mark_paid(Local::now().date_naive());
+    let racun = tokio::task::spawn_blocking(move || storage::save(&racun).map(|_| racun)).await??;
+    Ok(Json(InvoiceSummary::from(&racun)))
+}
+
+async fn openapi_document() -> Json<serde_json::Value> {
+    Json(openapi::document())
+}
+
+pub fn router(token: String) -> Router {
+    let state = AppState {
+        token: Arc::new(token),
+        create_lock: Arc::new(Mutex::new(())),
+    };
+    Router::new()
+        .route("/invoices", get(list_invoices).post(create_invoice))
+        .route("/invoices/:number", get(get_invoice))
+        .route("/invoices/:number/pdf", get(get_pdf))
+        .route("/invoices/:number/paid", post(mark_paid))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .route("/openapi.json", get(openapi_document))
+        .with_state(state)
+}
+
+//Token from the argument, then the environment, otherwise a random one for this run
+pub fn resolve_token(token: Option<String>) -> String {
+    token
+        .or_else(|| std::env::var(TOKEN_VARIABLE).ok())
+        .filter(|token| !token.is_empty())
+        .unwrap_or_else(|| {
+            let token: String = rand::thread_rng()
+                .sample_iter(&rand::distributions::Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect();
+            println!("Generated API token: {}", token);
+            token
+        })
+}
+
+pub async fn serve(address: &str, token: String) -> Result<(), InvoiceError> {
+    fs::create_dir_all(storage::invoices_dir())?;
+    let listener = TcpListener::bind(address).await?;
+    println!("Invoice API listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, router(token)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{manifest_dir, Workspace};
+    use std::io::Read;
+
+    const TOKEN: &str = "test-token";
+
+    struct Reply {
+        status: u16,
+        content_type: String,
+        body: Vec<u8>,
+    }
+
+    impl Reply {
+        fn json(&self) -> Value {
+            serde_json::from_slice(&self.body).unwrap()
+        }
+    }
+
+    //Address of the API running on a free port
+    async fn start() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move { axum::serve(listener, router(TOKEN.to_string())).await });
+        address
+    }
+
+    async fn call(
+        method: &str,
+        url: String,
+        token: Option<&'static str>,
+        body: Option<Value>,
+    ) -> Reply {
+        let method = method.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut request = ureq::request(&method, &url);
+            if let Some(token) = token {
+                request = request.set("Authorization", &format!("Bearer {}", token));
+            }
+            let result = match body {
+                Some(body) => request.send_json(body),
+                None => request.call(),
+            };
+            let response = match result {
+                Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+                Err(err) => panic!("{}", err),
+            };
+            let status = response.status();
+            let content_type = response.content_type().to_string();
+            let mut body = Vec::new();
+            response.into_reader().read_to_end(&mut body).unwrap();
+            Reply {
+                status,
+                content_type,
+                body,
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    //The invoice from data.json with another number and partner
+    fn document(number: i32, partner: &str, date: &str) -> Value {
+        let data = fs::read_to_string(manifest_dir().join("data.json")).unwrap();
+        let mut document: Value = serde_json::from_str(&data).unwrap();
+        document["invoice"]["invoiceNumber"] = json!(number);
+        document["invoice"]["invoiceDate"] = json!(date);
+        document["invoice"]["status"] = json!("UNPAID");
+        document["invoice"]["partner"]["partnerName"] = json!(partner);
+        document
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(same_token("secret", "secret"));
+        assert!(!same_token("secre", "secret"));
+        assert!(!same_token("secret!", "secret"));
+        assert!(!same_token("Secret", "secret"));
+    }
+
+    #[tokio::test]
+    async fn requests_without_the_token_are_refused() {
+        let _workspace = Workspace::new();
+        let address = start().await;
+        let url = format!("http://{}/invoices", address);
+        assert_eq!(call("GET", url.clone(), None, None).await.status, 401);
+        assert_eq!(call("GET", url, Some("wrong"), None).await.status, 401);
+        let openapi = call(
+            "GET",
+            format!("http://{}/openapi.json", address),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(openapi.status, 200);
+    }
+
+    #[tokio::test]
+    async fn invoices_are_created_listed_downloaded_and_paid() {
+        let _workspace = Workspace::with_fonts();
+        let address = start().await;
+        let invoices = format!("http://{}/invoices", address);
+
+        let created = call(
+            "POST",
+            invoices.clone(),
+            Some(TOKEN),
+            Some(document(12, "Acme d.o.o.", "21.05.2022")),
+        )
+        .await;
+        assert_eq!(
+            created.status,
+            201,
+            "{}",
+            String::from_utf8_lossy(&created.body)
+        );
+        let created = created.json();
+        assert_eq!(created["invoiceNumber"], 12);
+        assert_eq!(created["status"], "UNPAID");
+        assert_eq!(created["gross"], 185.71);
+        let other = document(13, "Beta d.o.o.", "01.06.2022");
+        assert_eq!(
+            call("POST", invoices.clone(), Some(TOKEN), Some(other))
+                .await
+                .status,
+            201
+        );
+
+        let duplicate = document(12, "Acme d.o.o.", "21.05.2022");
+        let duplicate = call("POST", invoices.clone(), Some(TOKEN), Some(duplicate)).await;
+        assert_eq!(duplicate.status, 409);
+
+        let numbers = |reply: Reply| -> Vec<i64> {
+            reply
+                .json()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|summary| summary["invoiceNumber"].as_i64().unwrap())
+                .collect()
+        };
+        let list = |query: &str| call("GET", format!("{}{}", invoices, query), Some(TOKEN), None);
+        let mut all = numbers(list("").await);
+        all.sort();
+        assert_eq!(all, vec![12, 13]);
+        assert_eq!(numbers(list("?partner=acme").await), vec![12]);
+        assert_eq!(numbers(list("?from=2022-05-22").await), vec![13]);
+        assert_eq!(numbers(list("?to=2022-05-31").await), vec![12]);
+        assert!(numbers(list("?company=nobody").await).is_empty());
+
+        let pdf = call("GET", format!("{}/12/pdf", invoices), Some(TOKEN), None).await;
+        assert_eq!(pdf.status, 200);
+        assert_eq!(pdf.content_type, "application/pdf");
+        assert!(pdf.body.starts_with(b"%PDF"));
+        let missing = call("GET", format!("{}/99/pdf", invoices), Some(TOKEN), None).await;
+        assert_eq!(missing.status, 404);
+
+        let paid = call("POST", format!("{}/13/paid", invoices), Some(TOKEN), None).await;
+        assert_eq!(paid.status, 200);
+        let paid = paid.json();
+        assert_eq!(paid["status"], "PAID");
+        assert_eq!(
+            paid["paidDate"],
+            Local::now().date_naive().to_string().as_str()
+        );
+        assert_eq!(numbers(list("?status=paid").await), vec![13]);
+        assert_eq!(numbers(list("?status=unpaid").await), vec![12]);
+        let stored = call("GET", format!("{}/13", invoices), Some(TOKEN), None).await;
+        assert_eq!(stored.json()["invoice"]["status"], "PAID");
+    }
+
+    #[tokio::test]
+    async fn invalid_invoices_are_unprocessable() {
+        let _workspace = Workspace::new();
+        let address = start().await;
+        let invoices = format!("http://{}/invoices", address);
+        let mut invalid = document(12, "Acme d.o.o.", "21.05.2022");
+        invalid["invoice"]["company"]["companyIban"] = json!("SI56 1234 1234 1234 123");
+        let reply = call("POST", invoices.clone(), Some(TOKEN), Some(invalid)).await;
+        assert_eq!(reply.status, 422);
+        assert!(reply.json()["error"].as_str().unwrap().contains("IBAN"));
+        let reply = call("POST", invoices, Some(TOKEN), Some(json!({ "invoice": 1 }))).await;
+        assert_eq!(reply.status, 422);
+        assert!(!storage::exists(12));
+    }
+}
@@ -0,0 +1,151 @@
+use serde_json::{json, Value};
+
+use crate::schema;
+
+//OpenAPI 3 description of the routes in api/mod.rs, served at /openapi.json
+pub fn document() -> Value {
+    let number = json!({
+        "name": "number",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "integer" }
+    });
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } }
+        })
+    };
+    let summary = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/InvoiceSummary" } } }
+        })
+    };
+    let query = |name: &str, format: Option<&str>, description: &str| {
+        let mut schema = json!({ "type": "string" });
+        if let Some(format) = format {
+            schema["format"] = json!(format);
+        }
+        json!({ "name": name, "in": "query", "required": false, "description": description, "schema": schema })
+    };
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Invoice API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Create, list and fetch invoices. Invoices use the same JSON as data.json."
+        },
+        "servers": [{ "url": "http://127.0.0.1:8080" }],
+        "security": [{ "bearer": [] }],
+        "paths": {
+            "/invoices": {
+                "get": {
+                    "summary": "List invoices",
+                    "parameters": [
+                        query("status", None, "PAID or UNPAID"),
+                        query("partner", None, "Part of the partner name"),
+                        query("company", None, "Part of the company name"),
+                        query("from", Some("date"), "Invoice date on or after"),
+                        query("to", Some("date"), "Invoice date on or before")
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "Invoices sorted by number",
+                            "content": { "application/json": { "schema": {
+                                "type": "array",
+                                "items": { "$ref": "#/components/schemas/InvoiceSummary" }
+                            } } }
+                        },
+                        "401": error("Missing or wrong token")
+                    }
+                },
+                "post": {
+                    "summary": "Create and render an invoice",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Racun" } } }
+                    },
+                    "responses": {
+                        "201": summary("Invoice created, the Location header points to it"),
+                        "401": error("Missing or wrong token"),
+                        "409": error("Invoice number already exists"),
+                        "422": error("Invoice data is invalid"),
+                        "500": error("Rendering failed")
+                    }
+                }
+            },
+            "/invoices/{number}": {
+                "get": {
+                    "summary": "Get an invoice",
+                    "parameters": [number],
+                    "responses": {
+                        "200": {
+                            "description": "The stored invoice",
+                            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Racun" } } }
+                        },
+                        "401": error("Missing or wrong token"),
+                        "404": error("Invoice doesn't exist")
+                    }
+                }
+            },
+            "/invoices/{number}/pdf": {
+                "get": {
+                    "summary": "Download the invoice pdf",
+                    "parameters": [number],
+                    "responses": {
+                        "200": {
+                            "description": "The rendered invoice",
+                            "content": { "application/pdf": { "schema": { "type": "string", "format": "binary" } } }
+                        },
+                        "401": error("Missing or wrong token"),
+                        "404": error("Invoice doesn't exist")
+                    }
+                }
+            },
+            "/invoices/{number}/paid": {
+                "post": {
+                    "summary": "Mark an invoice as paid",
+                    "parameters": [number],
+                    "responses": {
+                        "200": summary("The updated invoice"),
+                        "401": error("Missing or wrong token"),
+                        "404": error("Invoice doesn't exist")
+                    }
+                }
+            }
+        },
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" }
+            },
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "properties": { "error": { "type": "string" } }
+                },
+                "InvoiceSummary": {
+                    "type": "object",
+                    "properties": {
+                        "invoiceNumber": { "type": "integer" },
+                        "invoiceDate": { "type": "string", "format": "date" },
+                        "dueDate": { "type": "string", "format": "date" },
+                        "company": { "type": "string" },
+                        "partner": { "type": "string" },
+                        "status": { "type": "string", "enum": ["PAID", "UNPAID"] },
+                        "paidDate": { "type": "string", "format": "date", "nullable": true },
+                        "net": { "type": "number" },
+                        "vat": { "type": "number" },
+                        "gross": { "type": "number" },
+                        "currency": { "type": "string" }
+                    }
+                }
+            }
+        }
+    });
+    //Racun and its parts are generated from the Rust types
+    if let Some(schemas) = document["components"]["schemas"].as_object_mut() {
+        schemas.extend(schema::openapi_schemas());
+    }
+    document
+}
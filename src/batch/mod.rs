@@ -0,0 +1,763 @@
+//Batch generation for month-end billing. A CSV with one row per service line (rows with the
+//same `invoice` key make one invoice) or a JSON lines file with one Racun per line is loaded
+//and validated first, then every valid invoice is generated on a pool of worker threads.
+//Nothing aborts the batch, every problem ends up in the report with the rows it came from.
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::{self, read_to_string},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::dates;
+use crate::error::InvoiceError;
+use crate::invoicer::{init, Discount, Partner, Racun, Service};
+use crate::registry::{Catalog, PartnerBook};
+use crate::schema;
+use crate::storage;
+use crate::validation;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchStage {
+    Import,
+    Validation,
+    Render,
+}
+
+impl Display for BatchStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatchStage::Import => write!(f, "import"),
+            BatchStage::Validation => write!(f, "validation"),
+            BatchStage::Render => write!(f, "render"),
+        }
+    }
+}
+
+//Line numbers are the lines in the source file, the CSV header is line 1
+#[derive(Debug, Clone)]
+pub struct RowError {
+    pub rows: Vec<usize>,
+    pub invoice_number: Option<i32>,
+    pub stage: BatchStage,
+    pub message: String,
+}
+
+impl RowError {
+    fn new(
+        rows: Vec<usize>,
+        invoice_number: Option<i32>,
+        stage: BatchStage,
+        message: impl ToString,
+    ) -> Self {
+        Self {
+            rows,
+            invoice_number,
+            stage,
+            message: message.to_string(),
+        }
+    }
+
+    fn rows_label(&self) -> String {
+        self.rows
+            .iter()
+            .map(|row| row.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}", self.rows_label())?;
+        if let Some(number) = self.invoice_number {
+            write!(f, " (invoice {})", number)?;
+        }
+        write!(f, " {}: {}", self.stage, self.message)
+    }
+}
+
+pub struct BatchJob {
+    pub racun: Racun,
+    pub rows: Vec<usize>,
+    //CSV invoices without an invoiceNumber get one only after they pass validation
+    pub auto_number: bool,
+}
+
+#[derive(Default)]
+pub struct BatchReport {
+    pub generated: Vec<i32>,
+    pub errors: Vec<RowError>,
+    //Generated invoices with a problem that didn't stop them, like FURS being offline
+    pub warnings: Vec<String>,
+}
+
+impl BatchReport {
+    pub fn has_stage(&self, stage: BatchStage) -> bool {
+        self.errors.iter().any(|error| error.stage == stage)
+    }
+
+    pub fn save_errors(&self, path: &Path) -> Result<(), InvoiceError> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["lines", "invoiceNumber", "stage", "message"])?;
+        for error in &self.errors {
+            writer.write_record([
+                error.rows_label(),
+                error
+                    .invoice_number
+                    .map(|number| number.to_string())
+                    .unwrap_or_default(),
+                error.stage.to_string(),
+                error.message.clone(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+//Shared between the workers and whoever draws the progress bar
+#[derive(Default)]
+pub struct Progress {
+    pub total: AtomicUsize,
+    pub done: AtomicUsize,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        self.done.load(Ordering::Relaxed) as f32 / total as f32
+    }
+}
+
+//Where the error report of a batch file goes by default
+pub fn report_path(source: &Path) -> PathBuf {
+    source.with_extension("errors.csv")
+}
+
+//Everything a CSV row can carry, empty cells fall back to the template, partner book or catalog
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct CsvRow {
+    invoice: String,
+    invoice_number: Option<i32>,
+    invoice_date: String,
+    service_date: String,
+    service_period_end: String,
+    payment_terms: String,
+    partner_name: String,
+    partner_address: String,
+    partner_postal_code: String,
+    partner_vat_id: String,
+    service_code: String,
+    service_name: String,
+    quantity: Option<f64>,
+    unit: String,
+    price: Option<f64>,
+    vat_rate: Option<f64>,
+    discount_percent: Option<f64>,
+}
+
+//Builds invoices from the template, the company profile should already be applied to it
+pub struct Importer<'a> {
+    template: &'a Racun,
+    partners: &'a PartnerBook,
+    catalog: &'a Catalog,
+    next_number: i32,
+    used_numbers: HashSet<i32>,
+}
+
+impl<'a> Importer<'a> {
+    pub fn new(template: &'a Racun, partners: &'a PartnerBook, catalog: &'a Catalog) -> Self {
+        Self {
+            template,
+            partners,
+            catalog,
+            next_number: template.invoice.invoice_number,
+            used_numbers: HashSet::new(),
+        }
+    }
+
+    //.csv files are read as service rows, anything else as JSON lines
+    pub fn load(&mut self, path: &Path) -> (Vec<BatchJob>, Vec<RowError>) {
+        let data = match read_to_string(path) {
+            Ok(data) => data,
+            Err(err) => {
+                return (
+                    Vec::new(),
+                    vec![RowError::new(Vec::new(), None, BatchStage::Import, err)],
+                )
+            }
+        };
+        let is_csv = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+        if is_csv {
+            self.load_csv(&data)
+        } else {
+            self.load_json_lines(&data)
+        }
+    }
+
+    pub fn load_json_lines(&mut self, data: &str) -> (Vec<BatchJob>, Vec<RowError>) {
+        let mut jobs = Vec::new();
+        let mut errors = Vec::new();
+        for (index, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match schema::from_str(line) {
+                Ok(racun) => {
+                    self.used_numbers.insert(racun.invoice.invoice_number);
+                    jobs.push(BatchJob {
+                        racun,
+                        rows: vec![index + 1],
+                        auto_number: false,
+                    });
+                }
+                Err(err) => errors.push(RowError::new(
+                    vec![index + 1],
+                    None,
+                    BatchStage::Import,
+                    err,
+                )),
+            }
+        }
+        (jobs, errors)
+    }
+
+    pub fn load_csv(&mut self, data: &str) -> (Vec<BatchJob>, Vec<RowError>) {
+        let mut errors = Vec::new();
+        //Keeps the order in which the invoice keys first appear
+        let mut keys: Vec<String> = Vec::new();
+        let mut groups: HashMap<String, Vec<(usize, CsvRow)>> = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes());
+        for (index, row) in reader.deserialize::<CsvRow>().enumerate() {
+            let line = index + 2;
+            match row {
+                Ok(row) if row.invoice.is_empty() => errors.push(RowError::new(
+                    vec![line],
+                    None,
+                    BatchStage::Import,
+                    "Missing invoice key",
+                )),
+                Ok(row) => {
+                    if let Some(number) = row.invoice_number {
+                        self.used_numbers.insert(number);
+                    }
+                    if !groups.contains_key(&row.invoice) {
+                        keys.push(row.invoice.clone());
+                    }
+                    groups
+                        .entry(row.invoice.clone())
+                        .or_default()
+                        .push((line, row));
+                }
+                Err(err) => errors.push(RowError::new(vec![line], None, BatchStage::Import, err)),
+            }
+        }
+        let mut jobs = Vec::new();
+        for key in keys {
+            let rows = groups.remove(&key).unwrap_or_default();
+            let lines: Vec<usize> = rows.iter().map(|(line, _)| *line).collect();
+            match self.build_invoice(&rows) {
+                Ok(racun) => jobs.push(BatchJob {
+                    auto_number: rows[0].1.invoice_number.is_none(),
+                    racun,
+                    rows: lines,
+                }),
+                Err(err) => errors.push(RowError::new(lines, None, BatchStage::Import, err)),
+            }
+        }
+        (jobs, errors)
+    }
+
+    //Next number that isn't stored yet or taken by another invoice in the file
+    fn allocate_number(&mut self) -> i32 {
+        while storage::exists(self.next_number) || self.used_numbers.contains(&self.next_number) {
+            self.next_number += 1;
+        }
+        self.used_numbers.insert(self.next_number);
+        self.next_number
+    }
+
+    fn build_invoice(&mut self, rows: &[(usize, CsvRow)]) -> Result<Racun, String> {
+        //Invoice level columns are taken from the first row of the group
+        let first = &rows[0].1;
+        let mut racun = self.template.clone();
+        let invoice = &mut racun.invoice;
+        invoice.services.clear();
+        invoice.fiscal = None;
+        invoice.invoice_number = first.invoice_number.unwrap_or_default();
+        let parse_date =
+            |value: &str| dates::parse(value).ok_or_else(|| format!("Invalid date {}", value));
+        if !first.invoice_date.is_empty() {
+            //Keep the template's payment period
+            let period = invoice.due_date - invoice.invoice_date;
+            invoice.invoice_date = parse_date(&first.invoice_date)?;
+            invoice.service_date = invoice.invoice_date;
+            invoice.due_date = invoice.invoice_date + period;
+        }
+        if !first.service_date.is_empty() {
+            invoice.service_date = parse_date(&first.service_date)?;
+        }
+        if !first.service_period_end.is_empty() {
+            invoice.service_period_end = Some(parse_date(&first.service_period_end)?);
+        }
+        let partner = self.find_partner(first);
+        invoice.set_partner(&partner);
+        //The row's terms win over the partner's
+        if !first.payment_terms.is_empty() {
+            invoice.payment_terms = Some(first.payment_terms.parse()?);
+        }
+        invoice.apply_payment_terms();
+        for (line, row) in rows {
+            let service = self
+                .build_service(row, &invoice.invoice_currency)
+                .map_err(|err| match rows.len() {
+                    1 => err,
+                    _ => format!("line {}: {}", line, err),
+                })?;
+            invoice.services.push(service);
+        }
+        Ok(racun)
+    }
+
+    //Splits the jobs into the ones that can be generated and errors for the rest,
+    //then numbers the valid ones that didn't bring their own number
+    pub fn validate(&mut self, jobs: Vec<BatchJob>) -> (Vec<BatchJob>, Vec<RowError>) {
+        let mut counts: HashMap<i32, usize> = HashMap::new();
+        for job in jobs.iter().filter(|job| !job.auto_number) {
+            *counts.entry(job.racun.invoice.invoice_number).or_default() += 1;
+        }
+        let mut valid = Vec::new();
+        let mut errors = Vec::new();
+        for mut job in jobs {
+            let prepared = job.racun.prepare();
+            let invoice = &job.racun.invoice;
+            let number = (!job.auto_number).then_some(invoice.invoice_number);
+            //Auto numbered jobs get their number only after they pass
+            let mut problems: Vec<String> = validation::validate(&job.racun)
+                .errors()
+                .filter(|issue| number.is_some() || issue.path != "invoice.invoiceNumber")
+                .map(|issue| issue.to_string())
+                .collect();
+            if let Err(err) = prepared {
+                problems.push(err.to_string());
+            }
+            if let Some(number) = number {
+                if storage::exists(number) {
+                    problems.push(format!("Invoice {} already exists", number));
+                }
+                if counts[&number] > 1 {
+                    problems.push(format!(
+                        "Invoice number {} is used more than once in the file",
+                        number
+                    ));
+                }
+            }
+            if problems.is_empty() {
+                if job.auto_number {
+                    job.racun.invoice.invoice_number = self.allocate_number();
+                }
+                valid.push(job);
+            } else {
+                errors.push(RowError::new(
+                    job.rows,
+                    number,
+                    BatchStage::Validation,
+                    problems.join(", "),
+                ));
+            }
+        }
+        (valid, errors)
+    }
+
+    //Partners from the address book are matched by VAT ID, then by name
+    fn find_partner(&self, row: &CsvRow) -> Partner {
+        let known = self.partners.partners.iter().find(|partner| {
+            if row.partner_vat_id.is_empty() {
+                partner.partner_name.eq_ignore_ascii_case(&row.partner_name)
+            } else {
+                partner.partner_vat_id == row.partner_vat_id
+            }
+        });
+        match known {
+            Some(partner) => partner.clone(),
+            None => Partner {
+                partner_name: row.partner_name.clone(),
+                partner_address: row.partner_address.clone(),
+                partner_postal_code: row.partner_postal_code.clone(),
+                partner_vat_id: row.partner_vat_id.clone(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn build_service(&self, row: &CsvRow, currency: &str) -> Result<Service, String> {
+        let mut service = if row.service_code.is_empty() {
+            Service {
+                service_quantity: 1.0,
+                service_tax: self.template.invoice.company.company_vat_rate,
+                service_currency: currency.to_string(),
+                ..Default::default()
+            }
+        } else {
+            self.catalog
+                .items
+                .iter()
+                .find(|item| item.code == row.service_code)
+                .map(|item| item.to_service(currency))
+                .ok_or_else(|| format!("Unknown catalog code {}", row.service_code))?
+        };
+        if !row.service_name.is_empty() {
+            service.service_name = row.service_name.clone();
+        }
+        if !row.unit.is_empty() {
+            service.service_unit = row.unit.clone();
+        }
+        if let Some(quantity) = row.quantity {
+            service.service_quantity = quantity;
+        }
+        if let Some(price) = row.price {
+            service.service_price = price;
+        }
+        if let Some(rate) = row.vat_rate {
+            service.service_tax = rate;
+        }
+        if let Some(percent) = row.discount_percent.filter(|percent| *percent > 0.0) {
+            service.service_discount = Some(Discount::Percent(percent));
+        }
+        if service.service_name.is_empty() {
+            return Err("Missing service name".to_string());
+        }
+        if row.service_code.is_empty() && row.price.is_none() {
+            return Err("Missing price".to_string());
+        }
+        Ok(service)
+    }
+}
+
+//Generates the invoices on `threads` workers, the progress is updated after every invoice
+pub fn generate(jobs: Vec<BatchJob>, threads: usize, progress: &Progress) -> BatchReport {
+    progress.total.store(jobs.len(), Ordering::Relaxed);
+    progress.done.store(0, Ordering::Relaxed);
+    let queue = Mutex::new(jobs.into_iter());
+    let report = Mutex::new(BatchReport::default());
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let job = match queue.lock().unwrap_or_else(|err| err.into_inner()).next() {
+                    Some(job) => job,
+                    None => break,
+                };
+                let number = job.racun.invoice.invoice_number;
+                //A panic in one invoice shouldn't take the rest of the batch down
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    init(job.racun).map_err(|err| err.to_string())
+                }))
+                .unwrap_or_else(|_| Err("Rendering panicked".to_string()));
+                let mut report = report.lock().unwrap_or_else(|err| err.into_inner());
+                match result {
+                    Ok(warnings) => {
+                        report.generated.push(number);
+                        report.warnings.extend(warnings);
+                    }
+                    Err(err) => report.errors.push(RowError::new(
+                        job.rows,
+                        Some(number),
+                        BatchStage::Render,
+                        err,
+                    )),
+                }
+                progress.done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+    });
+    let mut report = report.into_inner().unwrap_or_else(|err| err.into_inner());
+    report.generated.sort();
+    report
+}
+
+//Load, validate and generate in one go, import and validation errors come first in the report
+pub fn run(
+    importer: &mut Importer,
+    path: &Path,
+    threads: usize,
+    progress: &Progress,
+) -> BatchReport {
+    let (jobs, mut errors) = importer.load(path);
+    let (jobs, invalid) = importer.validate(jobs);
+    errors.extend(invalid);
+    let mut report = generate(jobs, threads, progress);
+    errors.append(&mut report.errors);
+    report.errors = errors;
+    report
+}
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(4)
+}
+
+//Removes a stale report so an old one isn't mistaken for the latest run
+pub fn clear_report(path: &Path) {
+    let _ = fs::remove_file(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{sample, Workspace};
+
+    const HEADER: &str =
+        "invoice,invoiceNumber,partnerName,partnerVatId,serviceName,quantity,price,vatRate";
+
+    fn csv(rows: &[&str]) -> String {
+        let mut data = HEADER.to_string();
+        for row in rows {
+            data.push('\n');
+            data.push_str(row);
+        }
+        data
+    }
+
+    fn import(template: &Racun, data: &str) -> (Vec<BatchJob>, Vec<RowError>) {
+        let partners = PartnerBook {
+            partners: vec![Partner {
+                partner_name: "Known d.o.o.".to_string(),
+                partner_address: "Known street 1".to_string(),
+                partner_vat_id: "SI12345679".to_string(),
+                ..Default::default()
+            }],
+        };
+        let catalog = Catalog::default();
+        let mut importer = Importer::new(template, &partners, &catalog);
+        let (jobs, mut errors) = importer.load_csv(data);
+        let (jobs, invalid) = importer.validate(jobs);
+        errors.extend(invalid);
+        (jobs, errors)
+    }
+
+    fn numbers(jobs: &[BatchJob]) -> Vec<i32> {
+        jobs.iter()
+            .map(|job| job.racun.invoice.invoice_number)
+            .collect()
+    }
+
+    #[test]
+    fn csv_rows_with_the_same_key_make_one_invoice() {
+        let _workspace = Workspace::new();
+        let template = sample();
+        let (jobs, errors) = import(
+            &template,
+            &csv(&[
+                "a,,First,,Towing,1,100,22",
+                "a,,,,Storage,2,15.5,9.5",
+                "b,,,SI12345679,Repair,3,20,",
+                "a,,,,Fuel,1,30,22",
+            ]),
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].rows, vec![2, 3, 5]);
+        assert_eq!(jobs[1].rows, vec![4]);
+        let first = &jobs[0].racun.invoice;
+        assert_eq!(first.partner.partner_name, "First");
+        let services: Vec<(&str, f64, f64, f64)> = first
+            .services
+            .iter()
+            .map(|service| {
+                (
+                    service.service_name.as_str(),
+                    service.service_quantity,
+                    service.service_price,
+                    service.service_tax,
+                )
+            })
+            .collect();
+        assert_eq!(
+            services,
+            vec![
+                ("Towing", 1.0, 100.0, 22.0),
+                ("Storage", 2.0, 15.5, 9.5),
+                ("Fuel", 1.0, 30.0, 22.0)
+            ]
+        );
+        //The partner comes from the address book, the rate from the company
+        let second = &jobs[1].racun.invoice;
+        assert_eq!(second.partner.partner_name, "Known d.o.o.");
+        assert_eq!(
+            second.services[0].service_tax,
+            template.invoice.company.company_vat_rate
+        );
+    }
+
+    #[test]
+    fn invoices_without_a_number_are_numbered_after_validation() {
+        let _workspace = Workspace::new();
+        let template = sample();
+        storage::save(&template).unwrap();
+        let (jobs, errors) = import(
+            &template,
+            &csv(&[
+                "a,,First,,Towing,1,100,22",
+                "b,14,Second,,Towing,1,100,22",
+                //Fails validation, so it doesn't use up a number
+                "c,,Third,,Towing,-1,100,22",
+                "d,,Fourth,,Towing,1,100,22",
+            ]),
+        );
+        //12 is stored and 14 is taken by b
+        assert_eq!(numbers(&jobs), vec![13, 14, 15]);
+        assert!(jobs[0].auto_number);
+        assert!(!jobs[1].auto_number);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rows, vec![4]);
+        assert_eq!(errors[0].invoice_number, None);
+        assert_eq!(errors[0].stage, BatchStage::Validation);
+    }
+
+    #[test]
+    fn duplicate_numbers_are_reported_with_their_rows() {
+        let _workspace = Workspace::new();
+        let template = sample();
+        storage::save(&template).unwrap();
+        let (jobs, errors) = import(
+            &template,
+            &csv(&[
+                "a,12,First,,Towing,1,100,22",
+                "b,20,Second,,Towing,1,100,22",
+                "c,20,Third,,Towing,1,100,22",
+                "d,21,Fourth,,Towing,1,100,22",
+            ]),
+        );
+        assert_eq!(numbers(&jobs), vec![21]);
+        let errors: Vec<(Vec<usize>, Option<i32>, &str)> = errors
+            .iter()
+            .map(|error| {
+                (
+                    error.rows.clone(),
+                    error.invoice_number,
+                    error.message.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (vec![2], Some(12), "Invoice 12 already exists"),
+                (
+                    vec![3],
+                    Some(20),
+                    "Invoice number 20 is used more than once in the file"
+                ),
+                (
+                    vec![4],
+                    Some(20),
+                    "Invoice number 20 is used more than once in the file"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_rows_do_not_stop_the_others() {
+        let _workspace = Workspace::new();
+        let template = sample();
+        let (jobs, errors) = import(
+            &template,
+            &csv(&[
+                ",,Nobody,,Towing,1,100,22",
+                "a,,First,,Towing,1,,22",
+                "b,,Second,,Towing,lots,100,22",
+                "c,,Third,,Towing,1,100,22",
+                "d,,Fourth,,,1,100,22",
+            ]),
+        );
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].racun.invoice.partner.partner_name, "Third");
+        let errors: Vec<(Vec<usize>, BatchStage)> = errors
+            .iter()
+            .map(|error| (error.rows.clone(), error.stage))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (vec![2], BatchStage::Import),
+                (vec![4], BatchStage::Import),
+                (vec![3], BatchStage::Import),
+                (vec![6], BatchStage::Import),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_lines_hold_one_invoice_per_line() {
+        let _workspace = Workspace::new();
+        let template = sample();
+        let mut second = sample();
+        second.invoice.invoice_number = 13;
+        let data = format!(
+            "{}\n\n{{\"invoice\": \n{}\n",
+            serde_json::to_string(&template).unwrap(),
+            serde_json::to_string(&second).unwrap()
+        );
+        let partners = PartnerBook::default();
+        let catalog = Catalog::default();
+        let mut importer = Importer::new(&template, &partners, &catalog);
+        let (jobs, errors) = importer.load_json_lines(&data);
+        assert_eq!(numbers(&jobs), vec![12, 13]);
+        assert_eq!(jobs[0].rows, vec![1]);
+        assert_eq!(jobs[1].rows, vec![4]);
+        assert!(!jobs[0].auto_number);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rows, vec![3]);
+        assert_eq!(errors[0].stage, BatchStage::Import);
+    }
+
+    #[test]
+    fn run_generates_the_valid_invoices_and_reports_the_rest() {
+        let workspace = Workspace::with_fonts();
+        workspace.write(
+            "batch.csv",
+            csv(&[
+                "a,30,First,,Towing,1,100,22",
+                "b,31,Second,,Towing,1,,22",
+                "c,32,Third,,Towing,1,50,9.5",
+            ]),
+        );
+        let template = sample();
+        let partners = PartnerBook::default();
+        let catalog = Catalog::default();
+        let mut importer = Importer::new(&template, &partners, &catalog);
+        let progress = Progress::default();
+        let report = run(&mut importer, Path::new("batch.csv"), 2, &progress);
+        assert_eq!(report.generated, vec![30, 32]);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].rows, vec![3]);
+        assert_eq!(progress.fraction(), 1.0);
+        assert!(storage::exists(30) && storage::exists(32));
+        assert!(!storage::exists(31));
+
+        report.save_errors(Path::new("batch.errors.csv")).unwrap();
+        let saved = fs::read_to_string("batch.errors.csv").unwrap();
+        assert_eq!(
+            saved,
+            "lines,invoiceNumber,stage,message\n3,,import,Missing price\n"
+        );
+    }
+}
@@ -0,0 +1,891 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use std::{
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    thread,
+    time::Duration,
+};
+
+use crate::api;
+use crate::batch::{self, BatchStage, Importer, Progress};
+use crate::currency;
+use crate::dates;
+use crate::email;
+use crate::error::InvoiceError;
+use crate::fiscal::{self, FiscalConfig};
+use crate::invoicer::{display_vat_id, format_quantity, init, Racun, Totals};
+use crate::registry::{
+    Catalog, ChartOfAccounts, CompanyProfile, CompanyProfiles, ExchangeRates, PartnerBook,
+};
+use crate::render;
+use crate::reporting::{edavki, eu, export, journal, vat, Period};
+#[cfg(all(unix, feature = "discord"))]
+use crate::rpc;
+use crate::schema;
+use crate::storage;
+use crate::validation::{self, Severity};
+
+//Exit codes, clap itself exits with 2 on bad arguments
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_VALIDATION: i32 = 3;
+pub const EXIT_DUPLICATE: i32 = 4;
+pub const EXIT_RENDER: i32 = 5;
+
+#[derive(Parser)]
+#[command(
+    name = "rust_pdf",
+    about = "Invoice generator, starts the GUI when run without a command"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate an invoice from a JSON file shaped like data.json
+    Generate { file: PathBuf },
+    /// Check an invoice JSON file without generating it
+    Validate { file: PathBuf },
+    /// Generate many invoices from a CSV of service lines or a JSON lines file
+    Batch {
+        file: PathBuf,
+        /// Where the per-row error report goes, defaults to <file>.errors.csv
+        #[arg(long)]
+        report: Option<PathBuf>,
+        #[arg(long, default_value_t = batch::default_threads())]
+        threads: usize,
+    },
+    /// List stored invoices
+    List,
+    /// Show one invoice
+    Show { number: i32 },
+    /// Export stored invoices, all of them unless numbers are given
+    Export {
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+        #[arg(long)]
+        output: Option<PathBuf>,
+        numbers: Vec<i32>,
+    },
+    /// Delete an invoice with its pdf and image
+    Delete { number: i32 },
+    /// Render the jpg preview of a stored invoice again
+    RenderPreview { number: i32 },
+    /// Register the business premise from furs.json with FURS
+    RegisterPremise,
+    /// Upgrade stored invoices to the current schema version
+    Migrate,
+    /// Import ECB reference rates (eurofxref-daily.xml or eurofxref-hist.xml)
+    ImportRates { file: PathBuf },
+    /// Book of issued invoices (KIR) for a period like 2024, 2024-05 or 2024-Q2
+    Kir {
+        #[arg(long)]
+        period: Period,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: KirFormat,
+        /// Required for xlsx and pdf
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Company profile, all companies when left out
+        #[arg(long)]
+        company: Option<String>,
+    },
+    /// Output VAT figures of the DDV-O return for a period
+    DdvO {
+        #[arg(long)]
+        period: Period,
+        #[arg(long, value_enum, default_value = "text")]
+        format: DdvOFormat,
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Company profile, all companies when left out
+        #[arg(long)]
+        company: Option<String>,
+    },
+    /// Recapitulative statement (RP-O) of reverse charge supplies per partner VAT ID
+    RpO {
+        #[arg(long)]
+        period: Period,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: EuFormat,
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Company profile, all companies when left out
+        #[arg(long)]
+        company: Option<String>,
+    },
+    /// OSS return of VAT charged at other member states' rates, per country and rate
+    Oss {
+        #[arg(long)]
+        period: Period,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: EuFormat,
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Company profile, all companies when left out
+        #[arg(long)]
+        company: Option<String>,
+    },
+    /// Journal entries of invoices and payments for bookkeeping, accounts come from accounts.json
+    Journal {
+        #[arg(long)]
+        period: Period,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: JournalFormat,
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Company profile, all companies when left out
+        #[arg(long)]
+        company: Option<String>,
+    },
+    /// Email the invoice PDF to the partner, settings come from email.json
+    Email {
+        number: i32,
+        /// Instead of the partner's address
+        #[arg(long)]
+        to: Option<String>,
+        /// Write the message to an .eml file instead of sending it
+        #[arg(long)]
+        eml: Option<PathBuf>,
+    },
+    /// Run a local stand-in for an SMTP server
+    SmtpMock {
+        #[arg(default_value = "127.0.0.1:2525")]
+        address: String,
+        /// Folder where received messages are saved as .eml files
+        #[arg(long)]
+        folder: Option<PathBuf>,
+    },
+    /// Print the JSON Schema of invoice files
+    Schema {
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Send invoices that were issued while FURS was offline
+    Resubmit,
+    /// Run the REST API, the token falls back to INVOICE_API_TOKEN or a generated one
+    Serve {
+        #[arg(long, default_value = api::DEFAULT_ADDRESS)]
+        address: String,
+        #[arg(long)]
+        token: Option<String>,
+    },
+    /// Run a local stand-in for the FURS endpoint
+    FursMock {
+        #[arg(default_value = "127.0.0.1:9002")]
+        address: String,
+    },
+    /// Run a local stand-in for the Discord IPC socket, start the app with XDG_RUNTIME_DIR set to its folder
+    #[cfg(all(unix, feature = "discord"))]
+    DiscordMock {
+        /// Folder for the discord-ipc-0 socket, the one the app looks in by default
+        #[arg(long)]
+        folder: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KirFormat {
+    Csv,
+    Xlsx,
+    Pdf,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DdvOFormat {
+    Text,
+    //eDavki import document
+    Xml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EuFormat {
+    Csv,
+    //eDavki import document
+    Xml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum JournalFormat {
+    Csv,
+    //Ledger and hledger journal
+    Ledger,
+}
+
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i32, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<InvoiceError> for Failure {
+    fn from(err: InvoiceError) -> Self {
+        let code = match err {
+            InvoiceError::Validation(_) | InvoiceError::Serialization(_) => EXIT_VALIDATION,
+            InvoiceError::DuplicateNumber(_) => EXIT_DUPLICATE,
+            InvoiceError::Font(_) | InvoiceError::Render(_) => EXIT_RENDER,
+            _ => EXIT_ERROR,
+        };
+        Failure::new(code, err)
+    }
+}
+
+//Runs the command line and returns the exit code
+pub fn run() -> i32 {
+    run_from(std::env::args_os())
+}
+
+fn run_from<I, T>(args: I) -> i32
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let cli = Cli::parse_from(args);
+    let result = match cli.command {
+        Command::Generate { file } => generate(&file),
+        Command::Validate { file } => validate(&file),
+        Command::Batch {
+            file,
+            report,
+            threads,
+        } => run_batch(&file, report, threads),
+        Command::List => {
+            list();
+            Ok(())
+        }
+        Command::Show { number } => show(number),
+        Command::Export {
+            format,
+            output,
+            numbers,
+        } => export(format, output, &numbers),
+        Command::Delete { number } => storage::delete(number)
+            .map(|_| println!("Invoice {} deleted", number))
+            .map_err(Failure::from),
+        Command::RenderPreview { number } => render::render_preview(number).map_err(Failure::from),
+        Command::RegisterPremise => register_premise(),
+        Command::Migrate => storage::migrate()
+            .map(|migrated| println!("{} invoices migrated", migrated))
+            .map_err(Failure::from),
+        Command::Kir {
+            period,
+            format,
+            output,
+            company,
+        } => kir(period, format, output, company),
+        Command::DdvO {
+            period,
+            format,
+            output,
+            company,
+        } => ddv_o(period, format, output, company),
+        Command::RpO {
+            period,
+            format,
+            output,
+            company,
+        } => rp_o(period, format, output, company),
+        Command::Oss {
+            period,
+            format,
+            output,
+            company,
+        } => oss(period, format, output, company),
+        Command::Journal {
+            period,
+            format,
+            output,
+            company,
+        } => journal(period, format, output, company),
+        Command::Email { number, to, eml } => email(number, to, eml),
+        Command::SmtpMock { address, folder } => email::mock::serve(&address, folder)
+            .map_err(InvoiceError::from)
+            .map_err(Failure::from),
+        Command::Schema { output } => write_schema(output),
+        Command::ImportRates { file } => import_rates(&file),
+        Command::Resubmit => resubmit(),
+        Command::Serve { address, token } => serve(&address, token),
+        Command::FursMock { address } => fiscal::mock::serve(&address)
+            .map_err(InvoiceError::from)
+            .map_err(Failure::from),
+        #[cfg(all(unix, feature = "discord"))]
+        Command::DiscordMock { folder } => {
+            rpc::mock::serve(&folder.unwrap_or_else(rpc::ipc::socket_folder))
+                .map_err(InvoiceError::from)
+                .map_err(Failure::from)
+        }
+    };
+    match result {
+        Ok(_) => EXIT_OK,
+        Err(failure) => {
+            eprintln!("Error: {}", failure.message);
+            failure.code
+        }
+    }
+}
+
+fn read_racun(file: &PathBuf) -> Result<Racun, InvoiceError> {
+    let data = read_to_string(file)?;
+    let mut racun = schema::from_str(&data)?;
+    racun.prepare()?;
+    Ok(racun)
+}
+
+fn generate(file: &PathBuf) -> Result<(), Failure> {
+    let racun = read_racun(file)?;
+    for warning in validation::validate(&racun).warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+    let number = racun.invoice.invoice_number;
+    for warning in init(racun)? {
+        eprintln!("Warning: {}", warning);
+    }
+    println!("{}", storage::pdf_path(number).display());
+    Ok(())
+}
+
+fn resubmit() -> Result<(), Failure> {
+    let resubmission = fiscal::resubmit_queue()?;
+    for (number, err) in &resubmission.queued {
+        eprintln!("Invoice {} is still queued: {}", number, err);
+    }
+    println!("{} invoices confirmed by FURS", resubmission.sent);
+    Ok(())
+}
+
+fn validate(file: &PathBuf) -> Result<(), Failure> {
+    let racun = read_racun(file)?;
+    let report = validation::validate(&racun);
+    for issue in &report.issues {
+        let severity = match issue.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        println!("{}: {}", severity, issue);
+    }
+    let errors = report.errors().count();
+    if errors > 0 {
+        return Err(Failure::new(
+            EXIT_VALIDATION,
+            format!(
+                "{} problems must be fixed before the invoice can be issued",
+                errors
+            ),
+        ));
+    }
+    println!("Invoice is valid");
+    Ok(())
+}
+
+fn import_rates(file: &Path) -> Result<(), Failure> {
+    let mut rates = ExchangeRates::load()?;
+    let added = rates.import_ecb(file)?;
+    rates.save()?;
+    println!("{} new exchange rates imported", added);
+    Ok(())
+}
+
+fn write_schema(output: Option<PathBuf>) -> Result<(), Failure> {
+    let schema =
+        serde_json::to_string_pretty(&schema::json_schema()).map_err(InvoiceError::from)?;
+    match output {
+        Some(path) => fs::write(path, schema + "\n").map_err(InvoiceError::from)?,
+        None => println!("{}", schema),
+    }
+    Ok(())
+}
+
+fn run_batch(file: &Path, report_path: Option<PathBuf>, threads: usize) -> Result<(), Failure> {
+    let mut template = Racun::parse_from_file()?;
+    let mut profiles = CompanyProfiles::load()?;
+    //Same first run seeding as the gui, so batch numbers continue from data.json
+    if profiles.profiles.is_empty() {
+        let profile = CompanyProfile::from_racun(&template);
+        profiles.active_profile = profile.profile_name.clone();
+        profiles.profiles.push(profile);
+    }
+    if let Some(profile) = profiles.active() {
+        profile.apply(&mut template);
+    }
+    let partners = PartnerBook::load()?;
+    let catalog = Catalog::load()?;
+    let progress = Progress::default();
+    let report = thread::scope(|scope| {
+        let worker = scope.spawn(|| {
+            let mut importer = Importer::new(&template, &partners, &catalog);
+            batch::run(&mut importer, file, threads, &progress)
+        });
+        let mut shown = usize::MAX;
+        while !worker.is_finished() {
+            let done = progress.done.load(Ordering::Relaxed);
+            if done != shown {
+                print_progress(&progress);
+                shown = done;
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+        print_progress(&progress);
+        eprintln!();
+        worker.join()
+    })
+    .map_err(|_| Failure::new(EXIT_ERROR, "Batch worker panicked"))?;
+
+    for number in &report.generated {
+        profiles.use_number(&template.invoice.company_profile, *number);
+    }
+    profiles.save()?;
+    for warning in &report.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    println!("{} invoices generated", report.generated.len());
+    let report_path = report_path.unwrap_or_else(|| batch::report_path(file));
+    if report.errors.is_empty() {
+        batch::clear_report(&report_path);
+        return Ok(());
+    }
+    for error in &report.errors {
+        eprintln!("{}", error);
+    }
+    report.save_errors(&report_path)?;
+    let code = if report.has_stage(BatchStage::Render) {
+        EXIT_RENDER
+    } else {
+        EXIT_VALIDATION
+    };
+    Err(Failure::new(
+        code,
+        format!(
+            "{} problems, see {}",
+            report.errors.len(),
+            report_path.display()
+        ),
+    ))
+}
+
+fn print_progress(progress: &Progress) {
+    const WIDTH: usize = 30;
+    let filled = (progress.fraction() * WIDTH as f32) as usize;
+    eprint!(
+        "\r[{}{}] {}/{}",
+        "#".repeat(filled),
+        ".".repeat(WIDTH - filled),
+        progress.done.load(Ordering::Relaxed),
+        progress.total.load(Ordering::Relaxed)
+    );
+}
+
+//Stored invoices, the broken ones are reported and left out
+fn load_all() -> Vec<Racun> {
+    let (invoices, errors) = storage::scan();
+    for (path, err) in errors {
+        eprintln!("Skipping {}: {}", path.display(), err);
+    }
+    invoices
+}
+
+fn list() {
+    for racun in load_all() {
+        let totals = racun.invoice.totals();
+        println!(
+            "{:>6}  {:<12} {:<30} {:<7} {:>12.2} {}",
+            racun.invoice.invoice_number,
+            dates::format(racun.invoice.invoice_date),
+            racun.invoice.partner.partner_name,
+            racun.invoice.status,
+            totals.gross,
+            racun.invoice.invoice_currency
+        );
+    }
+}
+
+fn show(number: i32) -> Result<(), Failure> {
+    let racun = storage::load(number)?;
+    let invoice = &racun.invoice;
+    let totals = invoice.totals();
+    println!("Invoice {} ({})", invoice.invoice_number, invoice.status);
+    println!("Company:      {}", invoice.company.company_name);
+    println!(
+        "Partner:      {}, {}, {} ({})",
+        invoice.partner.partner_name,
+        invoice.partner.partner_address,
+        invoice.partner.partner_postal_code,
+        display_vat_id(&invoice.partner.partner_vat_id)
+    );
+    println!("Invoice date: {}", dates::format(invoice.invoice_date));
+    println!("Service date: {}", invoice.service_period());
+    match invoice.payment_terms {
+        Some(terms) => println!(
+            "Due date:     {} ({})",
+            dates::format(invoice.due_date),
+            terms
+        ),
+        None => println!("Due date:     {}", dates::format(invoice.due_date)),
+    }
+    println!("VAT:          {}", invoice.vat_treatment);
+    for service in &invoice.services {
+        println!(
+            "  {:<40} {:>10} {:>10.2} {:>6}% {:>10.2}",
+            service.service_name.lines().next().unwrap_or_default(),
+            format_quantity(service),
+            service.service_price,
+            invoice.line_rate(service),
+            service.net_amount()
+        );
+    }
+    if totals.document_discount > 0.0 {
+        println!("Discount:     {:.2}", totals.document_discount);
+    }
+    println!(
+        "Net:          {:.2} {}",
+        totals.net, invoice.invoice_currency
+    );
+    for line in &totals.vat_lines {
+        println!(
+            "VAT {}%:     {:.2} of {:.2}",
+            line.rate, line.vat, line.base
+        );
+    }
+    println!(
+        "Total:        {:.2} {}",
+        totals.gross, invoice.invoice_currency
+    );
+    if let (Some(rate), Some(eur)) = (&invoice.exchange_rate, invoice.totals_eur()) {
+        println!(
+            "In EUR:       net {:.2}, VAT {:.2}, total {:.2} (1 EUR = {} {} on {})",
+            eur.net,
+            eur.vat,
+            eur.gross,
+            rate.rate,
+            rate.currency,
+            dates::format(rate.date)
+        );
+    }
+    if let Some(fiscal) = &invoice.fiscal {
+        println!("ZOI:          {}", fiscal.zoi);
+        println!(
+            "EOR:          {}",
+            fiscal.eor.as_deref().unwrap_or("queued")
+        );
+    }
+    for sent in email::sent_log(number) {
+        match sent.error {
+            None => println!("Sent:         {} to {}", sent.sent_at, sent.to),
+            Some(error) => println!("Not sent:     {} to {}: {}", sent.sent_at, sent.to, error),
+        }
+    }
+    Ok(())
+}
+
+fn export(format: ExportFormat, output: Option<PathBuf>, numbers: &[i32]) -> Result<(), Failure> {
+    let invoices: Vec<Racun> = load_all()
+        .into_iter()
+        .filter(|racun| numbers.is_empty() || numbers.contains(&racun.invoice.invoice_number))
+        .collect();
+    let data = match format {
+        ExportFormat::Json => {
+            serde_json::to_string_pretty(&invoices).map_err(InvoiceError::from)?
+        }
+        ExportFormat::Csv => export_csv(&invoices)?,
+    };
+    match output {
+        Some(path) => Ok(fs::write(path, data).map_err(InvoiceError::from)?),
+        None => {
+            print!("{}", data);
+            Ok(())
+        }
+    }
+}
+
+//One row per invoice with the totals
+fn export_csv(invoices: &[Racun]) -> Result<String, InvoiceError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "number",
+        "invoiceDate",
+        "dueDate",
+        "company",
+        "partner",
+        "partnerVatId",
+        "status",
+        "vatTreatment",
+        "linesNet",
+        "documentDiscount",
+        "net",
+        "vat",
+        "gross",
+        "currency",
+        "exchangeRate",
+        "netEur",
+        "vatEur",
+        "grossEur",
+    ])?;
+    for racun in invoices {
+        let invoice = &racun.invoice;
+        let totals = invoice.totals();
+        //Foreign currency invoices without a rate have no EUR amounts
+        let (rate, eur) = if invoice.invoice_currency == currency::EUR {
+            (String::new(), Some(totals.clone()))
+        } else {
+            (
+                invoice
+                    .exchange_rate
+                    .as_ref()
+                    .map_or(String::new(), |rate| rate.rate.to_string()),
+                invoice.totals_eur(),
+            )
+        };
+        let eur_amount = |amount: fn(&Totals) -> f64| {
+            eur.as_ref()
+                .map_or(String::new(), |eur| format!("{:.2}", amount(eur)))
+        };
+        writer.write_record([
+            invoice.invoice_number.to_string(),
+            invoice.invoice_date.to_string(),
+            invoice.due_date.to_string(),
+            invoice.company.company_name.clone(),
+            invoice.partner.partner_name.clone(),
+            invoice.partner.partner_vat_id.clone(),
+            invoice.status.to_string(),
+            invoice.vat_treatment.to_string(),
+            format!("{:.2}", totals.lines_net),
+            format!("{:.2}", totals.document_discount),
+            format!("{:.2}", totals.net),
+            format!("{:.2}", totals.vat),
+            format!("{:.2}", totals.gross),
+            invoice.invoice_currency.clone(),
+            rate,
+            eur_amount(|totals| totals.net),
+            eur_amount(|totals| totals.vat),
+            eur_amount(|totals| totals.gross),
+        ])?;
+    }
+    let data = writer
+        .into_inner()
+        .map_err(|err| InvoiceError::Io(err.into_error()))?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+fn find_profile(
+    profiles: &CompanyProfiles,
+    company: Option<String>,
+) -> Result<Option<&CompanyProfile>, InvoiceError> {
+    match company {
+        Some(name) => profiles
+            .profiles
+            .iter()
+            .find(|profile| profile.profile_name == name)
+            .map(Some)
+            .ok_or_else(|| {
+                InvoiceError::validation(format!("There is no company profile '{}'", name))
+            }),
+        None => Ok(None),
+    }
+}
+
+fn build_kir(period: Period, company: Option<String>) -> Result<vat::Kir, InvoiceError> {
+    let profiles = CompanyProfiles::load()?;
+    let profile = find_profile(&profiles, company)?;
+    vat::kir(&load_all(), period, profile)
+}
+
+//Text goes to stdout without --output, files are always written
+fn write_report(output: Option<PathBuf>, data: Vec<u8>) -> Result<(), Failure> {
+    match output {
+        Some(path) => {
+            fs::write(&path, data).map_err(InvoiceError::from)?;
+            println!("{}", path.display());
+        }
+        None => print!("{}", String::from_utf8_lossy(&data)),
+    }
+    Ok(())
+}
+
+fn kir(
+    period: Period,
+    format: KirFormat,
+    output: Option<PathBuf>,
+    company: Option<String>,
+) -> Result<(), Failure> {
+    if output.is_none() && !matches!(format, KirFormat::Csv) {
+        return Err(Failure::new(EXIT_ERROR, "xlsx and pdf need --output"));
+    }
+    let kir = build_kir(period, company)?;
+    let data = match format {
+        KirFormat::Csv => export::kir_csv(&kir)?.into_bytes(),
+        KirFormat::Xlsx => export::kir_xlsx(&kir)?,
+        KirFormat::Pdf => export::kir_pdf(&kir)?,
+    };
+    write_report(output, data)
+}
+
+fn ddv_o(
+    period: Period,
+    format: DdvOFormat,
+    output: Option<PathBuf>,
+    company: Option<String>,
+) -> Result<(), Failure> {
+    let kir = build_kir(period, company)?;
+    let ddv_o = kir.ddv_o();
+    let data = match format {
+        DdvOFormat::Text => {
+            let mut text = format!("DDV-O {}\n", ddv_o.period);
+            for (field, label, value) in ddv_o.fields() {
+                text += &format!("{:>4}  {:<52} {:>12.2}\n", field, label, value);
+            }
+            text
+        }
+        DdvOFormat::Xml => edavki::ddv_o_xml(&ddv_o, &kir.company),
+    };
+    write_report(output, data.into_bytes())
+}
+
+fn rp_o(
+    period: Period,
+    format: EuFormat,
+    output: Option<PathBuf>,
+    company: Option<String>,
+) -> Result<(), Failure> {
+    let profiles = CompanyProfiles::load()?;
+    let profile = find_profile(&profiles, company)?;
+    let rpo = eu::rpo(&load_all(), period, profile)?;
+    let data = match format {
+        EuFormat::Csv => export::rpo_csv(&rpo)?,
+        EuFormat::Xml => edavki::rp_o_xml(&rpo, &rpo.company),
+    };
+    write_report(output, data.into_bytes())
+}
+
+fn oss(
+    period: Period,
+    format: EuFormat,
+    output: Option<PathBuf>,
+    company: Option<String>,
+) -> Result<(), Failure> {
+    let profiles = CompanyProfiles::load()?;
+    let profile = find_profile(&profiles, company)?;
+    let oss = eu::oss(&load_all(), period, profile)?;
+    let data = match format {
+        EuFormat::Csv => export::oss_csv(&oss)?,
+        EuFormat::Xml => edavki::oss_xml(&oss, &oss.company),
+    };
+    write_report(output, data.into_bytes())
+}
+
+fn journal(
+    period: Period,
+    format: JournalFormat,
+    output: Option<PathBuf>,
+    company: Option<String>,
+) -> Result<(), Failure> {
+    let profiles = CompanyProfiles::load()?;
+    let profile = find_profile(&profiles, company)?;
+    let chart = ChartOfAccounts::load()?;
+    let entries = journal::journal(&load_all(), period, profile, &chart)?;
+    let data = match format {
+        JournalFormat::Csv => export::journal_csv(&entries)?,
+        JournalFormat::Ledger => export::journal_ledger(&entries),
+    };
+    write_report(output, data.into_bytes())
+}
+
+fn email(number: i32, to: Option<String>, eml: Option<PathBuf>) -> Result<(), Failure> {
+    match eml {
+        Some(path) => {
+            email::export_eml(number, to.as_deref(), &path)?;
+            println!("{}", path.display());
+        }
+        None => {
+            let to = email::send_invoice(number, to.as_deref())?;
+            println!("Invoice {} sent to {}", number, to);
+        }
+    }
+    Ok(())
+}
+
+fn register_premise() -> Result<(), Failure> {
+    let config =
+        FiscalConfig::load()?.ok_or_else(|| Failure::new(EXIT_ERROR, "furs.json is missing"))?;
+    fiscal::register_premise(&config).map_err(InvoiceError::from)?;
+    println!("Business premise registered");
+    Ok(())
+}
+
+fn serve(address: &str, token: Option<String>) -> Result<(), Failure> {
+    let token = api::resolve_token(token);
+    let runtime = tokio::runtime::Runtime::new().map_err(InvoiceError::from)?;
+    runtime.block_on(api::serve(address, token))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{manifest_dir, sample, Workspace};
+
+    fn run(args: &[&str]) -> i32 {
+        run_from(std::iter::once("rust_pdf").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn invoice_errors_map_to_their_exit_code() {
+        let code = |err: InvoiceError| Failure::from(err).code;
+        assert_eq!(code(InvoiceError::validation("bad")), EXIT_VALIDATION);
+        let json = serde_json::from_str::<Racun>("{").unwrap_err();
+        assert_eq!(code(InvoiceError::Serialization(json)), EXIT_VALIDATION);
+        assert_eq!(code(InvoiceError::DuplicateNumber(12)), EXIT_DUPLICATE);
+        assert_eq!(code(InvoiceError::Font("missing".to_string())), EXIT_RENDER);
+        assert_eq!(code(InvoiceError::render("broken")), EXIT_RENDER);
+        assert_eq!(code(InvoiceError::NotFound(12)), EXIT_ERROR);
+        let io = std::io::Error::other("disk");
+        assert_eq!(code(InvoiceError::Io(io)), EXIT_ERROR);
+    }
+
+    #[test]
+    fn validate_exits_with_3_for_invalid_invoices() {
+        let workspace = Workspace::new();
+        let data = fs::read_to_string(manifest_dir().join("data.json")).unwrap();
+        workspace.write("valid.json", &data);
+        assert_eq!(run(&["validate", "valid.json"]), EXIT_OK);
+        let mut invalid = sample();
+        invalid.invoice.partner.partner_name.clear();
+        workspace.write("invalid.json", serde_json::to_string(&invalid).unwrap());
+        assert_eq!(run(&["validate", "invalid.json"]), EXIT_VALIDATION);
+        workspace.write("broken.json", "{");
+        assert_eq!(run(&["validate", "broken.json"]), EXIT_VALIDATION);
+        assert_eq!(run(&["validate", "missing.json"]), EXIT_ERROR);
+    }
+
+    #[test]
+    fn show_exits_with_1_for_missing_invoices() {
+        let _workspace = Workspace::new();
+        assert_eq!(run(&["show", "12"]), EXIT_ERROR);
+        storage::save(&sample()).unwrap();
+        assert_eq!(run(&["show", "12"]), EXIT_OK);
+    }
+
+    #[test]
+    fn generate_exits_with_4_for_duplicates_and_5_without_fonts() {
+        let workspace = Workspace::new();
+        workspace.write("invoice.json", serde_json::to_string(&sample()).unwrap());
+        assert_eq!(run(&["generate", "invoice.json"]), EXIT_RENDER);
+        storage::save(&sample()).unwrap();
+        assert_eq!(run(&["generate", "invoice.json"]), EXIT_DUPLICATE);
+    }
+}
@@ -0,0 +1,217 @@
+//ISO 4217 currencies and exchange rates to EUR. Foreign currency invoices carry the ECB
+//reference rate they were issued with, the VAT summary is converted to EUR with it.
+use chrono::NaiveDate;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::error::InvoiceError;
+
+pub const EUR: &str = "EUR";
+
+//Code and the symbol printed on invoices, EUR and every currency in the ECB reference rates
+const CURRENCIES: [(&str, &str); 31] = [
+    ("EUR", "€"),
+    ("USD", "$"),
+    ("JPY", "¥"),
+    ("BGN", "лв"),
+    ("CZK", "Kč"),
+    ("DKK", "kr."),
+    ("GBP", "£"),
+    ("HUF", "Ft"),
+    ("PLN", "zł"),
+    ("RON", "lei"),
+    ("SEK", "kr"),
+    ("CHF", "CHF"),
+    ("ISK", "kr"),
+    ("NOK", "kr"),
+    ("TRY", "₺"),
+    ("AUD", "A$"),
+    ("BRL", "R$"),
+    ("CAD", "C$"),
+    ("CNY", "CN¥"),
+    ("HKD", "HK$"),
+    ("IDR", "Rp"),
+    ("ILS", "₪"),
+    ("INR", "₹"),
+    ("KRW", "₩"),
+    ("MXN", "MX$"),
+    ("MYR", "RM"),
+    ("NZD", "NZ$"),
+    ("PHP", "₱"),
+    ("SGD", "S$"),
+    ("THB", "฿"),
+    ("ZAR", "R"),
+];
+
+pub fn codes() -> impl Iterator<Item = &'static str> {
+    CURRENCIES.iter().map(|(code, _)| *code)
+}
+
+pub fn is_known(code: &str) -> bool {
+    CURRENCIES.iter().any(|(known, _)| *known == code)
+}
+
+//ISO code for what older files have in the currency fields, "€", "EURO" and "eur" are all EUR
+pub fn normalize(value: &str) -> Option<&'static str> {
+    let value = value.trim();
+    let upper = value.to_uppercase();
+    if upper == "EURO" {
+        return Some(EUR);
+    }
+    CURRENCIES
+        .iter()
+        .find(|(code, _)| *code == upper)
+        .or_else(|| CURRENCIES.iter().find(|(_, symbol)| *symbol == value))
+        .map(|(code, _)| *code)
+}
+
+//Printed after amounts, unknown codes are printed as they are
+pub fn symbol(code: &str) -> &str {
+    CURRENCIES
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map_or(code, |(_, symbol)| *symbol)
+}
+
+//1 EUR = rate units of the currency, the way the ECB publishes them
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub date: NaiveDate,
+    pub rate: f64,
+}
+
+impl ExchangeRate {
+    pub fn to_eur(&self, amount: f64) -> f64 {
+        if self.rate > 0.0 {
+            amount / self.rate
+        } else {
+            0.0
+        }
+    }
+}
+
+//Reads eurofxref-daily.xml or eurofxref-hist.xml from the ECB. Every <Cube time=".."> holds
+//<Cube currency=".." rate=".."/> entries, nothing else in the file is needed.
+pub fn parse_ecb_xml(data: &str) -> Result<Vec<ExchangeRate>, InvoiceError> {
+    let mut rates = Vec::new();
+    let mut date = None;
+    for tag in data.split("<Cube").skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        if let Some(time) = attribute(tag, "time") {
+            date = Some(NaiveDate::parse_from_str(time, "%Y-%m-%d").map_err(|_| {
+                InvoiceError::validation(format!("Invalid date {} in the ECB file", time))
+            })?);
+        }
+        if let (Some(currency), Some(rate)) = (attribute(tag, "currency"), attribute(tag, "rate")) {
+            let date = date.ok_or_else(|| {
+                InvoiceError::validation("ECB file has a rate outside of a dated Cube")
+            })?;
+            let rate = rate.parse().map_err(|_| {
+                InvoiceError::validation(format!(
+                    "Invalid {} rate {} in the ECB file",
+                    currency, rate
+                ))
+            })?;
+            rates.push(ExchangeRate {
+                currency: currency.to_string(),
+                date,
+                rate,
+            });
+        }
+    }
+    if rates.is_empty() {
+        return Err(InvoiceError::validation(
+            "No exchange rates found, is this an ECB reference rate file?",
+        ));
+    }
+    Ok(rates)
+}
+
+//Value of name='..' or name=".." inside a tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=", name))? + name.len() + 2;
+    let quote = tag[start..].chars().next()?;
+    let rest = &tag[start + 1..];
+    rest.find(quote).map(|end| &rest[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn parses_the_ecb_reference_rates() {
+        let rates = parse_ecb_xml(include_str!("fixtures/eurofxref-hist.xml")).unwrap();
+        assert_eq!(rates.len(), 6);
+        assert_eq!(
+            rates[0],
+            ExchangeRate {
+                currency: "USD".to_string(),
+                date: date("2024-05-17"),
+                rate: 1.0866,
+            }
+        );
+        assert_eq!(
+            rates[5],
+            ExchangeRate {
+                currency: "CHF".to_string(),
+                date: date("2024-05-16"),
+                rate: 0.9812,
+            }
+        );
+        //Double quotes work too
+        let rates = parse_ecb_xml(
+            r#"<Cube><Cube time="2024-05-17"><Cube currency="JPY" rate="169.21"/></Cube></Cube>"#,
+        )
+        .unwrap();
+        assert_eq!(rates[0].rate, 169.21);
+    }
+
+    #[test]
+    fn broken_ecb_files_are_refused() {
+        for data in [
+            "<html></html>",
+            "<Cube currency='USD' rate='1.08'/>",
+            "<Cube time='17.05.2024'><Cube currency='USD' rate='1.08'/></Cube>",
+            "<Cube time='2024-05-17'><Cube currency='USD' rate='n/a'/></Cube>",
+        ] {
+            assert!(
+                matches!(parse_ecb_xml(data), Err(InvoiceError::Validation(_))),
+                "{}",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_old_currency_fields() {
+        assert_eq!(normalize("€"), Some(EUR));
+        assert_eq!(normalize("EURO"), Some(EUR));
+        assert_eq!(normalize(" eur "), Some(EUR));
+        assert_eq!(normalize("usd"), Some("USD"));
+        assert_eq!(normalize("£"), Some("GBP"));
+        assert_eq!(normalize("dollars"), None);
+        assert_eq!(symbol("EUR"), "€");
+        assert_eq!(symbol("XYZ"), "XYZ");
+        assert!(is_known("CHF"));
+        assert!(!is_known("chf"));
+    }
+
+    #[test]
+    fn converts_to_eur() {
+        let rate = ExchangeRate {
+            currency: "USD".to_string(),
+            date: date("2024-05-17"),
+            rate: 1.25,
+        };
+        assert_eq!(rate.to_eur(100.0), 80.0);
+        let broken = ExchangeRate { rate: 0.0, ..rate };
+        assert_eq!(broken.to_eur(100.0), 0.0);
+    }
+}
@@ -0,0 +1,263 @@
+//Invoice dates are stored as ISO dates (2022-05-21). Older files and data.json used
+//21.05.2022, the fake invoices used d/m/y, so reading accepts any of those.
+use chrono::{Datelike, Duration, NaiveDate};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Metadata, Schema, SchemaObject},
+    JsonSchema,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt::Display, str::FromStr};
+
+//How dates are printed on invoices and in the GUI
+pub const DISPLAY_FORMAT: &str = "%d.%m.%Y";
+
+const FORMATS: [&str; 5] = ["%Y-%m-%d", "%d.%m.%Y", "%d. %m. %Y", "%d/%m/%Y", "%d-%m-%Y"];
+
+//Day first like everywhere in Slovenia, a trailing dot (21.05.2022.) is ignored
+pub fn parse(value: &str) -> Option<NaiveDate> {
+    let value = value.trim().trim_end_matches('.');
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+}
+
+pub fn format(date: NaiveDate) -> String {
+    date.format(DISPLAY_FORMAT).to_string()
+}
+
+//For #[serde(with = "crate::dates::lenient")]
+pub mod lenient {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, serializer: S) -> Result<S::Ok, S::Error> {
+        date.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse(&value).ok_or_else(|| serde::de::Error::custom(format!("'{}' is not a date", value)))
+    }
+}
+
+//Same as lenient for optional dates, an empty string counts as no date
+pub mod lenient_option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        date: &Option<NaiveDate>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        date.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NaiveDate>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(value) if !value.trim().is_empty() => parse(&value)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("'{}' is not a date", value))),
+            _ => Ok(None),
+        }
+    }
+}
+
+//"net 30" is due 30 days after the invoice date, "net 30 eom" 30 days after the end of that month
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PaymentTerms {
+    pub days: u32,
+    pub end_of_month: bool,
+}
+
+impl PaymentTerms {
+    pub fn net(days: u32) -> Self {
+        Self {
+            days,
+            end_of_month: false,
+        }
+    }
+
+    pub fn due_date(&self, invoice_date: NaiveDate) -> NaiveDate {
+        let start = if self.end_of_month {
+            end_of_month(invoice_date)
+        } else {
+            invoice_date
+        };
+        start + Duration::days(self.days as i64)
+    }
+}
+
+fn end_of_month(date: NaiveDate) -> NaiveDate {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|first| first.pred_opt())
+        .unwrap_or(date)
+}
+
+impl Display for PaymentTerms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.days == 0 && !self.end_of_month {
+            return write!(f, "due on receipt");
+        }
+        write!(f, "net {}", self.days)?;
+        if self.end_of_month {
+            write!(f, " eom")?;
+        }
+        Ok(())
+    }
+}
+
+//Accepts "net 30", "NET30 EOM", "30 days", "30" and "due on receipt"
+impl FromStr for PaymentTerms {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let lower = value.trim().to_lowercase();
+        if lower == "due on receipt" || lower == "immediate" {
+            return Ok(PaymentTerms::default());
+        }
+        let (rest, end_of_month) = match lower.strip_suffix("eom") {
+            Some(rest) => (rest.trim(), true),
+            None => (lower.as_str(), false),
+        };
+        let rest = rest.strip_prefix("net").unwrap_or(rest).trim();
+        let rest = rest.strip_suffix("days").unwrap_or(rest).trim();
+        rest.parse()
+            .map(|days| PaymentTerms { days, end_of_month })
+            .map_err(|_| format!("'{}' are not payment terms like net 30", value))
+    }
+}
+
+impl Serialize for PaymentTerms {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PaymentTerms {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+//Written as a string like "net 30" in the JSON Schema
+impl JsonSchema for PaymentTerms {
+    fn schema_name() -> String {
+        "PaymentTerms".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            metadata: Some(Box::new(Metadata {
+                description: Some("net 30, net 15 eom or due on receipt".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_every_stored_format() {
+        let expected = Some(date(2022, 5, 21));
+        for value in [
+            "2022-05-21",
+            "21.05.2022",
+            " 21.05.2022. ",
+            "21. 05. 2022",
+            "21/05/2022",
+            "21/5/2022",
+            "21-05-2022",
+        ] {
+            assert_eq!(parse(value), expected, "{}", value);
+        }
+        //Day first, 05/21 isn't a month
+        assert_eq!(parse("05/21/2022"), None);
+        assert_eq!(parse("2022-02-30"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(format(date(2022, 5, 1)), "01.05.2022");
+    }
+
+    #[test]
+    fn lenient_dates_read_any_format_and_write_iso() {
+        #[derive(Serialize, Deserialize)]
+        struct Dates {
+            #[serde(with = "lenient")]
+            date: NaiveDate,
+            #[serde(with = "lenient_option")]
+            optional: Option<NaiveDate>,
+        }
+        let dates: Dates =
+            serde_json::from_str(r#"{"date": "21.05.2022", "optional": ""}"#).unwrap();
+        assert_eq!(dates.date, date(2022, 5, 21));
+        assert_eq!(dates.optional, None);
+        assert_eq!(
+            serde_json::to_string(&dates).unwrap(),
+            r#"{"date":"2022-05-21","optional":null}"#
+        );
+        assert!(serde_json::from_str::<Dates>(r#"{"date": "soon", "optional": null}"#).is_err());
+    }
+
+    #[test]
+    fn parses_payment_terms() {
+        let terms = |days, end_of_month| PaymentTerms { days, end_of_month };
+        assert_eq!("net 30".parse(), Ok(terms(30, false)));
+        assert_eq!("NET30 EOM".parse(), Ok(terms(30, true)));
+        assert_eq!("net 30 eom".parse(), Ok(terms(30, true)));
+        assert_eq!("15 days".parse(), Ok(terms(15, false)));
+        assert_eq!("8".parse(), Ok(terms(8, false)));
+        assert_eq!("due on receipt".parse(), Ok(PaymentTerms::default()));
+        assert_eq!("Immediate".parse(), Ok(PaymentTerms::default()));
+        for invalid in ["net", "net -5", "soon", "net 30 days eom later"] {
+            assert!(invalid.parse::<PaymentTerms>().is_err(), "{}", invalid);
+        }
+        //Printed the way they are read
+        for terms in [terms(30, false), terms(15, true), PaymentTerms::default()] {
+            assert_eq!(terms.to_string().parse(), Ok(terms));
+        }
+        assert_eq!(PaymentTerms::default().to_string(), "due on receipt");
+    }
+
+    #[test]
+    fn due_dates_count_from_the_end_of_the_month() {
+        assert_eq!(end_of_month(date(2023, 12, 5)), date(2023, 12, 31));
+        assert_eq!(end_of_month(date(2024, 2, 10)), date(2024, 2, 29));
+        assert_eq!(end_of_month(date(2023, 2, 10)), date(2023, 2, 28));
+        let eom = PaymentTerms {
+            days: 30,
+            end_of_month: true,
+        };
+        assert_eq!(eom.due_date(date(2023, 12, 5)), date(2024, 1, 30));
+        assert_eq!(eom.due_date(date(2024, 2, 1)), date(2024, 3, 30));
+        //Across the new year and the 29th of February
+        assert_eq!(
+            PaymentTerms::net(30).due_date(date(2023, 12, 15)),
+            date(2024, 1, 14)
+        );
+        assert_eq!(
+            PaymentTerms::net(1).due_date(date(2024, 2, 28)),
+            date(2024, 2, 29)
+        );
+        assert_eq!(
+            PaymentTerms::default().due_date(date(2024, 2, 29)),
+            date(2024, 2, 29)
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//Local stand-in for an SMTP server. It accepts every message without TLS, so set
+//"security": "none", no username and the port in email.json. Messages are printed and,
+//with a folder given, saved there as .eml files.
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+#[derive(Default, Debug, Clone)]
+pub struct Received {
+    pub from: String,
+    pub to: Vec<String>,
+    pub data: String,
+}
+
+fn reply(stream: &mut TcpStream, line: &str) -> io::Result<()> {
+    stream.write_all(line.as_bytes())?;
+    stream.write_all(b"\r\n")
+}
+
+//Answers one client until QUIT, every accepted message goes to `received`
+fn handle(mut stream: TcpStream, mut received: impl FnMut(Received)) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    reply(&mut stream, "220 localhost SMTP mock ready")?;
+    let mut message = Received::default();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim_end();
+        let verb = line
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_uppercase();
+        let argument = |prefix: &str| {
+            line.get(prefix.len()..)
+                .unwrap_or_default()
+                .trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        };
+        match verb.as_str() {
+            "EHLO" => reply(
+                &mut stream,
+                "250-localhost\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME",
+            )?,
+            "HELO" | "NOOP" => reply(&mut stream, "250 OK")?,
+            "AUTH" => reply(&mut stream, "235 Authenticated")?,
+            "STARTTLS" => reply(&mut stream, "454 TLS not available")?,
+            "RSET" => {
+                message = Received::default();
+                reply(&mut stream, "250 OK")?;
+            }
+            "MAIL" => {
+                message.from = argument("MAIL FROM:");
+                reply(&mut stream, "250 OK")?;
+            }
+            "RCPT" if message.from.is_empty() => reply(&mut stream, "503 MAIL first")?,
+            "RCPT" => {
+                message.to.push(argument("RCPT TO:"));
+                reply(&mut stream, "250 OK")?;
+            }
+            "DATA" if message.to.is_empty() => reply(&mut stream, "503 RCPT first")?,
+            "DATA" => {
+                reply(&mut stream, "354 End data with <CR><LF>.<CR><LF>")?;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line)? == 0 {
+                        return Ok(());
+                    }
+                    if line.trim_end() == "." {
+                        break;
+                    }
+                    //Dot stuffing is undone
+                    message
+                        .data
+                        .push_str(line.strip_prefix('.').unwrap_or(&line));
+                }
+                received(std::mem::take(&mut message));
+                reply(&mut stream, "250 OK queued")?;
+            }
+            "QUIT" => return reply(&mut stream, "221 Bye"),
+            _ => reply(&mut stream, "502 Command not implemented")?,
+        }
+    }
+}
+
+//Answers every client on its own thread
+pub fn listen(
+    listener: TcpListener,
+    received: impl Fn(Received) + Clone + Send + 'static,
+) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let received = received.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, received) {
+                eprintln!("SMTP mock error: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+pub fn serve(address: &str, folder: Option<PathBuf>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("SMTP mock listening on {}", listener.local_addr()?);
+    if let Some(folder) = &folder {
+        fs::create_dir_all(folder)?;
+    }
+    let count = Arc::new(AtomicUsize::new(0));
+    listen(listener, move |message| {
+        let number = count.fetch_add(1, Ordering::Relaxed) + 1;
+        println!(
+            "Message {} from {} to {}, {} bytes",
+            number,
+            message.from,
+            message.to.join(", "),
+            message.data.len()
+        );
+        if let Some(folder) = &folder {
+            let path = folder.join(format!("{}.eml", number));
+            if let Err(err) = fs::write(&path, &message.data) {
+                eprintln!("Could not save {}: {}", path.display(), err);
+            }
+        }
+    })
+}
@@ -0,0 +1,762 @@
+//Sends the invoice PDF to the partner's email address over SMTP. Settings come from
+//email.json, the subject and body from templates in the partner's language.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fmt::Display,
+    fs::{self, read_to_string},
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    time::Duration,
+};
+
+use crate::currency;
+use crate::dates;
+use crate::error::InvoiceError;
+use crate::invoicer::Racun;
+use crate::storage;
+
+pub mod mock;
+
+const CONFIG_FILE: &str = "email.json";
+//Used when email.json has no password, so it doesn't have to be stored in a file
+pub const PASSWORD_VARIABLE: &str = "SMTP_PASSWORD";
+const DEFAULT_LANGUAGE: &str = "sl";
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Security {
+    //Plain connection upgraded with STARTTLS, usually port 587
+    #[default]
+    StartTls,
+    //TLS from the start, usually port 465
+    Tls,
+    //No encryption, only for local test servers
+    None,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub language: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Template {
+    fn new(language: &str, subject: &str, body: &str) -> Self {
+        Self {
+            language: language.to_string(),
+            subject: subject.to_string(),
+            body: body.to_string(),
+        }
+    }
+}
+
+fn default_templates() -> Vec<Template> {
+    vec![
+        Template::new(
+            "sl",
+            "Račun {number}",
+            "Pozdravljeni,\n\nv prilogi vam pošiljamo račun {number} z dne {invoiceDate} v znesku {amount}.\nRok plačila je {dueDate}, sklic {reference}.\n\nLep pozdrav,\n{company}\n",
+        ),
+        Template::new(
+            "en",
+            "Invoice {number}",
+            "Hello,\n\nplease find attached invoice {number} from {invoiceDate} for {amount}.\nIt is due on {dueDate}, reference {reference}.\n\nKind regards,\n{company}\n",
+        ),
+    ]
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+//Settings for the SMTP server, read from email.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: Security,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    pub from: String, //Address or "Name <address>"
+    #[serde(default)]
+    pub bcc: String, //Copy of every message, for the sent folder
+    #[serde(default)]
+    pub templates: Vec<Template>,
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+impl EmailConfig {
+    //No email.json means invoices can only be exported as .eml
+    pub fn load() -> Result<Option<Self>, InvoiceError> {
+        if !Path::new(CONFIG_FILE).exists() {
+            return Ok(None);
+        }
+        let data = read_to_string(CONFIG_FILE)?;
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+
+    fn password(&self) -> String {
+        if self.password.is_empty() {
+            env::var(PASSWORD_VARIABLE).unwrap_or_default()
+        } else {
+            self.password.clone()
+        }
+    }
+}
+
+//The configured template for the language, then the built in one, then Slovenian
+pub fn template(configured: &[Template], language: &str) -> Template {
+    let find = |templates: &[Template], language: &str| {
+        templates
+            .iter()
+            .find(|template| template.language.eq_ignore_ascii_case(language.trim()))
+            .cloned()
+    };
+    let defaults = default_templates();
+    find(configured, language)
+        .or_else(|| find(&defaults, language))
+        .or_else(|| find(configured, DEFAULT_LANGUAGE))
+        .or_else(|| find(&defaults, DEFAULT_LANGUAGE))
+        .unwrap_or_else(|| defaults[0].clone())
+}
+
+#[derive(Debug)]
+pub enum EmailError {
+    Io(io::Error),
+    Tls(String),
+    //The server answered with an unexpected code
+    Smtp(String),
+    NotConfigured,
+    NoRecipient(i32),
+    //A username with "security": "none" would send the password in the clear
+    InsecureLogin,
+}
+
+impl Display for EmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailError::Io(err) => write!(f, "Could not reach the mail server: {}", err),
+            EmailError::Tls(err) => write!(f, "TLS error: {}", err),
+            EmailError::Smtp(reply) => write!(f, "Mail server refused the message: {}", reply),
+            EmailError::NotConfigured => write!(f, "{} is missing", CONFIG_FILE),
+            EmailError::NoRecipient(number) => {
+                write!(f, "Invoice {} has no partner email address", number)
+            }
+            EmailError::InsecureLogin => write!(
+                f,
+                "Logging in without encryption isn't allowed, use startTls or tls"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmailError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EmailError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EmailError {
+    fn from(err: io::Error) -> Self {
+        EmailError::Io(err)
+    }
+}
+
+//Fills {number}, {partner}, {company}, {amount}, {invoiceDate}, {dueDate} and {reference}
+pub fn render(text: &str, racun: &Racun) -> String {
+    let invoice = &racun.invoice;
+    let totals = invoice.totals();
+    text.replace("{number}", &invoice.invoice_number.to_string())
+        .replace("{partner}", &invoice.partner.partner_name)
+        .replace("{company}", &invoice.company.company_name)
+        .replace(
+            "{amount}",
+            &format!(
+                "{:.2} {}",
+                totals.gross * invoice.sign(),
+                currency::symbol(&invoice.invoice_currency)
+            ),
+        )
+        .replace("{invoiceDate}", &dates::format(invoice.invoice_date))
+        .replace("{dueDate}", &dates::format(invoice.due_date))
+        .replace("{reference}", &invoice.invoice_reference)
+}
+
+pub struct Message {
+    pub from: String,
+    pub to: String,
+    pub bcc: String,
+    pub subject: String,
+    pub body: String,
+    pub attachment_name: String,
+    pub attachment: Vec<u8>,
+}
+
+//Line breaks would end the header or SMTP command and start another one
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+//Address part of "Name <address>"
+fn address(mailbox: &str) -> String {
+    let address = match (mailbox.find('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox,
+    };
+    single_line(address.trim())
+}
+
+//RFC 2047 encoded word for headers that aren't plain ASCII
+fn header_text(text: &str) -> String {
+    let text = single_line(text);
+    if text.is_ascii() {
+        text
+    } else {
+        format!("=?UTF-8?B?{}?=", STANDARD.encode(text))
+    }
+}
+
+//Only the name of "Name <address>" is encoded, the address has to stay readable
+fn mailbox_header(mailbox: &str) -> String {
+    match mailbox.find('<') {
+        Some(start) if start > 0 => format!(
+            "{} <{}>",
+            header_text(mailbox[..start].trim()),
+            address(mailbox)
+        ),
+        _ => single_line(mailbox.trim()),
+    }
+}
+
+//Base64 in lines of 76 characters
+fn base64_lines(data: &[u8]) -> String {
+    let encoded = STANDARD.encode(data);
+    let mut lines = String::with_capacity(encoded.len() + encoded.len() / 76 * 2 + 2);
+    for chunk in encoded.as_bytes().chunks(76) {
+        lines.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        lines.push_str("\r\n");
+    }
+    lines
+}
+
+impl Message {
+    //The invoice email in the partner's language with the PDF attached
+    pub fn for_invoice(
+        racun: &Racun,
+        config: Option<&EmailConfig>,
+        to: Option<&str>,
+    ) -> Result<Self, InvoiceError> {
+        let invoice = &racun.invoice;
+        let to = to
+            .map(str::to_string)
+            .unwrap_or_else(|| invoice.partner.partner_email.trim().to_string());
+        if to.is_empty() {
+            return Err(EmailError::NoRecipient(invoice.invoice_number).into());
+        }
+        let template = template(
+            config.map_or(&[], |config| config.templates.as_slice()),
+            &invoice.partner.partner_language,
+        );
+        let number = invoice.invoice_number;
+        Ok(Self {
+            from: config.map_or_else(
+                || invoice.company.company_name.clone(),
+                |config| config.from.clone(),
+            ),
+            to,
+            bcc: config.map_or_else(String::new, |config| config.bcc.clone()),
+            subject: render(&template.subject, racun),
+            body: render(&template.body, racun),
+            attachment_name: format!("racun {}.pdf", number),
+            attachment: fs::read(storage::pdf_path(number))?,
+        })
+    }
+
+    //Multipart message with the text and the PDF, lines end with CRLF
+    pub fn to_mime(&self) -> String {
+        let now = Local::now();
+        let boundary = format!(
+            "----=_invoice_{}{}",
+            now.timestamp(),
+            now.timestamp_subsec_nanos()
+        );
+        let from = address(&self.from);
+        let domain = from
+            .split_once('@')
+            .map_or("localhost", |(_, domain)| domain);
+        let mut mime = String::new();
+        mime += &format!("From: {}\r\n", mailbox_header(&self.from));
+        mime += &format!("To: {}\r\n", mailbox_header(&self.to));
+        mime += &format!("Subject: {}\r\n", header_text(&self.subject));
+        mime += &format!("Date: {}\r\n", now.to_rfc2822());
+        mime += &format!(
+            "Message-ID: <{}.{}@{}>\r\n",
+            now.timestamp_millis(),
+            std::process::id(),
+            domain
+        );
+        mime += "MIME-Version: 1.0\r\n";
+        mime += &format!(
+            "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n",
+            boundary
+        );
+        mime += &format!("--{}\r\n", boundary);
+        mime +=
+            "Content-Type: text/plain; charset=UTF-8\r\nContent-Transfer-Encoding: base64\r\n\r\n";
+        mime += &base64_lines(self.body.replace('\n', "\r\n").as_bytes());
+        mime += &format!("--{}\r\n", boundary);
+        mime += &format!(
+            "Content-Type: application/pdf; name=\"{0}\"\r\nContent-Transfer-Encoding: base64\r\nContent-Disposition: attachment; filename=\"{0}\"\r\n\r\n",
+            self.attachment_name
+        );
+        mime += &base64_lines(&self.attachment);
+        mime += &format!("--{}--\r\n", boundary);
+        mime
+    }
+}
+
+enum Connection {
+    Plain(TcpStream),
+    Tls(Box<native_tls::TlsStream<TcpStream>>),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+struct Session {
+    connection: Connection,
+}
+
+impl Session {
+    //One reply, continuation lines (250-...) are read until the last one (250 ...)
+    fn reply(&mut self) -> Result<(u16, String), EmailError> {
+        let mut text = String::new();
+        loop {
+            let mut line = Vec::new();
+            let mut byte = [0; 1];
+            while !line.ends_with(b"\r\n") {
+                if self.connection.read(&mut byte)? == 0 {
+                    return Err(EmailError::Smtp("connection closed".to_string()));
+                }
+                line.push(byte[0]);
+            }
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| EmailError::Smtp(line.clone()))?;
+            text += &line;
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok((code, text));
+            }
+            text += "\n";
+        }
+    }
+
+    fn expect(&mut self, codes: &[u16]) -> Result<String, EmailError> {
+        let (code, text) = self.reply()?;
+        if codes.contains(&code) {
+            Ok(text)
+        } else {
+            Err(EmailError::Smtp(text))
+        }
+    }
+
+    fn command(&mut self, command: &str, codes: &[u16]) -> Result<String, EmailError> {
+        self.connection.write_all(command.as_bytes())?;
+        self.connection.write_all(b"\r\n")?;
+        self.expect(codes)
+    }
+
+    fn upgrade(self, host: &str) -> Result<Self, EmailError> {
+        let Connection::Plain(stream) = self.connection else {
+            return Ok(self);
+        };
+        Ok(Session {
+            connection: Connection::Tls(Box::new(tls(host, stream)?)),
+        })
+    }
+}
+
+fn tls(host: &str, stream: TcpStream) -> Result<native_tls::TlsStream<TcpStream>, EmailError> {
+    let connector =
+        native_tls::TlsConnector::new().map_err(|err| EmailError::Tls(err.to_string()))?;
+    connector
+        .connect(host, stream)
+        .map_err(|err| EmailError::Tls(err.to_string()))
+}
+
+//Lines starting with a dot get another one so they don't end the DATA
+fn dot_stuffed(message: &str) -> String {
+    let mut data = String::with_capacity(message.len() + 16);
+    for line in message.split_inclusive("\r\n") {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+    }
+    data
+}
+
+pub fn send(config: &EmailConfig, message: &Message) -> Result<(), EmailError> {
+    if config.security == Security::None && !config.username.is_empty() {
+        return Err(EmailError::InsecureLogin);
+    }
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let server = (config.host.as_str(), config.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| EmailError::Smtp(format!("unknown host {}", config.host)))?;
+    let stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let connection = match config.security {
+        Security::Tls => Connection::Tls(Box::new(tls(&config.host, stream)?)),
+        Security::StartTls | Security::None => Connection::Plain(stream),
+    };
+    let mut session = Session { connection };
+    session.expect(&[220])?;
+    let hello = format!("EHLO {}", hostname());
+    session.command(&hello, &[250])?;
+    if config.security == Security::StartTls {
+        session.command("STARTTLS", &[220])?;
+        session = session.upgrade(&config.host)?;
+        session.command(&hello, &[250])?;
+    }
+    if !config.username.is_empty() {
+        let credentials = format!("\0{}\0{}", config.username, config.password());
+        session.command(
+            &format!("AUTH PLAIN {}", STANDARD.encode(credentials)),
+            &[235],
+        )?;
+    }
+    session.command(&format!("MAIL FROM:<{}>", address(&message.from)), &[250])?;
+    for recipient in [&message.to, &message.bcc] {
+        for recipient in recipient.split(',').filter(|r| !r.trim().is_empty()) {
+            session.command(&format!("RCPT TO:<{}>", address(recipient)), &[250, 251])?;
+        }
+    }
+    session.command("DATA", &[354])?;
+    let mut data = dot_stuffed(&message.to_mime());
+    data += ".";
+    session.command(&data, &[250])?;
+    //The message is accepted, a failing QUIT doesn't change that
+    let _ = session.command("QUIT", &[221]);
+    Ok(())
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+//One line of invoices/<number>/sent.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SentRecord {
+    pub sent_at: String,
+    pub to: String,
+    pub subject: String,
+    //Why sending failed, None when the server accepted the message
+    pub error: Option<String>,
+}
+
+pub fn sent_log(invoice_number: i32) -> Vec<SentRecord> {
+    read_to_string(storage::sent_log_path(invoice_number))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn log_sent(invoice_number: i32, record: SentRecord) -> Result<(), InvoiceError> {
+    let mut log = sent_log(invoice_number);
+    log.push(record);
+    fs::write(
+        storage::sent_log_path(invoice_number),
+        serde_json::to_string_pretty(&log)?,
+    )?;
+    Ok(())
+}
+
+//Sends the stored invoice and records the attempt in its sent log. Returns the recipient.
+pub fn send_invoice(invoice_number: i32, to: Option<&str>) -> Result<String, InvoiceError> {
+    let config = EmailConfig::load()?.ok_or(EmailError::NotConfigured)?;
+    let racun = storage::load(invoice_number)?;
+    let message = Message::for_invoice(&racun, Some(&config), to)?;
+    let result = send(&config, &message);
+    log_sent(
+        invoice_number,
+        SentRecord {
+            sent_at: Local::now().format("%Y-%m-%dT%H:%M:%S").to_string(),
+            to: message.to.clone(),
+            subject: message.subject.clone(),
+            error: result.as_ref().err().map(|err| err.to_string()),
+        },
+    )?;
+    result?;
+    Ok(message.to)
+}
+
+//The message that would be sent as an .eml file, for sending it from a mail program
+pub fn export_eml(invoice_number: i32, to: Option<&str>, path: &Path) -> Result<(), InvoiceError> {
+    let config = EmailConfig::load()?;
+    let racun = storage::load(invoice_number)?;
+    let message = Message::for_invoice(&racun, config.as_ref(), to)?;
+    fs::write(path, message.to_mime())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invoicer::init;
+    use crate::test_support::{sample, Workspace};
+    use mock::Received;
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    //Port of a running mock and the messages it accepted
+    fn mock() -> (u16, Arc<Mutex<Vec<Received>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let messages = received.clone();
+        thread::spawn(move || {
+            mock::listen(listener, move |message| {
+                messages.lock().unwrap().push(message)
+            })
+        });
+        (port, received)
+    }
+
+    fn config(port: u16, security: Security) -> EmailConfig {
+        EmailConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            security,
+            username: String::new(),
+            password: String::new(),
+            from: "Firma d.o.o. <racuni@example.com>".to_string(),
+            bcc: "arhiv@example.com".to_string(),
+            templates: Vec::new(),
+            timeout_secs: 5,
+        }
+    }
+
+    fn write_config(workspace: &Workspace, config: &EmailConfig) {
+        workspace.write(CONFIG_FILE, serde_json::to_string(config).unwrap());
+    }
+
+    //Stored invoice 12 for a partner with an email address
+    fn stored_invoice(language: &str) -> Racun {
+        let mut racun = sample();
+        racun.invoice.partner.partner_email = "kupec@example.com".to_string();
+        racun.invoice.partner.partner_language = language.to_string();
+        init(racun).unwrap();
+        storage::load(12).unwrap()
+    }
+
+    //Decoded parts of a message with the given boundary
+    fn parts(mime: &str) -> Vec<(String, Vec<u8>)> {
+        let boundary = mime
+            .split("boundary=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap();
+        mime.split(&format!("--{}", boundary))
+            .skip(1)
+            .filter_map(|part| part.split_once("\r\n\r\n"))
+            .map(|(headers, body)| {
+                let body: String = body.split_whitespace().collect();
+                (headers.to_string(), STANDARD.decode(body).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn templates_follow_the_partner_language() {
+        let configured = vec![Template::new("de", "Rechnung {number}", "{company}")];
+        assert_eq!(template(&configured, "DE ").subject, "Rechnung {number}");
+        assert_eq!(template(&configured, "en").subject, "Invoice {number}");
+        assert_eq!(template(&configured, "fr").subject, "Račun {number}");
+        assert_eq!(template(&[], "").language, "sl");
+    }
+
+    #[test]
+    fn placeholders_are_filled_from_the_invoice() {
+        let racun = sample();
+        let text = "{number} {partner} {company} {amount} {invoiceDate} {dueDate} {reference}";
+        assert_eq!(
+            render(text, &racun),
+            format!(
+                "12 Partner name TEST COMPANY S.P. 185.71 € {} {} SI",
+                dates::format(racun.invoice.invoice_date),
+                dates::format(racun.invoice.due_date)
+            )
+        );
+    }
+
+    #[test]
+    fn line_breaks_cant_add_headers_or_commands() {
+        assert_eq!(
+            header_text("Invoice\r\nBcc: x@example.com"),
+            "Invoice  Bcc: x@example.com"
+        );
+        assert_eq!(
+            address("<a@example.com\r\nRCPT TO:b@example.com>"),
+            "a@example.com  RCPT TO:b@example.com"
+        );
+        assert_eq!(address("a@example.com\nDATA"), "a@example.com DATA");
+        let message = Message {
+            from: "Firma\r\nX-Injected: 1 <racuni@example.com>".to_string(),
+            to: "kupec@example.com".to_string(),
+            bcc: String::new(),
+            subject: "Račun 12\r\nBcc: x@example.com".to_string(),
+            body: String::new(),
+            attachment_name: "racun 12.pdf".to_string(),
+            attachment: Vec::new(),
+        };
+        let mime = message.to_mime();
+        assert!(!mime.contains("\r\nBcc:"));
+        assert!(!mime.contains("\r\nX-Injected:"));
+        assert!(mime.starts_with("From: Firma  X-Injected: 1 <racuni@example.com>\r\n"));
+    }
+
+    #[test]
+    fn messages_carry_the_text_and_the_pdf() {
+        let workspace = Workspace::with_fonts();
+        let racun = stored_invoice("en");
+        let config = config(2525, Security::None);
+        write_config(&workspace, &config);
+        let message = Message::for_invoice(&racun, Some(&config), None).unwrap();
+        assert_eq!(message.to, "kupec@example.com");
+        assert_eq!(message.subject, "Invoice 12");
+        let mime = message.to_mime();
+        assert!(mime.contains("Subject: Invoice 12\r\n"));
+        assert!(mime.contains("From: Firma d.o.o. <racuni@example.com>\r\n"));
+        let parts = parts(&mime);
+        assert_eq!(parts.len(), 2);
+        let body = String::from_utf8(parts[0].1.clone()).unwrap();
+        assert!(body.starts_with("Hello,\r\n\r\nplease find attached invoice 12"));
+        assert!(body.contains("185.71 €"));
+        assert!(parts[1].0.contains("filename=\"racun 12.pdf\""));
+        assert_eq!(parts[1].1, fs::read(storage::pdf_path(12)).unwrap());
+
+        let eml = workspace.path.join("racun.eml");
+        export_eml(12, Some("drugi@example.com"), &eml).unwrap();
+        let eml = fs::read_to_string(eml).unwrap();
+        assert!(eml.contains("To: drugi@example.com\r\n"));
+    }
+
+    #[test]
+    fn invoices_are_sent_and_logged() {
+        let workspace = Workspace::with_fonts();
+        stored_invoice("sl");
+        let (port, received) = mock();
+        write_config(&workspace, &config(port, Security::None));
+        assert_eq!(send_invoice(12, None).unwrap(), "kupec@example.com");
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].from, "racuni@example.com");
+        assert_eq!(
+            received[0].to,
+            vec!["kupec@example.com", "arhiv@example.com"]
+        );
+        let subject = format!("Subject: {}\r\n", header_text("Račun 12"));
+        assert!(received[0].data.contains(&subject));
+        let parts = parts(&received[0].data);
+        assert_eq!(parts[1].1, fs::read(storage::pdf_path(12)).unwrap());
+
+        let log = sent_log(12);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].to, "kupec@example.com");
+        assert_eq!(log[0].subject, "Račun 12");
+        assert_eq!(log[0].error, None);
+    }
+
+    #[test]
+    fn logins_without_encryption_are_refused() {
+        let workspace = Workspace::with_fonts();
+        stored_invoice("sl");
+        let (port, received) = mock();
+        let mut config = config(port, Security::None);
+        config.username = "racuni@example.com".to_string();
+        config.password = "secret".to_string();
+        write_config(&workspace, &config);
+        let err = send_invoice(12, None).unwrap_err();
+        assert!(matches!(
+            err,
+            InvoiceError::Email(EmailError::InsecureLogin)
+        ));
+        assert!(received.lock().unwrap().is_empty());
+        //Failed attempts are logged too
+        let log = sent_log(12);
+        assert_eq!(log.len(), 1);
+        assert!(log[0].error.as_ref().unwrap().contains("encryption"));
+    }
+
+    #[test]
+    fn starttls_is_required_before_anything_is_sent() {
+        let workspace = Workspace::with_fonts();
+        stored_invoice("sl");
+        //The mock doesn't speak TLS and refuses STARTTLS
+        let (port, received) = mock();
+        write_config(&workspace, &config(port, Security::StartTls));
+        let err = send_invoice(12, None).unwrap_err();
+        assert!(err.to_string().contains("454"), "{}", err);
+        assert!(received.lock().unwrap().is_empty());
+        assert!(sent_log(12)[0].error.is_some());
+    }
+
+    #[test]
+    fn lines_starting_with_a_dot_are_stuffed() {
+        assert_eq!(dot_stuffed("a\r\n.b\r\n..c\r\n"), "a\r\n..b\r\n...c\r\n");
+    }
+}
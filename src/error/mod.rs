@@ -0,0 +1,112 @@
+use std::{error::Error, fmt::Display, io};
+
+use crate::email::EmailError;
+use crate::fiscal::FiscalError;
+
+//Everything that can go wrong while making, storing or rendering an invoice
+#[derive(Debug)]
+pub enum InvoiceError {
+    Io(io::Error),
+    Font(String),
+    Serialization(serde_json::Error),
+    Validation(Vec<String>),
+    Render(String),
+    DuplicateNumber(i32),
+    NotFound(i32),
+    Fiscal(FiscalError),
+    Email(EmailError),
+}
+
+impl InvoiceError {
+    pub fn validation(message: impl ToString) -> Self {
+        InvoiceError::Validation(vec![message.to_string()])
+    }
+
+    pub fn render(message: impl ToString) -> Self {
+        InvoiceError::Render(message.to_string())
+    }
+}
+
+impl Display for InvoiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvoiceError::Io(err) => write!(f, "File error: {}", err),
+            InvoiceError::Font(err) => write!(f, "Font error: {}", err),
+            InvoiceError::Serialization(err) => write!(f, "Invalid JSON: {}", err),
+            InvoiceError::Validation(issues) => write!(f, "{}", issues.join(", ")),
+            InvoiceError::Render(err) => write!(f, "Rendering failed: {}", err),
+            InvoiceError::DuplicateNumber(number) => {
+                write!(f, "Invoice {} already exists", number)
+            }
+            InvoiceError::NotFound(number) => write!(f, "Invoice {} doesn't exist", number),
+            InvoiceError::Fiscal(err) => write!(f, "{}", err),
+            InvoiceError::Email(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for InvoiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InvoiceError::Io(err) => Some(err),
+            InvoiceError::Serialization(err) => Some(err),
+            InvoiceError::Fiscal(err) => Some(err),
+            InvoiceError::Email(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for InvoiceError {
+    fn from(err: io::Error) -> Self {
+        InvoiceError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for InvoiceError {
+    fn from(err: serde_json::Error) -> Self {
+        InvoiceError::Serialization(err)
+    }
+}
+
+impl From<csv::Error> for InvoiceError {
+    fn from(err: csv::Error) -> Self {
+        InvoiceError::Io(err.into())
+    }
+}
+
+impl From<FiscalError> for InvoiceError {
+    fn from(err: FiscalError) -> Self {
+        InvoiceError::Fiscal(err)
+    }
+}
+
+impl From<EmailError> for InvoiceError {
+    fn from(err: EmailError) -> Self {
+        InvoiceError::Email(err)
+    }
+}
+
+impl From<pdfium_render::prelude::PdfiumError> for InvoiceError {
+    fn from(err: pdfium_render::prelude::PdfiumError) -> Self {
+        InvoiceError::Render(format!("{:?}", err))
+    }
+}
+
+impl From<printpdf::Error> for InvoiceError {
+    fn from(err: printpdf::Error) -> Self {
+        InvoiceError::Render(err.to_string())
+    }
+}
+
+impl From<rust_xlsxwriter::XlsxError> for InvoiceError {
+    fn from(err: rust_xlsxwriter::XlsxError) -> Self {
+        InvoiceError::Render(err.to_string())
+    }
+}
+
+impl From<::image::ImageError> for InvoiceError {
+    fn from(err: ::image::ImageError) -> Self {
+        InvoiceError::Render(err.to_string())
+    }
+}
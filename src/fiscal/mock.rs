@@ -0,0 +1,129 @@
+//Local stand-in for the FURS endpoint. It checks the shape of the signed requests and
+//answers like FURS does, but doesn't verify signatures. Plain HTTP, so point the endpoint
+//in furs.json to http://127.0.0.1:<port>/v1/cash_registers
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+fn unsigned_token(payload: &Value) -> String {
+    format!(
+        "{}.{}.",
+        URL_SAFE_NO_PAD.encode(json!({ "alg": "none" }).to_string()),
+        URL_SAFE_NO_PAD.encode(payload.to_string())
+    )
+}
+
+fn request_payload(body: &[u8]) -> Option<Value> {
+    let body: Value = serde_json::from_slice(body).ok()?;
+    let token = body["token"].as_str()?;
+    let mut parts = token.split('.');
+    let header: Value =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts.next()?).ok()?).ok()?;
+    if header["alg"] != "RS256" {
+        return None;
+    }
+    let payload = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+    parts.next().filter(|signature| !signature.is_empty())?;
+    serde_json::from_slice(&payload).ok()
+}
+
+fn error(response: &str, code: &str, message: &str) -> Value {
+    json!({ response: { "Error": { "ErrorCode": code, "ErrorMessage": message } } })
+}
+
+//Builds the answer FURS would give for the request payload
+fn answer(path: &str, payload: Option<Value>) -> Value {
+    let payload = match payload {
+        Some(payload) => payload,
+        None => return error("InvoiceResponse", "S001", "Invalid token"),
+    };
+    if path.ends_with("/invoices/register") {
+        let premise = &payload["BusinessPremiseRequest"]["BusinessPremise"];
+        if premise["TaxNumber"].as_u64().is_none() || premise["BusinessPremiseID"].is_null() {
+            return error("BusinessPremiseResponse", "S002", "Missing premise data");
+        }
+        return json!({ "BusinessPremiseResponse": { "Header": payload["BusinessPremiseRequest"]["Header"] } });
+    }
+    let invoice = &payload["InvoiceRequest"]["Invoice"];
+    let zoi = invoice["ProtectedID"].as_str().unwrap_or_default();
+    if zoi.len() != 32 || invoice["InvoiceAmount"].as_f64().is_none() {
+        return error("InvoiceResponse", "S002", "Invalid invoice data");
+    }
+    json!({
+        "InvoiceResponse": {
+            "Header": payload["InvoiceRequest"]["Header"],
+            "UniqueInvoiceID": format!(
+                "{}-{}-{}-{}-{}",
+                &zoi[0..8],
+                &zoi[8..12],
+                &zoi[12..16],
+                &zoi[16..20],
+                &zoi[20..32]
+            ),
+        }
+    })
+}
+
+fn handle(mut stream: TcpStream, received: impl Fn(&Value)) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let payload = request_payload(&body);
+    if let Some(payload) = &payload {
+        received(payload);
+    }
+    let response = json!({ "token": unsigned_token(&answer(&path, payload)) }).to_string();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=UTF-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    )
+}
+
+pub fn serve(address: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("FURS mock listening on http://{}", listener.local_addr()?);
+    listen(listener, |_| {})
+}
+
+//Answers every connection on its own thread, `received` sees the payload of each request
+pub fn listen(
+    listener: TcpListener,
+    received: impl Fn(&Value) + Clone + Send + 'static,
+) -> std::io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let received = received.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, received) {
+                eprintln!("FURS mock error: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,756 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Local, NaiveDateTime};
+use openssl::{
+    hash::{hash, MessageDigest},
+    pkcs12::Pkcs12,
+    pkey::{PKey, Private},
+    sign::Signer,
+    x509::{X509NameRef, X509},
+};
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, read_to_string},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::error::InvoiceError;
+use crate::invoicer::{Invoice, PaymentMethod, Racun, VatTreatment};
+
+pub mod mock;
+
+const CONFIG_FILE: &str = "furs.json";
+const QUEUE_FILE: &str = "furs_queue.json";
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PremiseAddress {
+    pub cadastral_number: u32,
+    pub building_number: u32,
+    pub building_section_number: u32,
+    pub street: String,
+    pub house_number: String,
+    pub community: String,
+    pub city: String,
+    pub postal_code: String,
+}
+
+//Settings for talking to FURS, read from furs.json
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FiscalConfig {
+    pub endpoint: String, //https://blagajne-test.fu.gov.si:9002/v1/cash_registers
+    pub certificate_path: String, //PKCS#12 certificate issued by FURS
+    pub certificate_password: String,
+    #[serde(default)]
+    pub ca_certificate_path: String,
+    pub tax_number: u32,
+    pub business_premise_id: String,
+    pub electronic_device_id: String,
+    #[serde(default)]
+    pub operator_tax_number: Option<u32>,
+    #[serde(default)]
+    pub software_supplier_tax_number: u32,
+    #[serde(default)]
+    pub premise: PremiseAddress,
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout() -> u64 {
+    3
+}
+
+impl FiscalConfig {
+    //No furs.json means the invoices are not fiscalized
+    pub fn load() -> Result<Option<Self>, InvoiceError> {
+        if !Path::new(CONFIG_FILE).exists() {
+            return Ok(None);
+        }
+        let data = read_to_string(CONFIG_FILE)?;
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+}
+
+//Fiscal data printed on the invoice, eor stays empty until FURS confirms the invoice
+#[derive(Default, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FiscalData {
+    pub tax_number: u32,
+    pub issue_date_time: String,
+    pub business_premise_id: String,
+    pub electronic_device_id: String,
+    pub zoi: String,
+    #[serde(default)]
+    pub eor: Option<String>,
+}
+
+impl FiscalData {
+    //60 digits: ZOI as a 39 digit number, tax number, yyMMddHHmmss and a control digit
+    pub fn qr_payload(&self) -> String {
+        let zoi = u128::from_str_radix(&self.zoi, 16).unwrap_or_default();
+        let date = NaiveDateTime::parse_from_str(&self.issue_date_time, "%Y-%m-%dT%H:%M:%S")
+            .map(|date| date.format("%y%m%d%H%M%S").to_string())
+            .unwrap_or_default();
+        let digits = format!("{:039}{:08}{}", zoi, self.tax_number, date);
+        let control: u32 = digits.chars().filter_map(|c| c.to_digit(10)).sum();
+        format!("{}{}", digits, control % 10)
+    }
+}
+
+#[derive(Debug)]
+pub enum FiscalError {
+    Offline(String),  //FURS could not be reached, the invoice goes into the queue
+    Rejected(String), //FURS answered with an error
+    Certificate(String),
+}
+
+impl Display for FiscalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FiscalError::Offline(err) => write!(f, "FURS is not reachable: {}", err),
+            FiscalError::Rejected(err) => write!(f, "FURS rejected the request: {}", err),
+            FiscalError::Certificate(err) => write!(f, "Certificate error: {}", err),
+        }
+    }
+}
+
+impl Error for FiscalError {}
+
+impl From<openssl::error::ErrorStack> for FiscalError {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        FiscalError::Certificate(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for FiscalError {
+    fn from(err: std::io::Error) -> Self {
+        FiscalError::Certificate(err.to_string())
+    }
+}
+
+struct Certificate {
+    key: PKey<Private>,
+    cert: X509,
+}
+
+impl Certificate {
+    fn load(config: &FiscalConfig) -> Result<Self, FiscalError> {
+        let der = fs::read(&config.certificate_path)?;
+        let parsed = Pkcs12::from_der(&der)?.parse2(&config.certificate_password)?;
+        match (parsed.pkey, parsed.cert) {
+            (Some(key), Some(cert)) => Ok(Self { key, cert }),
+            _ => Err(FiscalError::Certificate(
+                "The certificate file has no key or certificate".to_string(),
+            )),
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, FiscalError> {
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+        signer.update(data)?;
+        Ok(signer.sign_to_vec()?)
+    }
+
+    //FURS wants names like CN=...,O=...,C=SI
+    fn name(name: &X509NameRef) -> String {
+        name.entries()
+            .filter_map(|entry| {
+                let key = entry.object().nid().short_name().ok()?;
+                let value = entry.data().to_string().ok()?;
+                Some(format!("{}={}", key, value))
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    //Compact JWS signed with RS256
+    fn jws(&self, payload: &Value) -> Result<String, FiscalError> {
+        let serial = self.cert.serial_number().to_bn()?.to_dec_str()?;
+        let header = json!({
+            "alg": "RS256",
+            "subject_name": Self::name(self.cert.subject_name()),
+            "issuer_name": Self::name(self.cert.issuer_name()),
+            "serial": serial.parse::<u128>().unwrap_or_default(),
+        });
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(payload.to_string())
+        );
+        let signature = self.sign(signing_input.as_bytes())?;
+        Ok(format!(
+            "{}.{}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+}
+
+//Protective mark of the issuer: MD5 of the signed invoice identifiers
+pub fn compute_zoi(
+    config: &FiscalConfig,
+    issue_date_time: &NaiveDateTime,
+    invoice_number: i32,
+    amount: f64,
+) -> Result<String, FiscalError> {
+    let certificate = Certificate::load(config)?;
+    let data = format!(
+        "{}{}{}{}{}{:.2}",
+        config.tax_number,
+        issue_date_time.format("%d.%m.%Y %H:%M:%S"),
+        invoice_number,
+        config.business_premise_id,
+        config.electronic_device_id,
+        amount
+    );
+    let signature = certificate.sign(data.as_bytes())?;
+    let digest = hash(MessageDigest::md5(), &signature)?;
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn message_header() -> Value {
+    let mut rng = rand::thread_rng();
+    let bytes: [u8; 16] = rng.gen();
+    let id = format!(
+        "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_be_bytes([bytes[4], bytes[5]]),
+        u16::from_be_bytes([bytes[6], bytes[7]]) & 0x0fff,
+        (u16::from_be_bytes([bytes[8], bytes[9]]) & 0x3fff) | 0x8000,
+        u64::from_be_bytes([
+            0, 0, bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15],
+        ])
+    );
+    json!({
+        "MessageID": id,
+        "DateTime": Local::now().format("%Y-%m-%dT%H:%M:%S").to_string(),
+    })
+}
+
+//Amounts per VAT treatment in the format FURS expects, negative for credit notes
+fn taxes_per_seller(invoice: &Invoice) -> Value {
+    let totals = invoice.totals().signed(invoice.sign());
+    match invoice.vat_treatment {
+        //Taxed in another member state, so the whole amount is outside of Slovenian VAT
+        VatTreatment::Oss { .. } => json!([{ "NonTaxableAmount": totals.gross }]),
+        VatTreatment::Domestic => {
+            let vat: Vec<Value> = totals
+                .vat_lines
+                .iter()
+                .map(|line| {
+                    json!({
+                        "TaxRate": line.rate,
+                        "TaxableAmount": line.base,
+                        "TaxAmount": line.vat,
+                    })
+                })
+                .collect();
+            json!([{ "VAT": vat }])
+        }
+        VatTreatment::ReverseCharge => json!([{ "ReverseVATTaxableAmount": totals.net }]),
+        VatTreatment::Export | VatTreatment::Exempt { .. } => {
+            json!([{ "ExemptVATTaxableAmount": totals.net }])
+        }
+        VatTreatment::NonVatPayer => json!([{ "NonTaxableAmount": totals.net }]),
+    }
+}
+
+fn invoice_request(
+    invoice: &Invoice,
+    fiscal: &FiscalData,
+    config: &FiscalConfig,
+    subsequent: bool,
+) -> Value {
+    let totals = invoice.totals().signed(invoice.sign());
+    let mut body = json!({
+        "TaxNumber": fiscal.tax_number,
+        "IssueDateTime": fiscal.issue_date_time,
+        "NumberingStructure": "B",
+        "InvoiceIdentifier": {
+            "BusinessPremiseID": fiscal.business_premise_id,
+            "ElectronicDeviceID": fiscal.electronic_device_id,
+            "InvoiceNumber": invoice.invoice_number.to_string(),
+        },
+        "InvoiceAmount": totals.gross,
+        "PaymentAmount": totals.gross,
+        "TaxesPerSeller": taxes_per_seller(invoice),
+        "ProtectedID": fiscal.zoi,
+        "SubsequentSubmit": subsequent,
+    });
+    if let Some(operator) = config.operator_tax_number {
+        body["OperatorTaxNumber"] = json!(operator);
+    }
+    json!({
+        "InvoiceRequest": {
+            "Header": message_header(),
+            "Invoice": body,
+        }
+    })
+}
+
+fn agent(config: &FiscalConfig) -> Result<ureq::Agent, FiscalError> {
+    let der = fs::read(&config.certificate_path)?;
+    let identity = native_tls::Identity::from_pkcs12(&der, &config.certificate_password)
+        .map_err(|err| FiscalError::Certificate(err.to_string()))?;
+    let mut builder = native_tls::TlsConnector::builder();
+    builder.identity(identity);
+    if !config.ca_certificate_path.is_empty() {
+        let pem = fs::read(&config.ca_certificate_path)?;
+        let ca = native_tls::Certificate::from_pem(&pem)
+            .map_err(|err| FiscalError::Certificate(err.to_string()))?;
+        builder.add_root_certificate(ca);
+    }
+    let connector = builder
+        .build()
+        .map_err(|err| FiscalError::Certificate(err.to_string()))?;
+    Ok(ureq::AgentBuilder::new()
+        .tls_connector(Arc::new(connector))
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .build())
+}
+
+//Posts the signed payload and returns the payload of the signed answer
+fn send(config: &FiscalConfig, path: &str, payload: &Value) -> Result<Value, FiscalError> {
+    let certificate = Certificate::load(config)?;
+    let token = certificate.jws(payload)?;
+    let url = format!("{}/{}", config.endpoint.trim_end_matches('/'), path);
+    let response = agent(config)?
+        .post(&url)
+        .set("Content-Type", "application/json; charset=UTF-8")
+        .send_json(json!({ "token": token }));
+    let response: Value = match response {
+        Ok(response) => response
+            .into_json()
+            .map_err(|err| FiscalError::Offline(err.to_string()))?,
+        Err(ureq::Error::Status(code, response)) => {
+            let text = response.into_string().unwrap_or_default();
+            return Err(FiscalError::Rejected(format!("HTTP {}: {}", code, text)));
+        }
+        Err(err) => return Err(FiscalError::Offline(err.to_string())),
+    };
+    //The answer is signed by FURS too, the TLS connection already tells us who sent it
+    let token = response["token"].as_str().unwrap_or_default();
+    let payload = token
+        .split('.')
+        .nth(1)
+        .and_then(|part| URL_SAFE_NO_PAD.decode(part).ok())
+        .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+        .ok_or_else(|| FiscalError::Rejected("FURS sent an invalid token".to_string()))?;
+    Ok(payload)
+}
+
+fn response_error(payload: &Value) -> Option<String> {
+    payload
+        .as_object()?
+        .values()
+        .find_map(|response| response.get("Error"))
+        .map(|error| {
+            format!(
+                "{} {}",
+                error["ErrorCode"].as_str().unwrap_or_default(),
+                error["ErrorMessage"].as_str().unwrap_or_default()
+            )
+        })
+}
+
+//Sends the invoice to FURS and returns the EOR
+pub fn submit(
+    config: &FiscalConfig,
+    invoice: &Invoice,
+    fiscal: &FiscalData,
+    subsequent: bool,
+) -> Result<String, FiscalError> {
+    let payload = invoice_request(invoice, fiscal, config, subsequent);
+    let response = send(config, "invoices", &payload)?;
+    if let Some(error) = response_error(&response) {
+        return Err(FiscalError::Rejected(error));
+    }
+    response["InvoiceResponse"]["UniqueInvoiceID"]
+        .as_str()
+        .map(|eor| eor.to_string())
+        .ok_or_else(|| FiscalError::Rejected("FURS did not send an EOR".to_string()))
+}
+
+//Business premises have to be registered before any invoice is sent from them
+pub fn register_premise(config: &FiscalConfig) -> Result<(), FiscalError> {
+    let premise = &config.premise;
+    let payload = json!({
+        "BusinessPremiseRequest": {
+            "Header": message_header(),
+            "BusinessPremise": {
+                "TaxNumber": config.tax_number,
+                "BusinessPremiseID": config.business_premise_id,
+                "BPIdentifier": {
+                    "RealEstateBP": {
+                        "PropertyID": {
+                            "CadastralNumber": premise.cadastral_number,
+                            "BuildingNumber": premise.building_number,
+                            "BuildingSectionNumber": premise.building_section_number,
+                        },
+                        "Address": {
+                            "Street": premise.street,
+                            "HouseNumber": premise.house_number,
+                            "Community": premise.community,
+                            "City": premise.city,
+                            "PostalCode": premise.postal_code,
+                        }
+                    }
+                },
+                "ValidityDate": Local::now().format("%Y-%m-%d").to_string(),
+                "SoftwareSupplier": [{ "TaxNumber": config.software_supplier_tax_number }],
+            }
+        }
+    });
+    let response = send(config, "invoices/register", &payload)?;
+    match response_error(&response) {
+        Some(error) => Err(FiscalError::Rejected(error)),
+        None => Ok(()),
+    }
+}
+
+//Batch generation fiscalizes from several threads, the queue file is read and written whole
+static QUEUE_LOCK: Mutex<()> = Mutex::new(());
+
+//Invoices that were issued without an EOR and still have to be sent
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FiscalQueue {
+    pub invoices: Vec<Racun>,
+}
+
+impl FiscalQueue {
+    pub fn load() -> Result<Self, InvoiceError> {
+        if !Path::new(QUEUE_FILE).exists() {
+            return Ok(Self::default());
+        }
+        let data = read_to_string(QUEUE_FILE)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub fn save(&self) -> Result<(), InvoiceError> {
+        fs::write(QUEUE_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    //True for an invoice that got its ZOI but not the EOR yet
+    pub fn pending(racun: &Racun) -> bool {
+        racun
+            .invoice
+            .fiscal
+            .as_ref()
+            .is_some_and(|fiscal| fiscal.eor.is_none())
+    }
+
+    pub fn push(racun: &Racun) -> Result<(), InvoiceError> {
+        let _guard = QUEUE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let mut queue = Self::load()?;
+        queue.invoices.push(racun.clone());
+        queue.save()
+    }
+}
+
+//Computes the ZOI and asks FURS for the EOR, invoices paid by bank transfer are left alone.
+//When FURS can't be reached the invoice still gets its ZOI and the note that says so is
+//returned, the caller queues it with FiscalQueue::push once it is stored.
+pub fn fiscalize(racun: &mut Racun) -> Result<Option<String>, InvoiceError> {
+    if racun.invoice.payment_method == PaymentMethod::BankTransfer || racun.invoice.fiscal.is_some()
+    {
+        return Ok(None);
+    }
+    let config = match FiscalConfig::load()? {
+        Some(config) => config,
+        None => {
+            return Err(InvoiceError::validation(
+                "Cash and card invoices need furs.json to be fiscally verified",
+            ))
+        }
+    };
+    let issued = Local::now().naive_local();
+    //A credit note is signed with the amount it returns
+    let amount = racun.invoice.totals().signed(racun.invoice.sign()).gross;
+    let mut fiscal = FiscalData {
+        tax_number: config.tax_number,
+        issue_date_time: issued.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        business_premise_id: config.business_premise_id.clone(),
+        electronic_device_id: config.electronic_device_id.clone(),
+        zoi: compute_zoi(&config, &issued, racun.invoice.invoice_number, amount)?,
+        eor: None,
+    };
+    match submit(&config, &racun.invoice, &fiscal, false) {
+        Ok(eor) => {
+            fiscal.eor = Some(eor);
+            racun.invoice.fiscal = Some(fiscal);
+            Ok(None)
+        }
+        Err(FiscalError::Offline(err)) => {
+            racun.invoice.fiscal = Some(fiscal);
+            Ok(Some(format!(
+                "FURS is not reachable, invoice {} was queued: {}",
+                racun.invoice.invoice_number, err
+            )))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Resubmission {
+    pub sent: usize,
+    //Invoice number and why it is still queued
+    pub queued: Vec<(i32, String)>,
+}
+
+//Sends every queued invoice again with the subsequent delivery flag and stores the EOR.
+//An invoice FURS confirmed but that couldn't be stored stays queued with its EOR, the next
+//run only stores it.
+pub fn resubmit_queue() -> Result<Resubmission, InvoiceError> {
+    let config = match FiscalConfig::load()? {
+        Some(config) => config,
+        None => return Ok(Resubmission::default()),
+    };
+    let _guard = QUEUE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut queue = FiscalQueue::load()?;
+    let mut resubmission = Resubmission::default();
+    let mut remaining = Vec::new();
+    for mut racun in queue.invoices.drain(..) {
+        let number = racun.invoice.invoice_number;
+        let Some(fiscal) = racun.invoice.fiscal.clone() else {
+            continue;
+        };
+        if fiscal.eor.is_none() {
+            match submit(&config, &racun.invoice, &fiscal, true) {
+                Ok(eor) => {
+                    racun.invoice.fiscal = Some(FiscalData {
+                        eor: Some(eor),
+                        ..fiscal
+                    })
+                }
+                Err(err) => {
+                    resubmission.queued.push((number, err.to_string()));
+                    remaining.push(racun);
+                    continue;
+                }
+            }
+        }
+        match crate::storage::save(&racun) {
+            Ok(()) => resubmission.sent += 1,
+            Err(err) => {
+                resubmission.queued.push((number, err.to_string()));
+                remaining.push(racun);
+            }
+        }
+    }
+    queue.invoices = remaining;
+    queue.save()?;
+    Ok(resubmission)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage;
+    use crate::test_support::{
+        furs::{certificate, config, mock, offline},
+        sample, Workspace,
+    };
+
+    fn cash_invoice() -> Racun {
+        let mut racun = sample();
+        racun.invoice.payment_method = PaymentMethod::Cash;
+        racun
+    }
+
+    fn configure(workspace: &Workspace, address: &str) {
+        workspace.write(
+            CONFIG_FILE,
+            serde_json::to_string(&config(address)).unwrap(),
+        );
+    }
+
+    fn eor_for(zoi: &str) -> String {
+        format!(
+            "{}-{}-{}-{}-{}",
+            &zoi[0..8],
+            &zoi[8..12],
+            &zoi[12..16],
+            &zoi[16..20],
+            &zoi[20..32]
+        )
+    }
+
+    #[test]
+    fn qr_payload_is_60_digits_ending_with_the_control_digit() {
+        let fiscal = FiscalData {
+            tax_number: 12345678,
+            issue_date_time: "2024-05-21T13:45:09".to_string(),
+            zoi: "a7e5f55e1dbb48b799268e1a6d8618a3".to_string(),
+            ..Default::default()
+        };
+        let payload = fiscal.qr_payload();
+        assert_eq!(payload.len(), 60);
+        assert!(payload.chars().all(|c| c.is_ascii_digit()));
+        let zoi = u128::from_str_radix(&fiscal.zoi, 16).unwrap();
+        assert_eq!(&payload[..39], format!("{:039}", zoi));
+        assert_eq!(&payload[39..47], "12345678");
+        assert_eq!(&payload[47..59], "240521134509");
+        let sum: u32 = payload[..59].chars().filter_map(|c| c.to_digit(10)).sum();
+        assert_eq!(payload[59..].parse::<u32>().unwrap(), sum % 10);
+    }
+
+    #[test]
+    fn zoi_is_an_md5_in_hex() {
+        let workspace = Workspace::new();
+        workspace.write("furs.p12", certificate());
+        let issued =
+            NaiveDateTime::parse_from_str("2024-05-21T13:45:09", "%Y-%m-%dT%H:%M:%S").unwrap();
+        let zoi = compute_zoi(&config(&offline()), &issued, 12, 185.71).unwrap();
+        assert_eq!(zoi.len(), 32);
+        assert!(zoi.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn fiscalize_gets_the_eor_from_furs() {
+        let workspace = Workspace::new();
+        workspace.write("furs.p12", certificate());
+        let (address, received) = mock();
+        configure(&workspace, &address);
+        let mut racun = cash_invoice();
+        assert_eq!(fiscalize(&mut racun).unwrap(), None);
+        let fiscal = racun.invoice.fiscal.unwrap();
+        assert_eq!(fiscal.eor, Some(eor_for(&fiscal.zoi)));
+        assert_eq!(fiscal.tax_number, 12345678);
+        let received = received.lock().unwrap();
+        let invoice = &received[0]["InvoiceRequest"]["Invoice"];
+        assert_eq!(invoice["SubsequentSubmit"], false);
+        assert_eq!(invoice["ProtectedID"], fiscal.zoi.as_str());
+        assert_eq!(invoice["InvoiceIdentifier"]["InvoiceNumber"], "12");
+        assert!(!Path::new(QUEUE_FILE).exists());
+    }
+
+    #[test]
+    fn credit_notes_are_fiscalized_with_negative_amounts() {
+        let workspace = Workspace::new();
+        workspace.write("furs.p12", certificate());
+        let (address, received) = mock();
+        configure(&workspace, &address);
+        let mut racun = cash_invoice();
+        racun.invoice.credit_note_for = Some(11);
+        let totals = racun.invoice.totals();
+        assert!(totals.gross > 0.0);
+        fiscalize(&mut racun).unwrap();
+        //RSA signatures are deterministic, so the ZOI can be computed again
+        let fiscal = racun.invoice.fiscal.unwrap();
+        let issued =
+            NaiveDateTime::parse_from_str(&fiscal.issue_date_time, "%Y-%m-%dT%H:%M:%S").unwrap();
+        let zoi = compute_zoi(&config(&address), &issued, 12, -totals.gross).unwrap();
+        assert_eq!(fiscal.zoi, zoi);
+        let received = received.lock().unwrap();
+        let invoice = &received[0]["InvoiceRequest"]["Invoice"];
+        assert_eq!(invoice["InvoiceAmount"], -totals.gross);
+        assert_eq!(invoice["PaymentAmount"], -totals.gross);
+        let vat = invoice["TaxesPerSeller"][0]["VAT"].as_array().unwrap();
+        assert_eq!(vat.len(), totals.vat_lines.len());
+        for (sent, line) in vat.iter().zip(&totals.vat_lines) {
+            assert_eq!(sent["TaxRate"], line.rate);
+            assert_eq!(sent["TaxableAmount"], -line.base);
+            assert_eq!(sent["TaxAmount"], -line.vat);
+        }
+    }
+
+    #[test]
+    fn submit_returns_the_eor() {
+        let workspace = Workspace::new();
+        workspace.write("furs.p12", certificate());
+        let (address, _) = mock();
+        let fiscal = FiscalData {
+            tax_number: 12345678,
+            issue_date_time: "2024-05-21T13:45:09".to_string(),
+            business_premise_id: "PP1".to_string(),
+            electronic_device_id: "B1".to_string(),
+            zoi: "a7e5f55e1dbb48b799268e1a6d8618a3".to_string(),
+            eor: None,
+        };
+        let eor = submit(&config(&address), &cash_invoice().invoice, &fiscal, false).unwrap();
+        assert_eq!(eor, eor_for(&fiscal.zoi));
+    }
+
+    #[test]
+    fn bank_transfers_are_not_fiscalized() {
+        let _workspace = Workspace::new();
+        let mut racun = sample();
+        assert_eq!(fiscalize(&mut racun).unwrap(), None);
+        assert!(racun.invoice.fiscal.is_none());
+    }
+
+    #[test]
+    fn offline_invoices_are_queued_and_resubmitted() {
+        let workspace = Workspace::new();
+        workspace.write("furs.p12", certificate());
+        configure(&workspace, &offline());
+        let mut racun = cash_invoice();
+        let note = fiscalize(&mut racun).unwrap().unwrap();
+        assert!(note.contains("queued"), "{}", note);
+        let fiscal = racun.invoice.fiscal.clone().unwrap();
+        assert_eq!(fiscal.zoi.len(), 32);
+        assert_eq!(fiscal.eor, None);
+        assert!(FiscalQueue::pending(&racun));
+        //Queuing is left to the caller, once the invoice is stored
+        assert!(FiscalQueue::load().unwrap().invoices.is_empty());
+        FiscalQueue::push(&racun).unwrap();
+        assert_eq!(FiscalQueue::load().unwrap().invoices.len(), 1);
+
+        //Still offline, the invoice stays queued
+        let resubmission = resubmit_queue().unwrap();
+        assert_eq!(resubmission.sent, 0);
+        assert_eq!(resubmission.queued.len(), 1);
+        assert_eq!(FiscalQueue::load().unwrap().invoices.len(), 1);
+
+        let (address, received) = mock();
+        configure(&workspace, &address);
+        let resubmission = resubmit_queue().unwrap();
+        assert_eq!(resubmission.sent, 1);
+        assert!(resubmission.queued.is_empty());
+        assert!(FiscalQueue::load().unwrap().invoices.is_empty());
+        let invoice = &received.lock().unwrap()[0]["InvoiceRequest"]["Invoice"];
+        assert_eq!(invoice["SubsequentSubmit"], true);
+        assert_eq!(invoice["ProtectedID"], fiscal.zoi.as_str());
+        let stored = storage::load(racun.invoice.invoice_number).unwrap();
+        assert_eq!(
+            stored.invoice.fiscal.unwrap().eor,
+            Some(eor_for(&fiscal.zoi))
+        );
+    }
+
+    #[test]
+    fn confirmed_invoices_that_could_not_be_stored_stay_queued_with_their_eor() {
+        let workspace = Workspace::new();
+        workspace.write("furs.p12", certificate());
+        configure(&workspace, &offline());
+        let mut racun = cash_invoice();
+        fiscalize(&mut racun).unwrap();
+        FiscalQueue::push(&racun).unwrap();
+        //A file where the invoices folder should be makes storing fail
+        workspace.write("invoices", "");
+        let (address, received) = mock();
+        configure(&workspace, &address);
+        let resubmission = resubmit_queue().unwrap();
+        assert_eq!(resubmission.sent, 0);
+        assert_eq!(resubmission.queued.len(), 1);
+        let queued = FiscalQueue::load().unwrap().invoices;
+        assert!(queued[0].invoice.fiscal.as_ref().unwrap().eor.is_some());
+
+        fs::remove_file("invoices").unwrap();
+        let resubmission = resubmit_queue().unwrap();
+        assert_eq!(resubmission.sent, 1);
+        assert!(FiscalQueue::load().unwrap().invoices.is_empty());
+        //FURS only saw the invoice once
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
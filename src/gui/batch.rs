@@ -0,0 +1,116 @@
+use eframe::egui;
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread,
+};
+
+use super::GuiApp;
+use crate::batch::{self, BatchReport, Importer, Progress};
+
+impl GuiApp {
+    //Runs the batch on its own thread, the window polls the progress and picks up the report
+    fn start_batch(&mut self) {
+        let path = PathBuf::from(self.batch_path.trim());
+        let template = self.draft.clone();
+        let partners = self.partner_book.clone();
+        let catalog = self.catalog.clone();
+        let progress = Arc::new(Progress::default());
+        let result = Arc::new(Mutex::new(None));
+        self.batch_progress = Some(progress.clone());
+        self.batch_result = result.clone();
+        self.batch_report = None;
+        let notifications = self.notifications.clone();
+        thread::spawn(move || {
+            let mut importer = Importer::new(&template, &partners, &catalog);
+            let report = batch::run(&mut importer, &path, batch::default_threads(), &progress);
+            let report_path = batch::report_path(&path);
+            if report.errors.is_empty() {
+                batch::clear_report(&report_path);
+            } else if let Err(err) = report.save_errors(&report_path) {
+                notifications.error(format!("Error saving the batch report: {}", err));
+            }
+            *result.lock().unwrap_or_else(|err| err.into_inner()) = Some(report);
+        });
+    }
+
+    fn finish_batch(&mut self, report: BatchReport) {
+        let profile_name = self.draft.invoice.company_profile.clone();
+        for number in &report.generated {
+            self.company_profiles.use_number(&profile_name, *number);
+        }
+        if let Err(err) = self.company_profiles.save() {
+            self.notifications
+                .error(format!("Error saving company profiles: {}", err));
+        }
+        if let Some(profile) = self.company_profiles.active() {
+            self.draft.invoice.invoice_number = profile.next_invoice_number;
+        }
+        for warning in &report.warnings {
+            self.notifications.error(warning);
+        }
+        self.batch_progress = None;
+        self.batch_report = Some(report);
+        self.worker.changed();
+    }
+
+    pub(super) fn show_batch_window(&mut self, ctx: &egui::Context) {
+        let finished = self
+            .batch_result
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .take();
+        if let Some(report) = finished {
+            self.finish_batch(report);
+        }
+        let mut open = self.show_batch;
+        egui::Window::new("Batch generation")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.label("CSV with one service per row (grouped by the invoice column) or JSON lines with one invoice per line");
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut self.batch_path);
+                    let running = self.batch_progress.is_some();
+                    if ui
+                        .add_enabled(!running && !self.batch_path.trim().is_empty(), egui::Button::new("Generate"))
+                        .clicked()
+                    {
+                        self.start_batch();
+                    }
+                });
+                if let Some(progress) = &self.batch_progress {
+                    ui.add(
+                        egui::ProgressBar::new(progress.fraction())
+                            .show_percentage()
+                            .text(format!(
+                                "{}/{}",
+                                progress.done.load(Ordering::Relaxed),
+                                progress.total.load(Ordering::Relaxed)
+                            )),
+                    );
+                    ctx.request_repaint();
+                }
+                if let Some(report) = &self.batch_report {
+                    ui.label(format!("{} invoices generated", report.generated.len()));
+                    if !report.errors.is_empty() {
+                        ui.colored_label(
+                            egui::Color32::RED,
+                            format!(
+                                "{} problems, the report is in {}",
+                                report.errors.len(),
+                                batch::report_path(&PathBuf::from(self.batch_path.trim())).display()
+                            ),
+                        );
+                        egui::ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                            for error in &report.errors {
+                                ui.label(error.to_string());
+                            }
+                        });
+                    }
+                }
+            });
+        self.show_batch = open;
+    }
+}
@@ -0,0 +1,82 @@
+use eframe::egui;
+
+use super::GuiApp;
+use crate::registry::CatalogItem;
+
+impl GuiApp {
+    //Searchable list of catalog items, returns the one that was clicked
+    pub(super) fn catalog_picker(&mut self, ui: &mut egui::Ui) -> Option<CatalogItem> {
+        let mut picked = None;
+        ui.horizontal(|ui| {
+            ui.label("Search catalog:");
+            ui.text_edit_singleline(&mut self.catalog_query);
+        });
+        egui::ScrollArea::vertical()
+            .id_source("catalog_picker")
+            .max_height(120.0)
+            .show(ui, |ui| {
+                for item in self.catalog.search(&self.catalog_query) {
+                    let text = format!(
+                        "{} {} ({:.2}/{})",
+                        item.code, item.description, item.unit_price, item.unit
+                    );
+                    if ui.selectable_label(false, text).clicked() {
+                        picked = Some(item.clone());
+                    }
+                }
+            });
+        picked
+    }
+
+    pub(super) fn show_catalog_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_catalog;
+        egui::Window::new("Catalog")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                let mut remove = None;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("catalog_grid").show(ui, |ui| {
+                        ui.label("Code");
+                        ui.label("Description");
+                        ui.label("Unit");
+                        ui.label("Unit price");
+                        ui.label("VAT");
+                        ui.end_row();
+                        for (index, item) in self.catalog.items.iter_mut().enumerate() {
+                            ui.text_edit_singleline(&mut item.code);
+                            ui.text_edit_multiline(&mut item.description);
+                            ui.text_edit_singleline(&mut item.unit);
+                            ui.add(egui::DragValue::new(&mut item.unit_price).speed(0.1));
+                            ui.add(egui::DragValue::new(&mut item.vat_rate).suffix("%"));
+                            if ui.button("Delete").clicked() {
+                                remove = Some(index);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                });
+                if let Some(index) = remove {
+                    self.catalog.items.remove(index);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Add item").clicked() {
+                        self.catalog.items.push(CatalogItem {
+                            unit: "kos".to_string(),
+                            vat_rate: 22.0,
+                            ..Default::default()
+                        });
+                    }
+                    if ui.button("Save").clicked() {
+                        match self.catalog.save() {
+                            Ok(_) => self.notifications.info("Catalog saved ✔"),
+                            Err(err) => self
+                                .notifications
+                                .error(format!("Error saving catalog: {}", err)),
+                        }
+                    }
+                });
+            });
+        self.show_catalog = open;
+    }
+}
@@ -0,0 +1,168 @@
+use eframe::egui;
+
+use super::GuiApp;
+use crate::invoicer::TaxpayerType;
+use crate::registry::{BankAccount, CompanyProfile};
+
+fn profile_fields(ui: &mut egui::Ui, profile: &mut CompanyProfile, id: usize) {
+    egui::Grid::new(format!("company_fields_{}", id)).show(ui, |ui| {
+        ui.label("Profile name");
+        ui.text_edit_singleline(&mut profile.profile_name);
+        ui.end_row();
+        ui.label("Company name");
+        ui.text_edit_singleline(&mut profile.company.company_name);
+        ui.end_row();
+        ui.label("Address");
+        ui.text_edit_singleline(&mut profile.company.company_address);
+        ui.end_row();
+        ui.label("Postal code");
+        ui.text_edit_singleline(&mut profile.company.company_postal_code);
+        ui.end_row();
+        ui.label("VAT ID");
+        ui.text_edit_singleline(&mut profile.company.company_vat_id);
+        ui.end_row();
+        ui.label("Taxpayer type");
+        egui::ComboBox::from_id_source(format!("taxpayer_type_{}", id))
+            .selected_text(profile.company.company_taxpayer_type.to_string())
+            .show_ui(ui, |ui| {
+                for taxpayer_type in [TaxpayerType::LegalPerson, TaxpayerType::SoleTrader] {
+                    ui.selectable_value(
+                        &mut profile.company.company_taxpayer_type,
+                        taxpayer_type,
+                        taxpayer_type.to_string(),
+                    );
+                }
+            });
+        ui.end_row();
+        ui.label("VAT payer");
+        ui.checkbox(&mut profile.vat_payer, "");
+        ui.end_row();
+        ui.label("VAT rate");
+        ui.add(egui::DragValue::new(&mut profile.company.company_vat_rate).suffix("%"));
+        ui.end_row();
+        ui.label("Registration number");
+        ui.text_edit_singleline(&mut profile.company.company_registration_number);
+        ui.end_row();
+        ui.label("Registered at");
+        ui.text_edit_singleline(&mut profile.company.company_business_registered_at);
+        ui.end_row();
+        ui.label("Phone");
+        ui.text_edit_singleline(&mut profile.company.company_phone);
+        ui.end_row();
+        ui.label("Currency");
+        ui.text_edit_singleline(&mut profile.company.company_currency);
+        ui.end_row();
+        ui.label("Logo path");
+        ui.text_edit_singleline(&mut profile.company.company_logo);
+        ui.end_row();
+        ui.label("Next invoice number");
+        ui.add(egui::DragValue::new(&mut profile.next_invoice_number).clamp_range(1..=i32::MAX));
+        ui.end_row();
+        ui.label("Font sizes");
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut profile.template.font_sizes.small));
+            ui.add(egui::DragValue::new(
+                &mut profile.template.font_sizes.medium,
+            ));
+            ui.add(egui::DragValue::new(&mut profile.template.font_sizes.large));
+        });
+        ui.end_row();
+    });
+    ui.label("Bank accounts");
+    let mut remove = None;
+    for (index, account) in profile.bank_accounts.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label("IBAN");
+            ui.text_edit_singleline(&mut account.iban);
+            ui.label("Bank");
+            ui.text_edit_singleline(&mut account.bank_name);
+            ui.label("SWIFT");
+            ui.text_edit_singleline(&mut account.swift);
+            if ui.button("Remove").clicked() {
+                remove = Some(index);
+            }
+        });
+    }
+    if let Some(index) = remove {
+        profile.bank_accounts.remove(index);
+    }
+    if ui.button("Add bank account").clicked() {
+        profile.bank_accounts.push(BankAccount::default());
+    }
+}
+
+impl GuiApp {
+    //Combo box for the active company, switching it also refills the invoice form
+    pub(super) fn company_selector(&mut self, ui: &mut egui::Ui) {
+        let mut selected = self.company_profiles.active_profile.clone();
+        egui::ComboBox::from_label("Company")
+            .selected_text(&selected)
+            .show_ui(ui, |ui| {
+                for profile in &self.company_profiles.profiles {
+                    ui.selectable_value(
+                        &mut selected,
+                        profile.profile_name.clone(),
+                        &profile.profile_name,
+                    );
+                }
+            });
+        if selected != self.company_profiles.active_profile {
+            self.company_profiles.active_profile = selected;
+            if let Some(profile) = self.company_profiles.active() {
+                profile.apply(&mut self.draft);
+            }
+            if let Err(err) = self.company_profiles.save() {
+                self.notifications
+                    .error(format!("Error saving company profiles: {}", err));
+            }
+        }
+    }
+
+    pub(super) fn show_companies_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_companies;
+        egui::Window::new("Companies")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                let mut remove = None;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (index, profile) in self.company_profiles.profiles.iter_mut().enumerate() {
+                        egui::CollapsingHeader::new(&profile.profile_name)
+                            .id_source(format!("company_{}", index))
+                            .show(ui, |ui| {
+                                profile_fields(ui, profile, index);
+                                if ui.button("Delete profile").clicked() {
+                                    remove = Some(index);
+                                }
+                            });
+                    }
+                });
+                if let Some(index) = remove {
+                    self.company_profiles.profiles.remove(index);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Add profile").clicked() {
+                        self.company_profiles.profiles.push(CompanyProfile {
+                            profile_name: "New company".to_string(),
+                            vat_payer: true,
+                            next_invoice_number: 1,
+                            template: self.draft.config.clone(),
+                            ..Default::default()
+                        });
+                    }
+                    if ui.button("Save").clicked() {
+                        match self.company_profiles.save() {
+                            Ok(_) => self.notifications.info("Company profiles saved ✔"),
+                            Err(err) => self
+                                .notifications
+                                .error(format!("Error saving company profiles: {}", err)),
+                        }
+                        if let Some(profile) = self.company_profiles.active() {
+                            profile.apply(&mut self.draft);
+                        }
+                    }
+                });
+            });
+        self.show_companies = open;
+    }
+}
@@ -0,0 +1,162 @@
+use eframe::egui;
+use egui::plot::{Bar, BarChart, Legend, Plot};
+
+use super::{GuiApp, WHITE};
+use crate::reporting::{self, Dashboard};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const PLOT_HEIGHT: f32 = 180.0;
+
+//Worked out again only after the invoices change
+#[derive(Default)]
+pub(super) struct DashboardView {
+    report: Option<Dashboard>,
+    //Year of the monthly chart, the latest one with invoices until another is picked
+    year: Option<i32>,
+}
+
+impl DashboardView {
+    pub(super) fn invalidate(&mut self) {
+        self.report = None;
+    }
+}
+
+fn revenue_chart(ui: &mut egui::Ui, id: &str, bars: Vec<Bar>) {
+    Plot::new(id)
+        .height(PLOT_HEIGHT)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show(ui, |plot| {
+            plot.bar_chart(BarChart::new(bars).name("Net revenue €"))
+        });
+}
+
+impl GuiApp {
+    pub(super) fn dashboard_tab(&mut self, ui: &mut egui::Ui) {
+        let report = self
+            .dashboard
+            .report
+            .get_or_insert_with(|| reporting::dashboard(&self.json_data));
+        if report.invoices == 0 {
+            ui.label("No invoices to report on");
+            return;
+        }
+        let latest = report.years().last().unwrap_or_default();
+        let year = self.dashboard.year.get_or_insert(latest);
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("{} invoices, amounts in EUR", report.invoices));
+                if report.skipped > 0 {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        format!(
+                            "{} invoices without an exchange rate are left out",
+                            report.skipped
+                        ),
+                    );
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.colored_label(WHITE, "Revenue per month");
+                egui::ComboBox::from_id_source("dashboard_year")
+                    .selected_text(year.to_string())
+                    .show_ui(ui, |ui| {
+                        for option in report.years() {
+                            ui.selectable_value(year, option, option.to_string());
+                        }
+                    });
+            });
+            let months = report
+                .monthly(*year)
+                .iter()
+                .enumerate()
+                .map(|(month, revenue)| {
+                    Bar::new(month as f64 + 1.0, *revenue)
+                        .name(MONTHS[month])
+                        .width(0.7)
+                })
+                .collect();
+            revenue_chart(ui, "monthly_revenue", months);
+            ui.colored_label(WHITE, "Revenue per year");
+            let years = report
+                .years
+                .iter()
+                .map(|(year, revenue)| Bar::new(*year as f64, *revenue).name(year).width(0.7))
+                .collect();
+            revenue_chart(ui, "yearly_revenue", years);
+            ui.separator();
+            let gross = report.paid + report.outstanding;
+            ui.colored_label(WHITE, "Paid and outstanding");
+            Plot::new("paid_outstanding")
+                .height(60.0)
+                .show_axes([true, false])
+                .allow_drag(false)
+                .allow_zoom(false)
+                .allow_scroll(false)
+                .legend(Legend::default())
+                .show(ui, |plot| {
+                    plot.bar_chart(
+                        BarChart::new(vec![Bar::new(0.0, report.paid).width(0.6)])
+                            .horizontal()
+                            .color(egui::Color32::GREEN)
+                            .name(format!("Paid {:.2} €", report.paid)),
+                    );
+                    plot.bar_chart(
+                        BarChart::new(vec![Bar::new(0.0, report.outstanding)
+                            .base_offset(report.paid)
+                            .width(0.6)])
+                        .horizontal()
+                        .color(egui::Color32::YELLOW)
+                        .name(format!("Outstanding {:.2} €", report.outstanding)),
+                    );
+                });
+            if gross > 0.0 {
+                ui.label(format!(
+                    "{:.0}% of the invoiced amount is paid",
+                    report.paid / gross * 100.0
+                ));
+            }
+            match report.average_days_to_payment {
+                Some(days) => ui.label(format!(
+                    "Paid on average {:.1} days after the invoice date",
+                    days
+                )),
+                None => ui.label("No paid invoices with a payment date yet"),
+            };
+            ui.separator();
+            ui.columns(2, |columns| {
+                columns[0].colored_label(WHITE, "Top partners");
+                let best = report
+                    .top_partners
+                    .first()
+                    .map_or(0.0, |(_, revenue)| *revenue);
+                for (partner, revenue) in &report.top_partners {
+                    let share = if best > 0.0 { revenue / best } else { 0.0 };
+                    columns[0].add(
+                        egui::ProgressBar::new(share as f32)
+                            .text(format!("{} {:.2} €", partner, revenue)),
+                    );
+                }
+                columns[1].colored_label(WHITE, "VAT per rate");
+                egui::Grid::new("dashboard_vat")
+                    .striped(true)
+                    .show(&mut columns[1], |ui| {
+                        ui.label("Rate");
+                        ui.label("Base");
+                        ui.label("VAT");
+                        ui.end_row();
+                        for vat in &report.vat {
+                            ui.label(format!("{}%", vat.rate));
+                            ui.label(format!("{:.2} €", vat.base));
+                            ui.label(format!("{:.2} €", vat.vat));
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+    }
+}
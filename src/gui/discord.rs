@@ -0,0 +1,77 @@
+use eframe::egui;
+use std::time::Duration;
+
+use super::GuiApp;
+use crate::currency;
+use crate::rpc::Presence;
+
+impl GuiApp {
+    //What the app is doing, the open windows win over the list
+    pub(super) fn presence(&self) -> Presence {
+        if self.create {
+            return Presence::Creating {
+                partner: self.draft.invoice.partner.partner_name.clone(),
+            };
+        }
+        if self.show_image {
+            let number = self.preview_number;
+            let invoice = self
+                .json_data
+                .iter()
+                .map(|racun| &racun.invoice)
+                .find(|invoice| invoice.invoice_number == number);
+            return Presence::Viewing {
+                number,
+                partner: invoice
+                    .map_or_else(String::new, |invoice| invoice.partner.partner_name.clone()),
+                amount: invoice.map_or_else(String::new, |invoice| {
+                    format!(
+                        "{:.2} {}",
+                        invoice.totals().signed(invoice.sign()).gross,
+                        currency::symbol(&invoice.invoice_currency)
+                    )
+                }),
+            };
+        }
+        Presence::Browsing(self.json_data.len())
+    }
+
+    fn save_rpc_config(&mut self) {
+        self.discord.configure(self.rpc_config.clone());
+        if let Err(err) = self.rpc_config.save() {
+            self.notifications
+                .error(format!("Error saving rpc.json: {}", err));
+        }
+    }
+
+    pub(super) fn show_discord_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_discord;
+        egui::Window::new("Discord")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let mut changed = ui
+                    .checkbox(&mut self.rpc_config.enabled, "Show activity in Discord")
+                    .changed();
+                changed |= ui
+                    .checkbox(
+                        &mut self.rpc_config.privacy,
+                        "Privacy mode, hide partner names and amounts",
+                    )
+                    .changed();
+                if changed {
+                    self.save_rpc_config();
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Status:");
+                    ui.label(self.discord.status().to_string());
+                });
+            });
+        //The status changes on the presence thread
+        if open {
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        self.show_discord = open;
+    }
+}
@@ -0,0 +1,331 @@
+use eframe::egui;
+use egui_extras::DatePickerButton;
+
+use super::{partners::partner_fields, GuiApp};
+use crate::currency;
+use crate::dates::PaymentTerms;
+use crate::invoicer::{Discount, PaymentMethod, Service, VatTreatment, EU_COUNTRIES};
+use crate::validation::{self, Severity};
+
+//None, a percentage or a fixed amount
+fn discount_editor(ui: &mut egui::Ui, discount: &mut Option<Discount>, id: &str) {
+    ui.horizontal(|ui| {
+        let text = match discount {
+            None => "None",
+            Some(Discount::Percent(_)) => "%",
+            Some(Discount::Amount(_)) => "Amount",
+        };
+        egui::ComboBox::from_id_source(format!("discount_{}", id))
+            .selected_text(text)
+            .width(70.0)
+            .show_ui(ui, |ui| {
+                if ui.selectable_label(discount.is_none(), "None").clicked() {
+                    *discount = None;
+                }
+                if ui
+                    .selectable_label(matches!(discount, Some(Discount::Percent(_))), "%")
+                    .clicked()
+                {
+                    *discount = Some(Discount::Percent(0.0));
+                }
+                if ui
+                    .selectable_label(matches!(discount, Some(Discount::Amount(_))), "Amount")
+                    .clicked()
+                {
+                    *discount = Some(Discount::Amount(0.0));
+                }
+            });
+        match discount {
+            Some(Discount::Percent(value)) => {
+                ui.add(
+                    egui::DragValue::new(value)
+                        .clamp_range(0.0..=100.0)
+                        .suffix("%"),
+                );
+            }
+            Some(Discount::Amount(value)) => {
+                ui.add(
+                    egui::DragValue::new(value)
+                        .speed(0.1)
+                        .clamp_range(0.0..=f64::MAX),
+                );
+            }
+            None => {}
+        }
+    });
+}
+
+impl GuiApp {
+    fn vat_treatment_editor(&mut self, ui: &mut egui::Ui) {
+        let treatment = &mut self.draft.invoice.vat_treatment;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("VAT treatment")
+                .selected_text(treatment.to_string())
+                .show_ui(ui, |ui| {
+                    let options = [
+                        VatTreatment::Domestic,
+                        VatTreatment::ReverseCharge,
+                        VatTreatment::Export,
+                        VatTreatment::Exempt {
+                            article: String::new(),
+                        },
+                        VatTreatment::NonVatPayer,
+                        VatTreatment::Oss {
+                            country: String::new(),
+                        },
+                    ];
+                    for option in options {
+                        let selected =
+                            std::mem::discriminant(treatment) == std::mem::discriminant(&option);
+                        if ui.selectable_label(selected, option.to_string()).clicked() && !selected
+                        {
+                            *treatment = option;
+                        }
+                    }
+                });
+            if let VatTreatment::Exempt { article } = treatment {
+                ui.label("Article");
+                ui.text_edit_singleline(article);
+            }
+            if let VatTreatment::Oss { country } = treatment {
+                egui::ComboBox::from_label("Member state")
+                    .selected_text(country.as_str())
+                    .show_ui(ui, |ui| {
+                        for code in EU_COUNTRIES.iter().filter(|code| **code != "SI") {
+                            ui.selectable_value(country, code.to_string(), *code);
+                        }
+                    });
+            }
+        });
+    }
+
+    //Foreign currencies get the ECB rate for the invoice date, it can still be corrected by hand
+    fn currency_editor(&mut self, ui: &mut egui::Ui) {
+        let invoice = &mut self.draft.invoice;
+        ui.horizontal(|ui| {
+            let mut selected = invoice.invoice_currency.clone();
+            egui::ComboBox::from_label("Currency")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for code in currency::codes() {
+                        ui.selectable_value(&mut selected, code.to_string(), code);
+                    }
+                });
+            if selected != invoice.invoice_currency {
+                invoice.set_currency(&selected);
+                self.exchange_rates.apply(invoice);
+            }
+            if invoice.invoice_currency == currency::EUR {
+                return;
+            }
+            if ui.button("ECB rate").clicked() {
+                self.exchange_rates.apply(invoice);
+            }
+            if let Some(rate) = &mut invoice.exchange_rate {
+                ui.label("1 EUR =");
+                ui.add(egui::DragValue::new(&mut rate.rate).speed(0.0001));
+                ui.label(&rate.currency);
+                ui.label("on");
+                ui.add(DatePickerButton::new(&mut rate.date).id_source("rate_date"));
+            }
+        });
+    }
+
+    fn services_editor(&mut self, ui: &mut egui::Ui) {
+        let mut remove = None;
+        egui::Grid::new("services_form").show(ui, |ui| {
+            ui.label("Description");
+            ui.label("Quantity");
+            ui.label("Unit");
+            ui.label("Price");
+            ui.label("VAT");
+            ui.label("Discount");
+            ui.label("Goods");
+            ui.end_row();
+            for (index, service) in self.draft.invoice.services.iter_mut().enumerate() {
+                ui.text_edit_multiline(&mut service.service_name);
+                ui.add(
+                    egui::DragValue::new(&mut service.service_quantity)
+                        .speed(0.1)
+                        .clamp_range(0.0..=f64::MAX),
+                );
+                ui.text_edit_singleline(&mut service.service_unit);
+                ui.add(egui::DragValue::new(&mut service.service_price).speed(0.1));
+                ui.add(egui::DragValue::new(&mut service.service_tax).suffix("%"));
+                discount_editor(ui, &mut service.service_discount, &index.to_string());
+                ui.checkbox(&mut service.service_goods, "");
+                if ui.button("Remove").clicked() {
+                    remove = Some(index);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(index) = remove {
+            self.draft.invoice.services.remove(index);
+        }
+        ui.horizontal(|ui| {
+            ui.label("Document discount");
+            discount_editor(ui, &mut self.draft.invoice.invoice_discount, "document");
+        });
+        let totals = self.draft.invoice.totals();
+        ui.label(format!(
+            "Net: {:.2} VAT: {:.2} Total: {:.2} {}",
+            totals.net, totals.vat, totals.gross, self.draft.invoice.invoice_currency
+        ));
+        if ui.button("Add service").clicked() {
+            let currency = self.draft.invoice.invoice_currency.clone();
+            self.draft.invoice.services.push(Service {
+                service_quantity: 1.0,
+                service_tax: self.draft.invoice.invoice_tax,
+                service_currency: currency,
+                ..Default::default()
+            });
+        }
+    }
+
+    pub(super) fn show_create_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Create invoice!")
+            .resizable(true)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    if let Some(profile) = self.company_profiles.active() {
+                        ui.label(format!("Issued by: {}", profile.company.company_name));
+                        egui::ComboBox::from_label("Bank account")
+                            .selected_text(&self.draft.invoice.company.company_iban)
+                            .show_ui(ui, |ui| {
+                                for account in &profile.bank_accounts {
+                                    let text = format!("{} ({})", account.iban, account.bank_name);
+                                    if ui.selectable_label(false, text).clicked() {
+                                        account.apply(&mut self.draft);
+                                    }
+                                }
+                            });
+                    }
+                    egui::Grid::new("invoice_form").show(ui, |ui| {
+                        ui.label("Invoice number");
+                        ui.add(egui::DragValue::new(&mut self.draft.invoice.invoice_number));
+                        ui.end_row();
+                        let invoice = &mut self.draft.invoice;
+                        ui.label("Credit note");
+                        ui.horizontal(|ui| {
+                            let mut credit_note = invoice.credit_note_for.is_some();
+                            if ui.checkbox(&mut credit_note, "for invoice").changed() {
+                                invoice.credit_note_for =
+                                    credit_note.then_some((invoice.invoice_number - 1).max(1));
+                            }
+                            if let Some(original) = &mut invoice.credit_note_for {
+                                ui.add(egui::DragValue::new(original).clamp_range(1..=i32::MAX));
+                            }
+                        });
+                        ui.end_row();
+                        ui.label("Invoice date");
+                        ui.add(
+                            DatePickerButton::new(&mut invoice.invoice_date)
+                                .id_source("invoice_date"),
+                        );
+                        ui.end_row();
+                        ui.label("Service date");
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                DatePickerButton::new(&mut invoice.service_date)
+                                    .id_source("service_date"),
+                            );
+                            let mut period = invoice.service_period_end.is_some();
+                            if ui.checkbox(&mut period, "Period until").changed() {
+                                invoice.service_period_end = period.then_some(invoice.service_date);
+                            }
+                            if let Some(end) = &mut invoice.service_period_end {
+                                ui.add(DatePickerButton::new(end).id_source("service_period_end"));
+                            }
+                        });
+                        ui.end_row();
+                        ui.label("Payment terms");
+                        ui.horizontal(|ui| {
+                            let mut net = invoice.payment_terms.is_some();
+                            if ui.checkbox(&mut net, "Net").changed() {
+                                //Start from the days the due date already gives
+                                let days = (invoice.due_date - invoice.invoice_date).num_days();
+                                invoice.payment_terms =
+                                    net.then(|| PaymentTerms::net(days.max(0) as u32));
+                            }
+                            if let Some(terms) = &mut invoice.payment_terms {
+                                ui.add(
+                                    egui::DragValue::new(&mut terms.days)
+                                        .clamp_range(0..=365)
+                                        .suffix(" days"),
+                                );
+                                ui.checkbox(&mut terms.end_of_month, "From the end of the month");
+                            }
+                        });
+                        ui.end_row();
+                        invoice.apply_payment_terms();
+                        ui.label("Due date");
+                        ui.add_enabled(
+                            invoice.payment_terms.is_none(),
+                            DatePickerButton::new(&mut invoice.due_date).id_source("due_date"),
+                        );
+                        ui.end_row();
+                    });
+                    self.currency_editor(ui);
+                    self.vat_treatment_editor(ui);
+                    egui::ComboBox::from_label("Payment method")
+                        .selected_text(self.draft.invoice.payment_method.to_string())
+                        .show_ui(ui, |ui| {
+                            for method in [
+                                PaymentMethod::BankTransfer,
+                                PaymentMethod::Cash,
+                                PaymentMethod::Card,
+                            ] {
+                                ui.selectable_value(
+                                    &mut self.draft.invoice.payment_method,
+                                    method,
+                                    method.to_string(),
+                                );
+                            }
+                        });
+                    ui.separator();
+                    ui.heading("Partner");
+                    if let Some(partner) = self.partner_picker(ui) {
+                        self.draft.invoice.set_partner(&partner);
+                    }
+                    partner_fields(ui, &mut self.draft.invoice.partner, "draft");
+                    if ui.button("Save partner to address book").clicked() {
+                        self.partner_book.upsert(self.draft.invoice.partner.clone());
+                        if let Err(err) = self.partner_book.save() {
+                            self.notifications
+                                .error(format!("Error saving partners: {}", err));
+                        }
+                    }
+                    ui.separator();
+                    ui.heading("Services");
+                    if let Some(item) = self.catalog_picker(ui) {
+                        let currency = self.draft.invoice.invoice_currency.clone();
+                        self.draft.invoice.services.push(item.to_service(&currency));
+                    }
+                    self.services_editor(ui);
+                    ui.separator();
+                    let report = validation::validate(&self.draft);
+                    for issue in &report.issues {
+                        let color = match issue.severity {
+                            Severity::Error => egui::Color32::RED,
+                            Severity::Warning => egui::Color32::YELLOW,
+                        };
+                        ui.colored_label(color, issue.to_string());
+                    }
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(!report.has_errors(), egui::Button::new("Generate"))
+                            .clicked()
+                        {
+                            //The number is only used up once the invoice is generated
+                            self.worker.generate(self.draft.clone());
+                            self.create = false;
+                        }
+                        if ui.button("Close").clicked() {
+                            self.create = false
+                        }
+                    });
+                });
+            });
+    }
+}
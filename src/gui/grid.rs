@@ -0,0 +1,469 @@
+use chrono::{Local, NaiveDate};
+use eframe::egui;
+use egui_extras::DatePickerButton;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap, fmt::Display};
+
+use super::{worker::Job, GuiApp, WHITE};
+use crate::currency;
+use crate::dates;
+use crate::invoicer::{PaymentStatus, Racun, Totals};
+use crate::registry::CompanyProfile;
+use crate::storage;
+
+//Key of the grid settings in the eframe storage
+pub(super) const GRID_KEY: &str = "invoice_grid";
+const PAGE_SIZES: [usize; 4] = [25, 50, 100, 250];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub(super) enum SortColumn {
+    #[default]
+    Number,
+    InvoiceDate,
+    ServiceDate,
+    DueDate,
+    Partner,
+    Provider,
+    Status,
+    Net,
+    //Saved filters from before the net and outstanding columns sorted by "Amount"
+    #[serde(alias = "Amount")]
+    Gross,
+    Outstanding,
+    Currency,
+}
+
+impl Display for SortColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            SortColumn::Number => "Invoice number",
+            SortColumn::InvoiceDate => "Invoice Date",
+            SortColumn::ServiceDate => "Service Date",
+            SortColumn::DueDate => "Due Date",
+            SortColumn::Partner => "Partner",
+            SortColumn::Provider => "Provider",
+            SortColumn::Status => "Status",
+            SortColumn::Net => "Net",
+            SortColumn::Gross => "Gross",
+            SortColumn::Outstanding => "Outstanding",
+            SortColumn::Currency => "Currency",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+//Everything the user set on the grid, saved between sessions
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub(super) struct GridFilter {
+    //Matches the number, the partner and the service descriptions
+    query: String,
+    status: Option<PaymentStatus>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    only_active_company: bool,
+    overdue: bool,
+    sort: SortColumn,
+    descending: bool,
+    page_size: usize,
+}
+
+impl Default for GridFilter {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            status: None,
+            from: None,
+            to: None,
+            only_active_company: false,
+            overdue: false,
+            sort: SortColumn::default(),
+            descending: false,
+            page_size: PAGE_SIZES[1],
+        }
+    }
+}
+
+impl GridFilter {
+    fn matches(&self, racun: &Racun, query: &str, company: Option<&CompanyProfile>) -> bool {
+        let invoice = &racun.invoice;
+        if self.status.is_some_and(|status| status != invoice.status) {
+            return false;
+        }
+        if self.from.is_some_and(|from| invoice.invoice_date < from)
+            || self.to.is_some_and(|to| invoice.invoice_date > to)
+        {
+            return false;
+        }
+        if self.overdue && !is_overdue(racun) {
+            return false;
+        }
+        if company.is_some_and(|profile| !profile.owns(racun)) {
+            return false;
+        }
+        query.is_empty()
+            || invoice.invoice_number.to_string().contains(query)
+            || invoice.partner.partner_name.to_lowercase().contains(query)
+            || invoice
+                .services
+                .iter()
+                .any(|service| service.service_name.to_lowercase().contains(query))
+    }
+
+    fn compare(&self, a: (&Racun, &Totals), b: (&Racun, &Totals)) -> Ordering {
+        let ((a, a_totals), (b, b_totals)) = ((&a.0.invoice, a.1), (&b.0.invoice, b.1));
+        match self.sort {
+            SortColumn::Number => a.invoice_number.cmp(&b.invoice_number),
+            SortColumn::InvoiceDate => a.invoice_date.cmp(&b.invoice_date),
+            SortColumn::ServiceDate => a.service_date.cmp(&b.service_date),
+            SortColumn::DueDate => a.due_date.cmp(&b.due_date),
+            SortColumn::Partner => a.partner.partner_name.cmp(&b.partner.partner_name),
+            SortColumn::Provider => a.company.company_name.cmp(&b.company.company_name),
+            SortColumn::Status => a.status.to_string().cmp(&b.status.to_string()),
+            SortColumn::Net => a_totals.net.total_cmp(&b_totals.net),
+            SortColumn::Gross => a_totals.gross.total_cmp(&b_totals.gross),
+            SortColumn::Outstanding => a_totals.outstanding.total_cmp(&b_totals.outstanding),
+            SortColumn::Currency => a.invoice_currency.cmp(&b.invoice_currency),
+        }
+    }
+}
+
+fn is_overdue(racun: &Racun) -> bool {
+    racun.invoice.status == PaymentStatus::UNPAID
+        && racun.invoice.due_date < Local::now().date_naive()
+}
+
+struct Row {
+    //Position in json_data
+    index: usize,
+    totals: Totals,
+}
+
+//Footer line of the filtered invoices in one currency
+#[derive(Default)]
+struct Sum {
+    invoices: usize,
+    net: f64,
+    gross: f64,
+    outstanding: f64,
+}
+
+//The filtered and sorted rows are only worked out again when the filter or the invoices
+//change, drawing a page of a 10k invoice list is then as cheap as drawing a short one
+#[derive(Default)]
+pub(super) struct InvoiceGrid {
+    pub(super) filter: GridFilter,
+    page: usize,
+    //Filter and company profile the rows were worked out for
+    applied: Option<(GridFilter, String)>,
+    rows: Vec<Row>,
+    sums: BTreeMap<String, Sum>,
+}
+
+impl InvoiceGrid {
+    pub(super) fn invalidate(&mut self) {
+        self.applied = None;
+    }
+
+    fn update(&mut self, invoices: &[Racun], company: Option<&CompanyProfile>) {
+        let key = (
+            self.filter.clone(),
+            company
+                .map(|profile| profile.profile_name.clone())
+                .unwrap_or_default(),
+        );
+        if self.applied.as_ref() == Some(&key) {
+            return;
+        }
+        if self
+            .applied
+            .as_ref()
+            .is_some_and(|(filter, _)| *filter != self.filter)
+        {
+            self.page = 0;
+        }
+        let company = company.filter(|_| self.filter.only_active_company);
+        let query = self.filter.query.trim().to_lowercase();
+        self.rows = (0..invoices.len())
+            .filter(|index| self.filter.matches(&invoices[*index], &query, company))
+            //Credit notes subtract from the sums like on the dashboard
+            .map(|index| Row {
+                index,
+                totals: invoices[index]
+                    .invoice
+                    .totals()
+                    .signed(invoices[index].invoice.sign()),
+            })
+            .collect();
+        let filter = &self.filter;
+        self.rows.sort_by(|a, b| {
+            let ordering = filter.compare(
+                (&invoices[a.index], &a.totals),
+                (&invoices[b.index], &b.totals),
+            );
+            if filter.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        self.sums.clear();
+        for row in &self.rows {
+            let currency = &invoices[row.index].invoice.invoice_currency;
+            let sum = self.sums.entry(currency.clone()).or_default();
+            sum.invoices += 1;
+            sum.net += row.totals.net;
+            sum.gross += row.totals.gross;
+            sum.outstanding += row.totals.outstanding;
+        }
+        self.applied = Some(key);
+    }
+
+    fn pages(&self) -> usize {
+        self.rows
+            .len()
+            .div_ceil(self.filter.page_size.max(1))
+            .max(1)
+    }
+}
+
+//Right aligned so the decimal points of a column line up
+fn amount_label(ui: &mut egui::Ui, amount: f64, code: &str) {
+    ui.label(
+        egui::RichText::new(format!("{:>12.2} {}", amount, currency::symbol(code))).monospace(),
+    );
+}
+
+impl GuiApp {
+    fn grid_filters(&mut self, ui: &mut egui::Ui) {
+        let filter = &mut self.grid.filter;
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            ui.text_edit_singleline(&mut filter.query)
+                .on_hover_text("Invoice number, partner or service");
+            egui::ComboBox::from_label("Status")
+                .selected_text(
+                    filter
+                        .status
+                        .map_or("All".to_string(), |status| status.to_string()),
+                )
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut filter.status, None, "All");
+                    for status in [PaymentStatus::PAID, PaymentStatus::UNPAID] {
+                        ui.selectable_value(&mut filter.status, Some(status), status.to_string());
+                    }
+                });
+            ui.checkbox(&mut filter.overdue, "Overdue");
+        });
+        ui.horizontal(|ui| {
+            let today = Local::now().date_naive();
+            let filter = &mut self.grid.filter;
+            for (label, date) in [("From", &mut filter.from), ("To", &mut filter.to)] {
+                let mut enabled = date.is_some();
+                if ui.checkbox(&mut enabled, label).changed() {
+                    *date = enabled.then_some(today);
+                }
+                if let Some(date) = date {
+                    ui.add(DatePickerButton::new(date).id_source(label));
+                }
+            }
+            self.company_selector(ui);
+            ui.checkbox(
+                &mut self.grid.filter.only_active_company,
+                "Only this company",
+            );
+        });
+    }
+
+    fn sort_header(&mut self, ui: &mut egui::Ui, column: SortColumn) {
+        let filter = &mut self.grid.filter;
+        let text = match (filter.sort == column, filter.descending) {
+            (true, false) => format!("{} ⏶", column),
+            (true, true) => format!("{} ⏷", column),
+            (false, _) => column.to_string(),
+        };
+        if ui
+            .selectable_label(
+                filter.sort == column,
+                egui::RichText::new(text).color(WHITE),
+            )
+            .clicked()
+        {
+            filter.descending = filter.sort == column && !filter.descending;
+            filter.sort = column;
+        }
+    }
+
+    fn grid_pager(&mut self, ui: &mut egui::Ui) {
+        let pages = self.grid.pages();
+        self.grid.page = self.grid.page.min(pages - 1);
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.grid.page > 0, egui::Button::new("◀"))
+                .clicked()
+            {
+                self.grid.page -= 1;
+            }
+            ui.label(format!(
+                "Page {} of {} ({} invoices)",
+                self.grid.page + 1,
+                pages,
+                self.grid.rows.len()
+            ));
+            if ui
+                .add_enabled(self.grid.page + 1 < pages, egui::Button::new("▶"))
+                .clicked()
+            {
+                self.grid.page += 1;
+            }
+            egui::ComboBox::from_label("per page")
+                .selected_text(self.grid.filter.page_size.to_string())
+                .show_ui(ui, |ui| {
+                    for size in PAGE_SIZES {
+                        ui.selectable_value(
+                            &mut self.grid.filter.page_size,
+                            size,
+                            size.to_string(),
+                        );
+                    }
+                });
+        });
+    }
+
+    pub(super) fn invoice_grid(&mut self, ui: &mut egui::Ui) {
+        self.grid_filters(ui);
+        self.grid
+            .update(&self.json_data, self.company_profiles.active());
+        self.grid_pager(ui);
+        if self.grid.rows.is_empty() {
+            if self.json_data.is_empty() && self.worker.is_running(Job::Load) {
+                return;
+            }
+            ui.label(if self.json_data.is_empty() {
+                "No invoices found"
+            } else {
+                "No invoices match the filter"
+            });
+            return;
+        }
+        let start = self.grid.page * self.grid.filter.page_size;
+        let end = (start + self.grid.filter.page_size).min(self.grid.rows.len());
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("invoice_grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    for column in [
+                        SortColumn::Number,
+                        SortColumn::InvoiceDate,
+                        SortColumn::ServiceDate,
+                        SortColumn::DueDate,
+                        SortColumn::Partner,
+                        SortColumn::Provider,
+                        SortColumn::Status,
+                        SortColumn::Net,
+                        SortColumn::Gross,
+                        SortColumn::Outstanding,
+                        SortColumn::Currency,
+                    ] {
+                        self.sort_header(ui, column);
+                    }
+                    ui.colored_label(WHITE, "Actions");
+                    ui.end_row();
+                    for row in &self.grid.rows[start..end] {
+                        let invoice = &self.json_data[row.index].invoice;
+                        ui.label(invoice.invoice_number.to_string());
+                        ui.label(dates::format(invoice.invoice_date));
+                        ui.label(dates::format(invoice.service_date));
+                        ui.label(dates::format(invoice.due_date));
+                        ui.label(&invoice.partner.partner_name);
+                        ui.label(&invoice.company.company_name);
+                        ui.label(invoice.status.to_string());
+                        amount_label(ui, row.totals.net, &invoice.invoice_currency);
+                        amount_label(ui, row.totals.gross, &invoice.invoice_currency);
+                        amount_label(ui, row.totals.outstanding, &invoice.invoice_currency);
+                        ui.label(&invoice.invoice_currency);
+                        ui.horizontal(|ui| {
+                            //View opens the preview image, Delete removes the whole invoice folder
+                            if ui.button("View").clicked() {
+                                self.preview_number = invoice.invoice_number;
+                                self.show_image = true;
+                            };
+                            if invoice.status == PaymentStatus::UNPAID
+                                && ui
+                                    .add_enabled(
+                                        !self
+                                            .worker
+                                            .is_running(Job::MarkPaid(invoice.invoice_number)),
+                                        egui::Button::new("Paid"),
+                                    )
+                                    .clicked()
+                            {
+                                self.worker.mark_paid(
+                                    self.json_data[row.index].clone(),
+                                    Local::now().date_naive(),
+                                );
+                            }
+                            if !invoice.partner.partner_email.trim().is_empty()
+                                && ui
+                                    .add_enabled(
+                                        !self.worker.is_running(Job::Email(invoice.invoice_number)),
+                                        egui::Button::new("Email"),
+                                    )
+                                    .on_hover_text(&invoice.partner.partner_email)
+                                    .clicked()
+                            {
+                                self.worker.email(invoice.invoice_number);
+                            }
+                            if ui.button("Delete").clicked() {
+                                match storage::delete(invoice.invoice_number) {
+                                    Ok(_) => {
+                                        self.previews.remove(&invoice.invoice_number);
+                                        self.worker.changed();
+                                    }
+                                    Err(err) => self.notifications.error(err),
+                                }
+                            };
+                        });
+                        ui.end_row();
+                    }
+                    //Sums of every filtered invoice, not only this page
+                    for (code, sum) in &self.grid.sums {
+                        ui.colored_label(WHITE, format!("Total ({})", sum.invoices));
+                        for _ in 0..6 {
+                            ui.label("");
+                        }
+                        amount_label(ui, sum.net, code);
+                        amount_label(ui, sum.gross, code);
+                        amount_label(ui, sum.outstanding, code);
+                        ui.colored_label(WHITE, code);
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+
+    #[test]
+    fn credit_notes_reduce_the_sums() {
+        let invoice = sample();
+        let mut credit_note = sample();
+        credit_note.invoice.invoice_number = 13;
+        credit_note.invoice.credit_note_for = Some(12);
+        let mut grid = InvoiceGrid::default();
+        grid.update(&[invoice.clone(), credit_note], None);
+        let totals = invoice.invoice.totals();
+        assert!(totals.gross > 0.0);
+        let row = grid.rows.iter().find(|row| row.index == 1).unwrap();
+        assert_eq!(row.totals.gross, -totals.gross);
+        let sum = &grid.sums[&invoice.invoice.invoice_currency];
+        assert_eq!(sum.invoices, 2);
+        assert_eq!(sum.net, 0.0);
+        assert_eq!(sum.gross, 0.0);
+        assert_eq!(sum.outstanding, 0.0);
+    }
+}
@@ -1,114 +1,303 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-use crate::invoicer::{Racun, init, Invoice, InvoiceStructure, FontSizes, Service, Company, Partner};
-use eframe;
+use crate::batch::{BatchReport, Progress};
+use crate::fiscal::{register_premise, resubmit_queue, FiscalConfig};
+use crate::invoicer::{
+    Company, FontSizes, Invoice, InvoiceStructure, Partner, Racun, Service, TaxpayerType,
+};
+use crate::registry::{Catalog, CompanyProfile, CompanyProfiles, ExchangeRates, PartnerBook};
+#[cfg(feature = "discord")]
+use crate::rpc::{DiscordRPC, RpcConfig};
+use crate::schema::SCHEMA_VERSION;
+use chrono::NaiveDate;
 use eframe::egui;
 use egui::{widgets, Color32, TextureHandle};
 use egui::{RichText, Vec2};
 use rand::Rng;
-use fs::File;
-use std::{env, thread};
-use std::fs::{self, DirEntry};
-use std::io::Read;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+mod batch;
+mod catalog;
+mod companies;
+mod dashboard;
+#[cfg(feature = "discord")]
+mod discord;
+mod form;
+mod grid;
+mod notifications;
+mod partners;
+mod rates;
+mod watcher;
+mod worker;
+use dashboard::DashboardView;
+use grid::{GridFilter, InvoiceGrid, GRID_KEY};
+use notifications::Notifications;
+use worker::{Change, Job, Outcome, Worker};
 //Consts
 const PADDING: f32 = 5.0;
 const WHITE: Color32 = Color32::WHITE;
 const CYAN: Color32 = Color32::from_rgb(0, 255, 255);
 
-
+#[derive(PartialEq)]
+enum Tab {
+    Invoices,
+    Dashboard,
+}
 
 struct GuiApp {
     allowed_to_close: bool,
     show_confirmation_dialog: bool,
     show_image: bool,
-    invoice_paths: Vec<DirEntry>,
     json_data: Vec<Racun>,
-    delete_invoice: bool,
-    clicked_pdf_path: PathBuf,
-    // delete_invoice_path: PathBuf,
-    texture: Option<TextureHandle>,
-    refresh: bool, 
+    //Invoice shown in the image window
+    preview_number: i32,
+    //Decoded previews by invoice number, dropped when the invoice changes
+    previews: HashMap<i32, TextureHandle>,
+    grid: InvoiceGrid,
+    tab: Tab,
+    dashboard: DashboardView,
+    worker: Worker,
     create: bool,
-    edit: bool,
-   
+    draft: Racun,
+    partner_book: PartnerBook,
+    partner_query: String,
+    show_partners: bool,
+    company_profiles: CompanyProfiles,
+    show_companies: bool,
+    catalog: Catalog,
+    catalog_query: String,
+    show_catalog: bool,
+    show_batch: bool,
+    exchange_rates: ExchangeRates,
+    show_rates: bool,
+    rates_path: String,
+    batch_path: String,
+    batch_progress: Option<Arc<Progress>>,
+    batch_result: Arc<Mutex<Option<BatchReport>>>,
+    batch_report: Option<BatchReport>,
+    notifications: Notifications,
+    #[cfg(feature = "discord")]
+    discord: DiscordRPC,
+    #[cfg(feature = "discord")]
+    rpc_config: RpcConfig,
+    #[cfg(feature = "discord")]
+    show_discord: bool,
 }
 
 trait Data {
-    fn get_invoices(&mut self) -> Vec<DirEntry>;
-    fn parse_jsons(&mut self);
-    
+    fn reload(&mut self);
+    fn watch(&mut self, ctx: &egui::Context);
+    fn finish(&mut self, outcome: Outcome, ctx: &egui::Context);
+
     fn new() -> Self;
 }
 
 impl Data for GuiApp {
     fn new() -> Self {
+        let notifications = Notifications::default();
+        let draft = Racun::parse_from_file().unwrap_or_else(|err| {
+            notifications.error(format!("Could not load data.json: {}", err));
+            Racun::default()
+        });
+        //A broken rpc.json only turns the presence off
+        #[cfg(feature = "discord")]
+        let rpc_config = RpcConfig::load().unwrap_or_else(|err| {
+            notifications.error(format!("Could not load rpc.json: {}", err));
+            RpcConfig {
+                enabled: false,
+                ..RpcConfig::default()
+            }
+        });
         let mut this = Self {
             allowed_to_close: false,
-            clicked_pdf_path: PathBuf::new(),
             show_confirmation_dialog: false,
             show_image: false,
-            invoice_paths: Vec::new(),
             json_data: Vec::new(),
-            delete_invoice: false,
-            // delete_invoice_path: PathBuf::new(),
-            texture: None,
-            refresh: false,    
+            preview_number: 0,
+            previews: HashMap::new(),
+            grid: InvoiceGrid::default(),
+            tab: Tab::Invoices,
+            dashboard: DashboardView::default(),
+            worker: Worker::default(),
             create: false,
-            edit: false,
+            draft,
+            partner_book: PartnerBook::load().unwrap_or_else(|err| {
+                notifications.error(format!("Could not load the partners: {}", err));
+                PartnerBook::default()
+            }),
+            partner_query: String::new(),
+            show_partners: false,
+            company_profiles: CompanyProfiles::load().unwrap_or_else(|err| {
+                notifications.error(format!("Could not load the company profiles: {}", err));
+                CompanyProfiles::default()
+            }),
+            show_companies: false,
+            catalog: Catalog::load().unwrap_or_else(|err| {
+                notifications.error(format!("Could not load the catalog: {}", err));
+                Catalog::default()
+            }),
+            catalog_query: String::new(),
+            show_catalog: false,
+            show_batch: false,
+            exchange_rates: ExchangeRates::load().unwrap_or_else(|err| {
+                notifications.error(format!("Could not load the exchange rates: {}", err));
+                ExchangeRates::default()
+            }),
+            show_rates: false,
+            rates_path: String::new(),
+            batch_path: String::new(),
+            batch_progress: None,
+            batch_result: Arc::new(Mutex::new(None)),
+            batch_report: None,
+            notifications,
+            #[cfg(feature = "discord")]
+            discord: DiscordRPC::start(rpc_config.clone()),
+            #[cfg(feature = "discord")]
+            rpc_config,
+            #[cfg(feature = "discord")]
+            show_discord: false,
         };
-        this.invoice_paths = this.get_invoices();
-        this.parse_jsons();
+        //The first run only knows the company from data.json
+        if this.company_profiles.profiles.is_empty() {
+            let profile = CompanyProfile::from_racun(&this.draft);
+            this.company_profiles.active_profile = profile.profile_name.clone();
+            this.company_profiles.profiles.push(profile);
+        }
+        if let Some(profile) = this.company_profiles.active() {
+            profile.apply(&mut this.draft);
+        }
+        this.worker.migrate_and_load();
+        let notifications = this.notifications.clone();
+        thread::spawn(move || resubmit_fiscal_queue(&notifications));
         this
     }
-   
 
-    fn get_invoices(&mut self) -> Vec<DirEntry> {
-        let mut path = env::current_dir().unwrap();
-        let invoice_folder = PathBuf::from("invoices");
-        path.push(&invoice_folder);
-    
-        let folders: Vec<DirEntry> = fs::read_dir(path)
-            .unwrap()
-            .filter_map(|entry| entry.ok())
-            .filter(|entry| entry.file_type().unwrap().is_dir())
-            .collect();
-        folders
+    //The list is only read again when something changed it
+    fn reload(&mut self) {
+        self.worker.load();
+    }
+    //Without the watcher the list only changes with the app's own writes and Refresh
+    fn watch(&mut self, ctx: &egui::Context) {
+        if let Err(err) = self.worker.watch(ctx) {
+            self.notifications
+                .error(format!("Could not watch the invoices folder: {}", err));
+        }
     }
-    fn parse_jsons(&mut self) {
-        let paths = self.get_invoices();
-        //Make a vector of invoices
-        let mut json_data: Vec<Racun> = Vec::new();
-        for path in paths {
-            let mut file_path = path.path();
-            file_path.push("output.json");
-            let mut file_content = match File::open(file_path.to_str().unwrap().to_string()) {
-                Ok(file) => file,
-                Err(_) => {
-                    println!("Could not parse the json file.There could be a problem with the json file or the file could be missing.");
-                    continue;
-            
-                }, //*!TODO This panics alot if the user clicks refresh too fast or if the dir doesnt have the json (idk how tho) *//
-            };
-            let mut contents = String::new();
-            match file_content.read_to_string(&mut contents) {
-                Ok(_) => {
-                    //println!("File contents: {}", contents);
-                    let invoice: Racun = match serde_json::from_str(&contents) {
-                        Ok(invoice) => invoice,
-                        Err(err) => panic!("Could not deserialize the file, error code: {}", err),
+    fn finish(&mut self, outcome: Outcome, ctx: &egui::Context) {
+        match outcome {
+            Outcome::Loaded {
+                invoices,
+                errors,
+                migrated,
+            } => {
+                match migrated {
+                    None | Some(Ok(0)) => {}
+                    Some(Ok(migrated)) => self.notifications.info(format!(
+                        "Upgraded {} stored invoices to the current schema",
+                        migrated
+                    )),
+                    Some(Err(err)) => self
+                        .notifications
+                        .error(format!("Could not upgrade the stored invoices: {}", err)),
+                }
+                //One broken output.json shouldn't hide all the other invoices
+                for (path, err) in errors {
+                    self.notifications
+                        .error(format!("Could not read {}: {}", path.display(), err));
+                }
+                self.previews.retain(|number, _| {
+                    invoices
+                        .iter()
+                        .any(|racun| racun.invoice.invoice_number == *number)
+                });
+                self.json_data = invoices;
+                self.grid.invalidate();
+                self.dashboard.invalidate();
+            }
+            Outcome::Updated { changes, errors } => {
+                for (path, err) in errors {
+                    self.notifications
+                        .error(format!("Could not read {}: {}", path.display(), err));
+                }
+                for change in changes {
+                    let number = match &change {
+                        Change::Stored(racun) => racun.invoice.invoice_number,
+                        Change::Removed(number) => *number,
                     };
-                    json_data.push(invoice);
+                    self.previews.remove(&number);
+                    let position = self
+                        .json_data
+                        .binary_search_by_key(&number, |racun| racun.invoice.invoice_number);
+                    match (change, position) {
+                        (Change::Stored(racun), Ok(index)) => self.json_data[index] = *racun,
+                        (Change::Stored(racun), Err(index)) => self.json_data.insert(index, *racun),
+                        (Change::Removed(_), Ok(index)) => {
+                            self.json_data.remove(index);
+                        }
+                        (Change::Removed(_), Err(_)) => {}
+                    }
+                }
+                self.grid.invalidate();
+                self.dashboard.invalidate();
+            }
+            Outcome::Generated(number, profile, result) => {
+                match result {
+                    Ok(warnings) => {
+                        self.notifications
+                            .info(format!("Invoice {} generated", number));
+                        for warning in warnings {
+                            self.notifications.error(warning);
+                        }
+                        self.company_profiles.use_number(&profile, number);
+                        if let Err(err) = self.company_profiles.save() {
+                            self.notifications
+                                .error(format!("Error saving company profiles: {}", err));
+                        }
+                        //The form moves on unless another number was typed in meanwhile
+                        if self.draft.invoice.company_profile == profile
+                            && self.draft.invoice.invoice_number <= number
+                        {
+                            self.draft.invoice.invoice_number = number + 1;
+                        }
+                    }
+                    Err(err) => self.notifications.error(err),
+                }
+                self.previews.remove(&number);
+                self.worker.changed();
+            }
+            Outcome::MarkedPaid(number, result) => match result {
+                Ok(()) => self.worker.changed(),
+                Err(err) => self.notifications.error(format!(
+                    "Invoice {} was not marked as paid: {}",
+                    number, err
+                )),
+            },
+            Outcome::Emailed(number, result) => match result {
+                Ok(to) => self
+                    .notifications
+                    .info(format!("Invoice {} sent to {}", number, to)),
+                Err(err) => self
+                    .notifications
+                    .error(format!("Invoice {} was not sent: {}", number, err)),
+            },
+            Outcome::Preview(number, result) => match result {
+                Ok(image) => {
+                    let texture =
+                        ctx.load_texture(format!("invoice_{}", number), image, Default::default());
+                    self.previews.insert(number, texture);
                 }
-                Err(err) => panic!("Could not read the json file, error code: {}", err),
-            };
+                Err(err) => {
+                    self.notifications
+                        .error(format!("Could not open the invoice image: {}", err));
+                    if self.preview_number == number {
+                        self.show_image = false;
+                    }
+                }
+            },
         }
-        self.json_data = json_data;
-        // println!("Json data: {:?}", self.json_data)
-        // Open the json file
     }
 }
 
-
 impl eframe::App for GuiApp {
     fn on_close_event(&mut self) -> bool {
         self.show_confirmation_dialog = true;
@@ -117,229 +306,151 @@ impl eframe::App for GuiApp {
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
         // Save settings here
     }
-    fn save(&mut self, _storage: &mut dyn eframe::Storage) {}
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, GRID_KEY, &self.grid.filter);
+    }
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if self.refresh {
-            self.invoice_paths = self.get_invoices();
-            self.parse_jsons();
-            self.refresh = false;
+        for outcome in self.worker.poll(ctx) {
+            self.finish(outcome, ctx);
+        }
+
+        self.notifications.show(ctx);
+        #[cfg(feature = "discord")]
+        {
+            let presence = self.presence();
+            self.discord.update(presence);
         }
-      
         egui::CentralPanel::default().show(ctx, |ui| {
-            self.refresh = true;
             egui::ScrollArea::new([false, false]).show(ui, |ui| {
                 ui.label("Project repo:");
                 ui.add(widgets::Hyperlink::new("https://github.com/actuallydoc"));
                 ui.add_space(PADDING);
                 ui.colored_label(
                     CYAN,
-                    RichText::new(format!("This is a simple invoice manager written in Rust")),
+                    RichText::new("This is a simple invoice manager written in Rust".to_string()),
                 );
-                if ui.button(RichText::new("Create").color(Color32::GREEN)).clicked() {
+                if ui
+                    .button(RichText::new("Create").color(Color32::GREEN))
+                    .clicked()
+                {
                     self.create = true;
                 }
-                //*!Only for debug purposes  *//
-                if ui.button("Generate fake invoice").clicked() {
-                   
-                    let racun = make_fake_invoice();
-                    //Spawn a new thread to generate the invoice and not freeze the ui
-                    thread::spawn(|| {
-                        match init(racun) {
-                            Ok(_) => {
-                                println!("Invoice generated");
-                                
-                    
-                            }
+                if ui.button("Partners").clicked() {
+                    self.show_partners = true;
+                }
+                if ui.button("Companies").clicked() {
+                    self.show_companies = true;
+                }
+                if ui.button("Catalog").clicked() {
+                    self.show_catalog = true;
+                }
+                if ui.button("Batch").clicked() {
+                    self.show_batch = true;
+                }
+                if ui.button("Exchange rates").clicked() {
+                    self.show_rates = true;
+                }
+                #[cfg(feature = "discord")]
+                if ui.button("Discord").clicked() {
+                    self.show_discord = true;
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Register business premise").clicked() {
+                        let notifications = self.notifications.clone();
+                        thread::spawn(move || match FiscalConfig::load() {
+                            Ok(Some(config)) => match register_premise(&config) {
+                                Ok(_) => notifications.info("Business premise registered ✔"),
+                                Err(err) => notifications.error(err),
+                            },
+                            Ok(None) => notifications.error("furs.json is missing"),
                             Err(err) => {
-                                println!("Error: {}", err);
+                                notifications.error(format!("Error reading furs.json: {}", err))
                             }
-                        };
+                        });
+                    }
+                    if ui.button("Resubmit queued invoices").clicked() {
+                        let notifications = self.notifications.clone();
+                        thread::spawn(move || resubmit_fiscal_queue(&notifications));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    //*!Only for debug purposes  *//
+                    if ui.button("Generate fake invoice").clicked() {
+                        self.worker.generate(make_fake_invoice());
+                    }
+                    if ui.button("Refresh").clicked() {
+                        self.reload();
+                    }
+                });
+                for job in self.worker.running() {
+                    ui.horizontal(|ui| {
+                        ui.add(widgets::Spinner::new());
+                        ui.label(job.to_string());
                     });
-                   
-
-                    //*!TODO Refresh doesnt work so it doesnnt. *//
-                   
-                
                 }
                 ui.add_space(PADDING);
                 ui.add_space(PADDING);
                 //Debug purpose ui.colored_label(WHITE, self.clicked_pdf_path.to_string_lossy());
                 ui.add_space(10.0);
-                egui::Grid::new("invoice_grid").show(ui, |ui| {
-                    if self.json_data.iter().count() == 0 {
-                        ui.add(widgets::Spinner::new());
-                        ui.label("No invoices found");
-                        self.refresh = true;
-                        ctx.request_repaint();
-                    } else {
-                        //fetch the invoices and display them
-                        ui.horizontal(|ui| ui.colored_label(WHITE, "Invoice number"));
-                        ui.colored_label(WHITE, "Invoice Date");
-                        ui.colored_label(WHITE, "Service Date");
-                        ui.colored_label(WHITE, "Due Date");
-                        ui.colored_label(WHITE, "Partner");
-                        ui.colored_label(WHITE, "Provider");
-                        ui.colored_label(WHITE, "Status");
-                        ui.colored_label(WHITE, "Amount");
-                        ui.colored_label(WHITE, "Currency");
-                        ui.colored_label(WHITE, "Actions");
-                        ui.end_row();
-                        for invoice in self.json_data.iter_mut() {
-                            ui.horizontal(|ui| {
-                                ui.label(invoice.invoice.invoice_number.to_string())
-                            });
-                            ui.label(invoice.invoice.invoice_date.to_string());
-                            ui.label(invoice.invoice.service_date.to_string());
-                            ui.label(invoice.invoice.due_date.to_string());
-                            ui.label(invoice.invoice.partner.partner_name.to_string());
-                            ui.label(invoice.invoice.company.company_name.to_string());
-                            ui.label(invoice.invoice.status.to_string());
-                         
-                            for service in &invoice.invoice.services {
-                                //Calculate the total price of the invoice
-                                let mut total_price = 0.0;
-                                total_price += service.service_price + service.service_tax;
-                                ui.label(total_price.to_string());
-                            }
-                            ui.label(invoice.invoice.invoice_currency.to_string());
-
-                            ui.horizontal(|ui| {
-                                //When a button is clicked make some actions edit will open the invoice data in another window and u will be able to edit it there
-                                //View will open the invoice in a pdf viewer
-                                //Delete will delete the invoice
-
-                                if ui.button("View").clicked() {
-                                  
-                                    for invoice_path in &self.invoice_paths {
-                                        if invoice_path
-                                            .path()
-                                            .ends_with(&invoice.invoice.invoice_number.to_string())
-                                        {
-                                            self.clicked_pdf_path = invoice_path.path();
-                                            //Get the JPG file from the clicked invoice and render it
-                                            if let Some(_) = self.clicked_pdf_path.file_name() {
-                                                
-                                                if let Ok(files) =
-                                                    fs::read_dir(&self.clicked_pdf_path)
-                                                {
-                                                    for file in files {
-                                                        if let Ok(file) = file {
-                                                            if let Some(extension) =
-                                                                file.path().extension()
-                                                            {
-                                                                if extension == "jpg" {
-                                                                    println!(
-                                                                        "File name: {}",
-                                                                        file.path()
-                                                                            .to_string_lossy()
-                                                                    );
-                                                                    //Get the image from the path and render it
-                                                                    let image = image::io::Reader::open(file.path()).unwrap().decode().unwrap();
-                                                                    let size = [image.width() as _, image.height() as _];
-                                                                    let image_buffer = image.to_rgba8();
-                                                                    let pixels = image_buffer.as_flat_samples();
-                                                                    let color_img = egui::ColorImage::from_rgba_unmultiplied(
-                                                                        size,
-                                                                        pixels.as_slice());
-                                                                        //self.image = Some(RetainedImage::from_color_image("Test image", color_img));
-                                                                        self.texture = None;
-                                                                        self.texture.get_or_insert_with(|| {
-                                                                            // Load the texture only once.
-                                                                            ui.ctx().load_texture(
-                                                                                "image",
-                                                                                color_img,
-                                                                                Default::default()
-                                                                            )
-                                                                        });
-                                                                       
-                                                                
-                                                                    // RetainedImage::from
-                                                                    
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            } else {
-                                                println!("Could not get the file name");
-                                            }
-
-                                            //Make the self.clicked_pdf_path -> Image on the GUI
-                                        }
-                                    }
-                                    //Check which invoice is clicked and open the pdf in a new window
-
-                                    self.show_image = true;
-                                };
-                                if ui.button("Edit").clicked() {
-                                    //Open the invoice in a new window with its data and allow the user to edit it
-                                    //TODO: Implement this
-                                };
-                                if ui.button("Delete").clicked() {
-                                    self.delete_invoice = true;
-                                    if self.delete_invoice {
-                                        for invoice_path in &self.invoice_paths {
-                                            if invoice_path
-                                                .path()
-                                                .ends_with(&invoice.invoice.invoice_number.to_string())
-                                            {
-                                             //Delete the invoice from the json file
-                                                match fs::remove_dir_all(invoice_path.path()) {
-                                                    Ok(_) => self.refresh = true ,
-                                                    Err(_) => (),
-                                            
-                                                }
-                                               
-                                            }
-
-                                        }
-                                    }
-                                    //Delete the invoice from the gui and from the json file
-                                    //TODO: Implement this
-                                };
-                            });
-
-                            ui.end_row();
-                        }
-
-                    }
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.tab, Tab::Invoices, "Invoices");
+                    ui.selectable_value(&mut self.tab, Tab::Dashboard, "Dashboard");
                 });
+                match self.tab {
+                    Tab::Invoices => self.invoice_grid(ui),
+                    Tab::Dashboard => self.dashboard_tab(ui),
+                }
             });
         });
         if self.create {
-            egui::Window::new("Create invoice!").resizable(true).show(ctx,|ui|{
-
-                if ui.button("Close").clicked(){
-                    self.create = false
-                }
-            
-            }); 
+            self.show_create_window(ctx);
+        }
+        if self.show_partners {
+            self.show_partners_window(ctx);
+        }
+        if self.show_companies {
+            self.show_companies_window(ctx);
+        }
+        if self.show_catalog {
+            self.show_catalog_window(ctx);
+        }
+        if self.show_rates {
+            self.show_rates_window(ctx);
+        }
+        #[cfg(feature = "discord")]
+        if self.show_discord {
+            self.show_discord_window(ctx);
+        }
+        if self.show_batch || self.batch_progress.is_some() {
+            self.show_batch_window(ctx);
         }
         if self.show_image {
-                // println!("Show image is true");
-            if self.texture.is_some() {
-               
-                // println!("Image is not none");
-                if let Some(texture) = &mut self.texture {
-                    egui::Window::new("Image").collapsible(true).resizable(true).default_size(Vec2::new(1000.0, 1000.0)).show(ctx, |ui| {
-                        egui::ScrollArea::new([true, true]).show(ui, |ui| {
-                        ui.add(egui::Image::new(texture.id(), [500.0, 700.0]));
-                        // });
+            let number = self.preview_number;
+            egui::Window::new(format!("Invoice {}", number))
+                .collapsible(true)
+                .resizable(true)
+                .default_size(Vec2::new(1000.0, 1000.0))
+                .show(ctx, |ui| {
+                    egui::ScrollArea::new([true, true]).show(ui, |ui| {
+                        match self.previews.get(&number) {
+                            Some(texture) => {
+                                ui.add(egui::Image::new(texture.id(), [500.0, 700.0]));
+                            }
+                            //Also asks again after the invoice changed while the window was open
+                            None => {
+                                self.worker.preview(number);
+                                ui.horizontal(|ui| {
+                                    ui.add(widgets::Spinner::new());
+                                    ui.label(Job::Preview(number).to_string());
+                                });
+                            }
+                        }
                         if ui.button("Close").clicked() {
                             self.show_image = false;
                         }
                     });
-                    });
-                
-                }   
-            }  else {
-                // println!("Image is none");
-                self.texture = None;
-            }   
-    
-} else {
-self.show_image = false;
-}
+                });
+        }
         if self.show_confirmation_dialog {
             // Show confirmation dialog:
             egui::Window::new("Do you want to quit?")
@@ -362,16 +473,21 @@ self.show_image = false;
     }
 }
 
-
 //Only for testing purposes
-fn make_fake_invoice()-> Racun {
+fn make_fake_invoice() -> Racun {
     let mut rng = rand::thread_rng();
-    let racun1 = Racun {
+    let invoice_date = NaiveDate::from_ymd_opt(2020, rng.gen_range(1..=12), rng.gen_range(1..=28))
+        .unwrap_or_default();
+    Racun {
+        schema_version: SCHEMA_VERSION,
         invoice: Invoice {
+            company_profile: String::new(),
             invoice_number: rng.gen_range(1..200),
-            invoice_date: format!("{}/{}/{}", rng.gen_range(1..31), rng.gen_range(1..12), rng.gen_range(2020..2021)),
-            due_date: format!("{}/{}/{}", rng.gen_range(1..31), rng.gen_range(1..12), rng.gen_range(2020..2021)),
-            service_date: format!("{}/{}/{}", rng.gen_range(1..31), rng.gen_range(1..12), rng.gen_range(2020..2021)),
+            invoice_date,
+            due_date: invoice_date + chrono::Duration::days(30),
+            service_date: invoice_date,
+            service_period_end: None,
+            payment_terms: None,
             invoice_currency: "EUR".to_string(),
             company: Company {
                 company_address: "Company address".to_string(),
@@ -379,14 +495,16 @@ fn make_fake_invoice()-> Racun {
                 company_bankname: "Company bank name".to_string(),
                 company_business_registered_at: "Company business registered at".to_string(),
                 company_currency: "EUR".to_string(),
-                company_iban: "Company iban".to_string(),
+                company_iban: "SI56 2633 0001 2039 086".to_string(),
                 company_phone: "Company phone".to_string(),
                 company_postal_code: "Company postal code".to_string(),
                 company_registration_number: "Company registration number".to_string(),
                 company_vat_rate: 22.0,
                 company_signature: "Company signature".to_string(),
-                company_swift: "Company swift".to_string(),
-                company_vat_id: "Company vat id".to_string(),
+                company_swift: "SABRSI2X".to_string(),
+                company_vat_id: "12345679".to_string(),
+                company_logo: String::new(),
+                company_taxpayer_type: TaxpayerType::LegalPerson,
             },
             invoice_location: "Slovenia".to_string(),
             partner: Partner {
@@ -394,64 +512,110 @@ fn make_fake_invoice()-> Racun {
                 partner_name: "Partner name".to_string(),
                 partner_postal_code: "Partner postal code".to_string(),
                 partner_vat_id: "Partner vat id".to_string(),
-
+                ..Default::default()
             },
             invoice_tax: 22.0,
             invoice_reference: "123456789".to_string(),
             created_by: "Invoice generator".to_string(),
-            services: vec![Service {
-                service_currency: "EUR".to_string(),
-                service_name: "Service name".to_string(),
-                service_price: 15.30,
-                service_quantity: 1,
-                service_tax: 22.0,
-
-            }, Service {
-                service_currency: "EUR".to_string(),
-                service_name: "Service name".to_string(),
-                service_price: 15.30,
-                service_quantity: 1,
-                service_tax: 22.0,
-
-            },Service {
-                service_currency: "EUR".to_string(),
-                service_name: "Service name".to_string(),
-                service_price: 15.30,
-                service_quantity: 1,
-                service_tax: 22.0,
-
-            }],
+            services: vec![
+                Service {
+                    service_currency: "EUR".to_string(),
+                    service_name: "Service name".to_string(),
+                    service_price: 15.30,
+                    service_quantity: 1.0,
+                    service_unit: "kos".to_string(),
+                    service_code: String::new(),
+                    service_tax: 22.0,
+                    service_discount: None,
+                    service_goods: false,
+                },
+                Service {
+                    service_currency: "EUR".to_string(),
+                    service_name: "Service name".to_string(),
+                    service_price: 15.30,
+                    service_quantity: 1.0,
+                    service_unit: "kos".to_string(),
+                    service_code: String::new(),
+                    service_tax: 22.0,
+                    service_discount: None,
+                    service_goods: false,
+                },
+                Service {
+                    service_currency: "EUR".to_string(),
+                    service_name: "Service name".to_string(),
+                    service_price: 15.30,
+                    service_quantity: 1.0,
+                    service_unit: "kos".to_string(),
+                    service_code: String::new(),
+                    service_tax: 22.0,
+                    service_discount: None,
+                    service_goods: false,
+                },
+            ],
             status: crate::invoicer::PaymentStatus::UNPAID,
-            
+            ..Default::default()
         },
         config: InvoiceStructure {
             font_sizes: FontSizes {
-                small:9.0,
-                medium:14.0,
-                large:16.0,
+                small: 9.0,
+                medium: 14.0,
+                large: 16.0,
+            },
+        },
+    }
+}
+
+//Invoices that were issued while FURS was offline
+fn resubmit_fiscal_queue(notifications: &Notifications) {
+    match resubmit_queue() {
+        Ok(resubmission) => {
+            if resubmission.sent > 0 {
+                notifications.info(format!(
+                    "{} queued invoices confirmed by FURS ✔",
+                    resubmission.sent
+                ));
+            }
+            for (number, err) in resubmission.queued {
+                notifications.error(format!("Invoice {} is still queued: {}", number, err));
             }
         }
-    };
-    racun1
+        Err(err) => notifications.error(format!("Error resubmitting invoices: {}", err)),
+    }
 }
 
-
-
-
-
-
-
-
 pub fn entry() {
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(900.0, 700.0)),
         ..Default::default()
     };
-    let app = GuiApp::new();
+    let mut app = GuiApp::new();
 
     eframe::run_native(
         "Invoice GUI",
         options.clone(),
-        Box::new(|_cc| Box::new(app)),
+        Box::new(|cc| {
+            //Filters and sorting from the last session
+            if let Some(filter) = cc
+                .storage
+                .and_then(|storage| eframe::get_value::<GridFilter>(storage, GRID_KEY))
+            {
+                app.grid.filter = filter;
+            }
+            app.watch(&cc.egui_ctx);
+            Box::new(app)
+        }),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_invoices_are_valid() {
+        let mut racun = make_fake_invoice();
+        racun.prepare().unwrap();
+        let report = crate::validation::validate(&racun);
+        assert!(report.check().is_ok(), "{:?}", report);
+    }
+}
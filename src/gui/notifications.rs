@@ -0,0 +1,73 @@
+use eframe::egui;
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+//Info messages disappear on their own, errors stay until they are dismissed
+const INFO_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Notification {
+    message: String,
+    error: bool,
+    created: Instant,
+}
+
+//Cloned into worker threads so they can report back to the notification area
+#[derive(Clone, Default)]
+pub(super) struct Notifications {
+    items: Arc<Mutex<Vec<Notification>>>,
+}
+
+impl Notifications {
+    fn push(&self, message: String, error: bool) {
+        let mut items = self.items.lock().unwrap_or_else(|err| err.into_inner());
+        //The invoice list is reloaded after every change, don't repeat the same problem
+        if items.iter().any(|item| item.message == message) {
+            return;
+        }
+        items.push(Notification {
+            message,
+            error,
+            created: Instant::now(),
+        });
+    }
+
+    pub(super) fn info(&self, message: impl Display) {
+        self.push(message.to_string(), false);
+    }
+
+    pub(super) fn error(&self, message: impl Display) {
+        self.push(message.to_string(), true);
+    }
+
+    pub(super) fn show(&self, ctx: &egui::Context) {
+        let mut items = self.items.lock().unwrap_or_else(|err| err.into_inner());
+        items.retain(|item| item.error || item.created.elapsed() < INFO_TIMEOUT);
+        if items.is_empty() {
+            return;
+        }
+        let mut dismiss = None;
+        egui::TopBottomPanel::bottom("notifications").show(ctx, |ui| {
+            for (index, item) in items.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("✖").clicked() {
+                        dismiss = Some(index);
+                    }
+                    if item.error {
+                        ui.colored_label(egui::Color32::RED, &item.message);
+                    } else {
+                        ui.label(&item.message);
+                    }
+                });
+            }
+        });
+        if let Some(index) = dismiss {
+            items.remove(index);
+        }
+        if items.iter().any(|item| !item.error) {
+            ctx.request_repaint_after(INFO_TIMEOUT);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use eframe::egui;
+use egui::RichText;
+
+use super::{GuiApp, CYAN};
+use crate::invoicer::Partner;
+
+//Editable fields of a partner, used by the address book and the invoice form
+pub(super) fn partner_fields(ui: &mut egui::Ui, partner: &mut Partner, id: &str) {
+    egui::Grid::new(format!("partner_fields_{}", id)).show(ui, |ui| {
+        ui.label("Name");
+        ui.text_edit_singleline(&mut partner.partner_name);
+        ui.end_row();
+        ui.label("Address");
+        ui.text_edit_singleline(&mut partner.partner_address);
+        ui.end_row();
+        ui.label("Postal code");
+        ui.text_edit_singleline(&mut partner.partner_postal_code);
+        ui.end_row();
+        ui.label("Country");
+        ui.text_edit_singleline(&mut partner.partner_country);
+        ui.end_row();
+        ui.label("VAT ID");
+        ui.text_edit_singleline(&mut partner.partner_vat_id);
+        ui.end_row();
+        ui.label("Email");
+        ui.text_edit_singleline(&mut partner.partner_email);
+        ui.end_row();
+        ui.label("Contact person");
+        ui.text_edit_singleline(&mut partner.partner_contact_person);
+        ui.end_row();
+        ui.label("Payment terms (days)");
+        ui.add(egui::DragValue::new(&mut partner.partner_payment_terms).clamp_range(0..=365));
+        ui.end_row();
+        ui.label("Language");
+        ui.text_edit_singleline(&mut partner.partner_language);
+        ui.end_row();
+        ui.label("Currency");
+        ui.text_edit_singleline(&mut partner.partner_currency);
+        ui.end_row();
+    });
+}
+
+impl GuiApp {
+    //Searchable list of saved partners, returns the one that was clicked
+    pub(super) fn partner_picker(&mut self, ui: &mut egui::Ui) -> Option<Partner> {
+        let mut picked = None;
+        ui.horizontal(|ui| {
+            ui.label("Search partners:");
+            ui.text_edit_singleline(&mut self.partner_query);
+        });
+        egui::ScrollArea::vertical()
+            .id_source("partner_picker")
+            .max_height(120.0)
+            .show(ui, |ui| {
+                for (_, partner) in self.partner_book.search(&self.partner_query) {
+                    let text = format!("{} ({})", partner.partner_name, partner.partner_vat_id);
+                    if ui.selectable_label(false, text).clicked() {
+                        picked = Some(partner.clone());
+                    }
+                }
+            });
+        picked
+    }
+
+    pub(super) fn show_partners_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_partners;
+        egui::Window::new("Partners")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.colored_label(
+                    CYAN,
+                    RichText::new(
+                        "Changes only apply to new invoices, issued invoices keep their copy.",
+                    ),
+                );
+                let mut remove = None;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (index, partner) in self.partner_book.partners.iter_mut().enumerate() {
+                        egui::CollapsingHeader::new(&partner.partner_name)
+                            .id_source(format!("partner_{}", index))
+                            .show(ui, |ui| {
+                                partner_fields(ui, partner, &index.to_string());
+                                if ui.button("Delete").clicked() {
+                                    remove = Some(index);
+                                }
+                            });
+                    }
+                });
+                if let Some(index) = remove {
+                    self.partner_book.remove(index);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Add partner").clicked() {
+                        self.partner_book.partners.push(Partner {
+                            partner_name: "New partner".to_string(),
+                            ..Default::default()
+                        });
+                    }
+                    if ui.button("Save").clicked() {
+                        match self.partner_book.save() {
+                            Ok(_) => self.notifications.info("Partners saved ✔"),
+                            Err(err) => self
+                                .notifications
+                                .error(format!("Error saving partners: {}", err)),
+                        }
+                    }
+                });
+            });
+        self.show_partners = open;
+    }
+}
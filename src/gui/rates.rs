@@ -0,0 +1,64 @@
+use eframe::egui;
+use std::path::PathBuf;
+
+use super::GuiApp;
+use crate::dates;
+
+impl GuiApp {
+    fn import_rates(&mut self) {
+        let path = PathBuf::from(self.rates_path.trim());
+        match self.exchange_rates.import_ecb(&path) {
+            Ok(added) => {
+                if let Err(err) = self.exchange_rates.save() {
+                    self.notifications
+                        .error(format!("Error saving the exchange rates: {}", err));
+                }
+                self.notifications
+                    .info(format!("{} new exchange rates imported", added));
+            }
+            Err(err) => {
+                self.notifications
+                    .error(format!("Error importing {}: {}", path.display(), err))
+            }
+        }
+    }
+
+    pub(super) fn show_rates_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_rates;
+        egui::Window::new("Exchange rates")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.label("ECB reference rates, eurofxref-daily.xml or eurofxref-hist.xml");
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut self.rates_path);
+                    if ui
+                        .add_enabled(
+                            !self.rates_path.trim().is_empty(),
+                            egui::Button::new("Import"),
+                        )
+                        .clicked()
+                    {
+                        self.import_rates();
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("rates_grid").show(ui, |ui| {
+                        ui.label("Currency");
+                        ui.label("1 EUR =");
+                        ui.label("Date");
+                        ui.end_row();
+                        for rate in self.exchange_rates.latest() {
+                            ui.label(&rate.currency);
+                            ui.label(rate.rate.to_string());
+                            ui.label(dates::format(rate.date));
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        self.show_rates = open;
+    }
+}
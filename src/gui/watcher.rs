@@ -0,0 +1,136 @@
+//Watches the invoices folder so invoices written by the cli, the api or another copy of the
+//app show up without rescanning. Events are collected until the folder has been quiet for a
+//moment and only the invoices they touched are read again.
+use eframe::egui;
+use notify::{
+    event::{AccessKind, AccessMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+use super::worker::{Change, Outcome};
+use crate::error::InvoiceError;
+use crate::storage;
+
+const DEBOUNCE: Duration = Duration::from_millis(300);
+//A file that still doesn't parse after this many quiet periods is reported as broken
+const RETRIES: u32 = 5;
+
+//Dropping the watcher stops the events, the debounce thread ends with it
+pub(super) fn watch(
+    outcomes: Sender<Outcome>,
+    ctx: egui::Context,
+) -> Result<RecommendedWatcher, InvoiceError> {
+    let dir = storage::invoices_dir();
+    fs::create_dir_all(&dir)?;
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .map_err(watch_error)?;
+    thread::spawn(move || debounce(events, outcomes, ctx));
+    Ok(watcher)
+}
+
+fn watch_error(err: notify::Error) -> InvoiceError {
+    InvoiceError::Io(std::io::Error::other(err))
+}
+
+//Only output.json and the invoice folders matter, previews and pdfs are written separately
+fn changed_invoice(path: &Path) -> Option<i32> {
+    let number = storage::number_from_path(path)?;
+    let relevant = path == storage::invoice_dir(number) || path == storage::json_path(number);
+    relevant.then_some(number)
+}
+
+fn relevant_kind(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    }
+}
+
+fn debounce(
+    events: Receiver<notify::Result<Event>>,
+    outcomes: Sender<Outcome>,
+    ctx: egui::Context,
+) {
+    //Invoice number and how many times it couldn't be read yet
+    let mut pending: HashMap<i32, u32> = HashMap::new();
+    loop {
+        let received = if pending.is_empty() {
+            events.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            events.recv_timeout(DEBOUNCE)
+        };
+        match received {
+            Ok(Ok(event)) if relevant_kind(&event.kind) => {
+                for number in event.paths.iter().filter_map(|path| changed_invoice(path)) {
+                    pending.insert(number, 0);
+                }
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                let errors = vec![(storage::invoices_dir(), watch_error(err))];
+                let outcome = Outcome::Updated {
+                    changes: Vec::new(),
+                    errors,
+                };
+                if outcomes.send(outcome).is_err() {
+                    return;
+                }
+                ctx.request_repaint();
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let Some(outcome) = reload(&mut pending) else {
+                    continue;
+                };
+                if outcomes.send(outcome).is_err() {
+                    return;
+                }
+                //Nothing else wakes the ui up while it is idle
+                ctx.request_repaint();
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+//Reads the pending invoices again, the ones that don't parse yet stay pending
+fn reload(pending: &mut HashMap<i32, u32>) -> Option<Outcome> {
+    let mut changes = Vec::new();
+    let mut errors = Vec::new();
+    pending.retain(|number, attempts| {
+        //Like scan, a folder without output.json isn't an invoice (yet)
+        if !storage::json_path(*number).exists() {
+            changes.push(Change::Removed(*number));
+            return false;
+        }
+        match storage::load(*number) {
+            Ok(racun) => {
+                changes.push(Change::Stored(Box::new(racun)));
+                false
+            }
+            Err(_) if *attempts < RETRIES => {
+                *attempts += 1;
+                true
+            }
+            Err(err) => {
+                errors.push((storage::json_path(*number), err));
+                false
+            }
+        }
+    });
+    if changes.is_empty() && errors.is_empty() {
+        return None;
+    }
+    Some(Outcome::Updated { changes, errors })
+}
@@ -0,0 +1,238 @@
+//Reading and writing the stored invoices, generating and decoding previews run on their own threads.
+//Results come back through a channel that update() drains every frame, so the ui never
+//waits on the disk or pdfium. The folder watcher reports through the same channel.
+use chrono::NaiveDate;
+use eframe::egui;
+use notify::RecommendedWatcher;
+use std::{
+    fmt::Display,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use super::watcher;
+use crate::email;
+use crate::error::InvoiceError;
+use crate::invoicer::{init, Racun};
+use crate::render;
+use crate::storage;
+
+//The image window shows the page at 500x700, the 2000px jpg is scaled down before upload
+const PREVIEW_SIZE: (u32, u32) = (1000, 1400);
+//Keeps spinners moving and picks up results while nothing else repaints
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum Job {
+    Load,
+    Generate(i32),
+    Preview(i32),
+    Email(i32),
+    MarkPaid(i32),
+}
+
+impl Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Job::Load => write!(f, "Loading invoices"),
+            Job::Generate(number) => write!(f, "Generating invoice {}", number),
+            Job::Preview(number) => write!(f, "Rendering the preview of invoice {}", number),
+            Job::Email(number) => write!(f, "Sending invoice {}", number),
+            Job::MarkPaid(number) => write!(f, "Marking invoice {} as paid", number),
+        }
+    }
+}
+
+pub(super) enum Change {
+    Stored(Box<Racun>),
+    Removed(i32),
+}
+
+pub(super) enum Outcome {
+    Loaded {
+        invoices: Vec<Racun>,
+        errors: Vec<(PathBuf, InvoiceError)>,
+        //How many invoices the first load upgraded to the current schema
+        migrated: Option<Result<usize, InvoiceError>>,
+    },
+    //Invoices the watcher saw change since the last update
+    Updated {
+        changes: Vec<Change>,
+        errors: Vec<(PathBuf, InvoiceError)>,
+    },
+    //Invoice number and the company profile it was numbered from
+    Generated(i32, String, Result<Vec<String>, InvoiceError>),
+    Preview(i32, Result<egui::ColorImage, InvoiceError>),
+    //Address the invoice was sent to
+    Emailed(i32, Result<String, InvoiceError>),
+    MarkedPaid(i32, Result<(), InvoiceError>),
+}
+
+impl Outcome {
+    fn job(&self) -> Option<Job> {
+        match self {
+            Outcome::Loaded { .. } => Some(Job::Load),
+            Outcome::Updated { .. } => None,
+            Outcome::Generated(number, _, _) => Some(Job::Generate(*number)),
+            Outcome::Preview(number, _) => Some(Job::Preview(*number)),
+            Outcome::Emailed(number, _) => Some(Job::Email(*number)),
+            Outcome::MarkedPaid(number, _) => Some(Job::MarkPaid(*number)),
+        }
+    }
+}
+
+pub(super) struct Worker {
+    sender: Sender<Outcome>,
+    receiver: Receiver<Outcome>,
+    running: Vec<Job>,
+    //Something changed while a load was running, its result is already stale
+    reload: bool,
+    watcher: Option<RecommendedWatcher>,
+}
+
+impl Default for Worker {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver,
+            running: Vec::new(),
+            reload: false,
+            watcher: None,
+        }
+    }
+}
+
+impl Worker {
+    fn spawn(&mut self, job: Job, work: impl FnOnce() -> Outcome + Send + 'static) {
+        self.running.push(job);
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            //The receiver only goes away when the app closes
+            let _ = sender.send(work());
+        });
+    }
+
+    pub(super) fn load(&mut self) {
+        if self.is_running(Job::Load) {
+            self.reload = true;
+            return;
+        }
+        self.spawn(Job::Load, || {
+            let (invoices, errors) = storage::scan();
+            Outcome::Loaded {
+                invoices,
+                errors,
+                migrated: None,
+            }
+        });
+    }
+
+    //The first load upgrades invoices from older schema versions before reading them
+    pub(super) fn migrate_and_load(&mut self) {
+        self.spawn(Job::Load, || {
+            let migrated = storage::migrate();
+            let (invoices, errors) = storage::scan();
+            Outcome::Loaded {
+                invoices,
+                errors,
+                migrated: Some(migrated),
+            }
+        });
+    }
+
+    pub(super) fn watch(&mut self, ctx: &egui::Context) -> Result<(), InvoiceError> {
+        self.watcher = Some(watcher::watch(self.sender.clone(), ctx.clone())?);
+        Ok(())
+    }
+
+    //The app wrote to the invoices folder, without the watcher everything is read again
+    pub(super) fn changed(&mut self) {
+        if self.watcher.is_none() {
+            self.load();
+        }
+    }
+
+    pub(super) fn generate(&mut self, racun: Racun) {
+        let number = racun.invoice.invoice_number;
+        let profile = racun.invoice.company_profile.clone();
+        self.spawn(Job::Generate(number), move || {
+            Outcome::Generated(number, profile, init(racun))
+        });
+    }
+
+    pub(super) fn preview(&mut self, number: i32) {
+        if self.is_running(Job::Preview(number)) {
+            return;
+        }
+        self.spawn(Job::Preview(number), move || {
+            Outcome::Preview(number, decode_preview(number))
+        });
+    }
+
+    pub(super) fn email(&mut self, number: i32) {
+        if self.is_running(Job::Email(number)) {
+            return;
+        }
+        self.spawn(Job::Email(number), move || {
+            Outcome::Emailed(number, email::send_invoice(number, None))
+        });
+    }
+
+    pub(super) fn mark_paid(&mut self, mut racun: Racun, date: NaiveDate) {
+        let number = racun.invoice.invoice_number;
+        if self.is_running(Job::MarkPaid(number)) {
+            return;
+        }
+        self.spawn(Job::MarkPaid(number), move || {
+            racun.invoice.mark_paid(date);
+            Outcome::MarkedPaid(number, storage::save(&racun))
+        });
+    }
+
+    pub(super) fn is_running(&self, job: Job) -> bool {
+        self.running.contains(&job)
+    }
+
+    pub(super) fn running(&self) -> &[Job] {
+        &self.running
+    }
+
+    //Finished jobs since the last frame
+    pub(super) fn poll(&mut self, ctx: &egui::Context) -> Vec<Outcome> {
+        let outcomes: Vec<Outcome> = self.receiver.try_iter().collect();
+        for outcome in &outcomes {
+            let Some(job) = outcome.job() else {
+                continue;
+            };
+            if let Some(index) = self.running.iter().position(|running| *running == job) {
+                self.running.remove(index);
+            }
+        }
+        if self.reload && !self.is_running(Job::Load) {
+            self.reload = false;
+            self.load();
+        }
+        if !self.running.is_empty() {
+            ctx.request_repaint_after(POLL_INTERVAL);
+        }
+        outcomes
+    }
+}
+
+fn decode_preview(number: i32) -> Result<egui::ColorImage, InvoiceError> {
+    let path = storage::image_path(number);
+    //Invoices generated while pdfium was missing only have the pdf
+    if !path.exists() {
+        render::render_preview(number)?;
+    }
+    let image = image::open(path)?.thumbnail(PREVIEW_SIZE.0, PREVIEW_SIZE.1);
+    let size = [image.width() as usize, image.height() as usize];
+    let pixels = image.to_rgba8();
+    Ok(egui::ColorImage::from_rgba_unmultiplied(
+        size,
+        pixels.as_flat_samples().as_slice(),
+    ))
+}
@@ -1,17 +1,25 @@
-use chrono::Datelike;
+use chrono::{Datelike, NaiveDate};
 use printpdf::*;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
-    env,
-    error::Error,
     fmt::Display,
     fs::{self, read_to_string, File},
-    io::{BufWriter, ErrorKind, Write},
+    io::BufWriter,
     path::PathBuf,
 };
 
+use crate::currency::{self, ExchangeRate};
+use crate::dates::{self, PaymentTerms};
+use crate::error::InvoiceError;
+use crate::fiscal::{fiscalize, FiscalData, FiscalQueue};
+use crate::registry::ExchangeRates;
 use crate::render::export_pdf_to_jpegs;
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+use crate::schema;
+use crate::storage;
+use crate::validation;
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum PaymentStatus {
     PAID,
     #[default]
@@ -26,7 +34,43 @@ impl Display for PaymentStatus {
     }
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Copy)]
+//Cash and card invoices have to be fiscally verified
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PaymentMethod {
+    #[default]
+    BankTransfer,
+    Cash,
+    Card,
+}
+impl Display for PaymentMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaymentMethod::BankTransfer => write!(f, "Bank transfer"),
+            PaymentMethod::Cash => write!(f, "Cash"),
+            PaymentMethod::Card => write!(f, "Card"),
+        }
+    }
+}
+
+//How the company files its tax returns, sole traders (s.p.) as natural persons
+#[derive(Default, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TaxpayerType {
+    #[default]
+    LegalPerson,
+    SoleTrader,
+}
+impl Display for TaxpayerType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaxpayerType::LegalPerson => write!(f, "Legal person"),
+            TaxpayerType::SoleTrader => write!(f, "Sole trader (s.p.)"),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Copy, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct FontSizes {
     pub small: f64,
@@ -34,31 +78,161 @@ pub struct FontSizes {
     pub large: f64,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct InvoiceStructure {
     pub font_sizes: FontSizes,
 }
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Partner {
     pub partner_name: String,
     pub partner_address: String,
     pub partner_postal_code: String,
     pub partner_vat_id: String,
+    //Address book fields, older invoices don't have them
+    #[serde(default)]
+    pub partner_country: String,
+    #[serde(default)]
+    pub partner_email: String,
+    #[serde(default)]
+    pub partner_contact_person: String,
+    #[serde(default)]
+    pub partner_payment_terms: u32, //Days until the invoice is due
+    #[serde(default)]
+    pub partner_language: String,
+    #[serde(default)]
+    pub partner_currency: String,
+}
+
+//How VAT is charged on the invoice, everything except domestic and OSS is charged at 0%
+#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum VatTreatment {
+    #[default]
+    Domestic,
+    ReverseCharge, //EU B2B, the partner pays the VAT
+    Export,        //Outside of the EU
+    Exempt {
+        article: String,
+    },
+    NonVatPayer, //Small business that is not in the VAT system
+    //EU consumer charged at the rate of their member state, declared through OSS
+    Oss {
+        country: String,
+    },
+}
+
+impl VatTreatment {
+    pub fn charges_vat(&self) -> bool {
+        matches!(self, VatTreatment::Domestic | VatTreatment::Oss { .. })
+    }
+
+    //The note that has to be printed on the invoice
+    pub fn legal_note(&self) -> Option<String> {
+        match self {
+            VatTreatment::Domestic => None,
+            VatTreatment::ReverseCharge => Some(
+                "Obrnjena davčna obveznost, DDV ni obračunan v skladu s 196. členom Direktive 2006/112/ES (reverse charge)."
+                    .to_string(),
+            ),
+            VatTreatment::Export => Some(
+                "Oproščeno plačila DDV v skladu s 52. členom ZDDV-1 (izvoz).".to_string(),
+            ),
+            VatTreatment::Exempt { article } => {
+                Some(format!("Oproščeno plačila DDV v skladu z {}.", article))
+            }
+            VatTreatment::NonVatPayer => Some(
+                "DDV ni obračunan na podlagi prvega odstavka 94. člena ZDDV-1.".to_string(),
+            ),
+            VatTreatment::Oss { country } => Some(format!(
+                "DDV obračunan po stopnji države članice {} (posebna ureditev VEM/OSS).",
+                country
+            )),
+        }
+    }
+}
+
+impl Display for VatTreatment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VatTreatment::Domestic => write!(f, "Domestic"),
+            VatTreatment::ReverseCharge => write!(f, "Reverse charge"),
+            VatTreatment::Export => write!(f, "Export"),
+            VatTreatment::Exempt { .. } => write!(f, "Exempt"),
+            VatTreatment::NonVatPayer => write!(f, "Not a VAT payer"),
+            VatTreatment::Oss { .. } => write!(f, "OSS"),
+        }
+    }
+}
+
+pub const EU_COUNTRIES: [&str; 27] = [
+    "AT", "BE", "BG", "CY", "CZ", "DE", "DK", "EE", "EL", "ES", "FI", "FR", "HR", "HU", "IE", "IT",
+    "LT", "LU", "LV", "MT", "NL", "PL", "PT", "RO", "SE", "SI", "SK",
+];
+
+//Slovenian VAT IDs are stored without the SI prefix
+pub fn display_vat_id(vat_id: &str) -> String {
+    match vat_id_country(vat_id) {
+        Some(_) => vat_id.trim().to_string(),
+        None => format!("SI{}", vat_id.trim()),
+    }
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+//Country prefix of a VAT ID like DE123456789, None for IDs without one
+pub fn vat_id_country(vat_id: &str) -> Option<&str> {
+    let prefix = vat_id.trim().get(..2)?;
+    if prefix.chars().all(|c| c.is_ascii_alphabetic()) {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum Discount {
+    Percent(f64),
+    Amount(f64),
+}
+
+impl Discount {
+    //How much gets taken off the given amount, never more than the amount itself
+    pub fn amount_off(&self, amount: f64) -> f64 {
+        let off = match self {
+            Discount::Percent(percent) => amount * percent / 100.0,
+            Discount::Amount(value) => *value,
+        };
+        off.clamp(0.0, amount.max(0.0))
+    }
+
+    pub fn label(&self, currency: &str) -> String {
+        match self {
+            Discount::Percent(percent) => format!("{}%", percent),
+            Discount::Amount(value) => format!("-{:.2}{}", value, currency),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Service {
+    #[serde(default)]
+    pub service_code: String, //Catalog code, empty for one-off services
     pub service_name: String,
-    pub service_quantity: i32,
+    pub service_quantity: f64,
+    #[serde(default)]
+    pub service_unit: String, //Unit of measure like h, kos or km
     pub service_price: f64,
     pub service_tax: f64,
     pub service_currency: String,
+    #[serde(default)]
+    pub service_discount: Option<Discount>,
+    #[serde(default)]
+    pub service_goods: bool, //Goods rather than a service, the RP-O reports them apart
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Company {
     pub company_currency: String,
@@ -74,23 +248,45 @@ pub struct Company {
     pub company_signature: String, //Base64 string
     pub company_vat_rate: f64,
     pub company_business_registered_at: String,
+    #[serde(default)]
+    pub company_logo: String, //Path to the logo image
+    #[serde(default)]
+    pub company_taxpayer_type: TaxpayerType,
 }
 impl Company {}
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Racun {
+    //Always written as the current version, older documents are upgraded by schema::upgrade
+    #[serde(default, serialize_with = "crate::schema::write_version")]
+    pub schema_version: u32,
     pub invoice: Invoice,
     pub config: InvoiceStructure,
 }
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Invoice {
+    #[serde(default)]
+    pub company_profile: String, //Name of the company profile that issued the invoice
     pub invoice_number: i32,
-    pub invoice_date: String,
+    #[serde(with = "crate::dates::lenient")]
+    #[schemars(with = "NaiveDate")]
+    pub invoice_date: NaiveDate,
     pub invoice_location: String,
-    pub service_date: String,
+    #[serde(with = "crate::dates::lenient")]
+    #[schemars(with = "NaiveDate")]
+    pub service_date: NaiveDate, //Start of the period when service_period_end is set
+    #[serde(default, with = "crate::dates::lenient_option")]
+    #[schemars(with = "Option<NaiveDate>")]
+    pub service_period_end: Option<NaiveDate>,
     pub invoice_currency: String,
-    pub due_date: String,
+    #[serde(default, with = "crate::dates::lenient")]
+    #[schemars(with = "NaiveDate")]
+    pub due_date: NaiveDate, //Can be left out when payment terms are given
+    #[serde(default)]
+    pub payment_terms: Option<PaymentTerms>,
+    #[serde(default)]
+    pub exchange_rate: Option<ExchangeRate>, //Rate to EUR for the VAT summary of foreign currency invoices
     pub partner: Partner,
     pub company: Company,
     pub invoice_tax: f64,
@@ -98,17 +294,299 @@ pub struct Invoice {
     pub services: Vec<Service>,
     pub created_by: String,
     pub status: PaymentStatus,
+    #[serde(default, with = "crate::dates::lenient_option")]
+    #[schemars(with = "Option<NaiveDate>")]
+    pub paid_date: Option<NaiveDate>, //Unknown for invoices marked as paid before it was recorded
+    #[serde(default)]
+    pub credit_note_for: Option<i32>, //Number of the invoice a credit note (dobropis) corrects
+    #[serde(default)]
+    pub invoice_discount: Option<Discount>, //Discount on the whole document
+    #[serde(default)]
+    pub vat_treatment: VatTreatment,
+    #[serde(default)]
+    pub payment_method: PaymentMethod,
+    #[serde(default)]
+    pub fiscal: Option<FiscalData>, //ZOI and EOR of fiscally verified invoices
 }
 
 impl Racun {
-    pub fn parse_from_file() -> Self {
-        let data = read_to_string("data.json").expect("Cannot read file");
-        let parsed: Self = serde_json::from_str(&data).expect("JSON does not have correct format.");
-        parsed
+    pub fn parse_from_file() -> Result<Self, InvoiceError> {
+        let data = read_to_string("data.json")?;
+        schema::from_str(&data)
+    }
+
+    //Fills in what comes from other fields before validation: the due date from the payment
+    //terms and the exchange rate of foreign currency invoices that don't bring their own
+    pub fn prepare(&mut self) -> Result<(), InvoiceError> {
+        self.invoice.apply_payment_terms();
+        if self.invoice.invoice_currency != currency::EUR && self.invoice.exchange_rate.is_none() {
+            ExchangeRates::load()?.apply(&mut self.invoice);
+        }
+        Ok(())
+    }
+}
+
+impl Service {
+    //Price multiplied by quantity
+    pub fn amount(&self) -> f64 {
+        self.service_price * self.service_quantity
+    }
+
+    pub fn discount_amount(&self) -> f64 {
+        self.service_discount
+            .map_or(0.0, |discount| discount.amount_off(self.amount()))
+    }
+
+    //Amount after the line discount, without VAT
+    pub fn net_amount(&self) -> f64 {
+        self.amount() - self.discount_amount()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct VatLine {
+    pub rate: f64,
+    pub base: f64,
+    pub vat: f64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Totals {
+    pub lines_net: f64, //Sum of the lines after line discounts
+    pub document_discount: f64,
+    pub net: f64,
+    pub vat: f64,
+    pub gross: f64,
+    pub vat_lines: Vec<VatLine>,
+    //Invoices are paid in full or not at all
+    pub paid: f64,
+    pub outstanding: f64,
+}
+
+impl Totals {
+    //Every amount times the sign, -1 turns a credit note's totals into what it subtracts
+    pub fn signed(self, sign: f64) -> Totals {
+        Totals {
+            lines_net: self.lines_net * sign,
+            document_discount: self.document_discount * sign,
+            net: self.net * sign,
+            vat: self.vat * sign,
+            gross: self.gross * sign,
+            vat_lines: self
+                .vat_lines
+                .into_iter()
+                .map(|line| VatLine {
+                    rate: line.rate,
+                    base: line.base * sign,
+                    vat: line.vat * sign,
+                })
+                .collect(),
+            paid: self.paid * sign,
+            outstanding: self.outstanding * sign,
+        }
+    }
+}
+
+pub fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+impl Invoice {
+    //VAT rate that really applies to the line, 0% unless the invoice is domestic
+    pub fn line_rate(&self, service: &Service) -> f64 {
+        if self.vat_treatment.charges_vat() {
+            service.service_tax
+        } else {
+            0.0
+        }
+    }
+
+    //Line amount after its discount with the VAT that applies to it
+    pub fn line_gross(&self, service: &Service) -> f64 {
+        service.net_amount() * (1.0 + self.line_rate(service) / 100.0)
+    }
+
+    //Combinations of VAT treatment and invoice data that can't be issued
+    pub fn vat_issues(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        let partner_country = vat_id_country(&self.partner.partner_vat_id);
+        match &self.vat_treatment {
+            VatTreatment::ReverseCharge => {
+                if self.partner.partner_vat_id.trim().is_empty() {
+                    issues.push("Reverse charge needs the partner's VAT ID".to_string());
+                } else if partner_country == Some("SI") {
+                    issues.push(
+                        "Reverse charge needs a partner from another EU member state".to_string(),
+                    );
+                } else if partner_country.is_some_and(|c| !EU_COUNTRIES.contains(&c)) {
+                    issues.push(
+                        "Reverse charge is only for partners inside the EU, use export".to_string(),
+                    );
+                }
+            }
+            VatTreatment::Export => {
+                if partner_country.is_some_and(|c| EU_COUNTRIES.contains(&c)) {
+                    issues.push("Export is only for partners outside of the EU".to_string());
+                }
+            }
+            VatTreatment::Exempt { article } => {
+                if article.trim().is_empty() {
+                    issues.push("Exempt invoices need the article of the exemption".to_string());
+                }
+            }
+            VatTreatment::Oss { country } => {
+                if !EU_COUNTRIES.contains(&country.as_str()) || country == "SI" {
+                    issues.push(
+                        "OSS needs the code of the consumer's EU member state other than SI"
+                            .to_string(),
+                    );
+                }
+                if !self.partner.partner_vat_id.trim().is_empty() {
+                    issues.push(
+                        "OSS is for consumers, partners with a VAT ID get reverse charge"
+                            .to_string(),
+                    );
+                }
+            }
+            VatTreatment::Domestic | VatTreatment::NonVatPayer => {}
+        }
+        issues
+    }
+
+    //Net, VAT per rate and gross. The document discount lowers every rate's base by the same share
+    pub fn totals(&self) -> Totals {
+        let lines_net: f64 = self.services.iter().map(|s| s.net_amount()).sum();
+        let document_discount = self
+            .invoice_discount
+            .map_or(0.0, |discount| discount.amount_off(lines_net));
+        let share = if lines_net > 0.0 {
+            1.0 - document_discount / lines_net
+        } else {
+            1.0
+        };
+        let mut vat_lines: Vec<VatLine> = Vec::new();
+        for service in self.services.iter() {
+            let base = service.net_amount() * share;
+            let rate = self.line_rate(service);
+            match vat_lines.iter_mut().find(|l| l.rate == rate) {
+                Some(line) => line.base += base,
+                None => vat_lines.push(VatLine {
+                    rate,
+                    base,
+                    vat: 0.0,
+                }),
+            }
+        }
+        for line in vat_lines.iter_mut() {
+            line.base = round2(line.base);
+            line.vat = round2(line.base * line.rate / 100.0);
+        }
+        vat_lines.sort_by(|a, b| b.rate.total_cmp(&a.rate));
+        let net = vat_lines.iter().map(|l| l.base).sum::<f64>();
+        let vat = vat_lines.iter().map(|l| l.vat).sum::<f64>();
+        let gross = round2(net + vat);
+        let paid = match self.status {
+            PaymentStatus::PAID => gross,
+            PaymentStatus::UNPAID => 0.0,
+        };
+        Totals {
+            lines_net: round2(lines_net),
+            document_discount: round2(document_discount),
+            net: round2(net),
+            vat: round2(vat),
+            gross,
+            vat_lines,
+            paid,
+            outstanding: round2(gross - paid),
+        }
+    }
+
+    //Copies the partner into the invoice, the invoice keeps this snapshot even if the address book changes
+    pub fn set_partner(&mut self, partner: &Partner) {
+        self.partner = partner.clone();
+        if !partner.partner_currency.is_empty() {
+            let currency = currency::normalize(&partner.partner_currency)
+                .map_or(partner.partner_currency.clone(), str::to_string);
+            self.set_currency(&currency);
+        }
+        if partner.partner_payment_terms > 0 {
+            self.payment_terms = Some(PaymentTerms::net(partner.partner_payment_terms));
+            self.apply_payment_terms();
+        }
+    }
+
+    //Lines are always in the invoice currency, the old rate doesn't apply to the new currency
+    pub fn set_currency(&mut self, currency: &str) {
+        self.invoice_currency = currency.to_string();
+        for service in &mut self.services {
+            service.service_currency = currency.to_string();
+        }
+        self.exchange_rate = None;
+    }
+
+    //Totals converted with the invoice's exchange rate, None for EUR invoices and missing rates
+    pub fn totals_eur(&self) -> Option<Totals> {
+        if self.invoice_currency == currency::EUR {
+            return None;
+        }
+        let rate = self.exchange_rate.as_ref()?;
+        let totals = self.totals();
+        let eur = |amount: f64| round2(rate.to_eur(amount));
+        Some(Totals {
+            lines_net: eur(totals.lines_net),
+            document_discount: eur(totals.document_discount),
+            net: eur(totals.net),
+            vat: eur(totals.vat),
+            gross: eur(totals.gross),
+            vat_lines: totals
+                .vat_lines
+                .iter()
+                .map(|line| VatLine {
+                    rate: line.rate,
+                    base: eur(line.base),
+                    vat: eur(line.vat),
+                })
+                .collect(),
+            paid: eur(totals.paid),
+            outstanding: eur(totals.outstanding),
+        })
+    }
+
+    //Credit notes are entered with positive amounts like any invoice, reports subtract them
+    pub fn sign(&self) -> f64 {
+        if self.credit_note_for.is_some() {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+
+    pub fn mark_paid(&mut self, date: NaiveDate) {
+        self.status = PaymentStatus::PAID;
+        self.paid_date = Some(date);
+    }
+
+    //Invoices without payment terms keep the due date they were given
+    pub fn apply_payment_terms(&mut self) {
+        if let Some(terms) = self.payment_terms {
+            self.due_date = terms.due_date(self.invoice_date);
+        }
+    }
+
+    //The service date or the from - to period
+    pub fn service_period(&self) -> String {
+        match self.service_period_end {
+            Some(end) => format!(
+                "{} - {}",
+                dates::format(self.service_date),
+                dates::format(end)
+            ),
+            None => dates::format(self.service_date),
+        }
     }
 }
 //Helper functions
-fn make_line(layer: &PdfLayerReference, x1: Mm, y1: Mm, x2: Mm, y2: Mm) {
+pub fn make_line(layer: &PdfLayerReference, x1: Mm, y1: Mm, x2: Mm, y2: Mm) {
     let line_points = vec![(Point::new(x1, y1), false), (Point::new(x2, y2), false)];
     let line = Line {
         points: line_points,
@@ -128,7 +606,7 @@ pub fn render_footer(
 ) {
     let y = y - Mm(97.0);
 
-    make_line(&layer, Mm(13.0), y + Mm(2.0), Mm(197.0), y + Mm(2.0));
+    make_line(layer, Mm(13.0), y + Mm(2.0), Mm(197.0), y + Mm(2.0));
 
     let y = y - Mm(1.0);
     layer.use_text(
@@ -149,20 +627,20 @@ pub fn render_payment_footer(
     standard_font: &IndirectFontRef,
     y: Mm,
 ) -> Mm {
-    let current_date = chrono::Utc::now().year();
+    let invoice_year = racun.invoice.invoice_date.year();
     let mut y = y - Mm(10.0);
     let base_x = Mm(15.0);
     layer.use_text(
         format!(
             "Sklic za številko: {}00 {:04}-{}",
-            racun.invoice.invoice_reference, racun.invoice.invoice_number, current_date
+            racun.invoice.invoice_reference, racun.invoice.invoice_number, invoice_year
         ),
         9.0,
         base_x,
         y,
         standard_font,
     );
-    y = y - Mm(3.0);
+    y -= Mm(3.0);
     layer.use_text(
         format!("Sestavil: {}", racun.invoice.created_by),
         9.0,
@@ -170,7 +648,7 @@ pub fn render_payment_footer(
         y,
         standard_font,
     );
-    y = y - Mm(4.0);
+    y -= Mm(4.0);
 
     //Payment info /method
     layer.use_text(
@@ -189,18 +667,81 @@ pub fn render_payment_footer(
     y
 }
 
+//ZOI, EOR and the fiscal QR code for fiscally verified invoices
+pub fn render_fiscal(
+    layer: &PdfLayerReference,
+    racun: &Racun,
+    standard_font: &IndirectFontRef,
+    y: Mm,
+) -> Mm {
+    let fiscal = match &racun.invoice.fiscal {
+        Some(fiscal) => fiscal,
+        None => return y,
+    };
+    let base_x = Mm(15.0);
+    let y = y - Mm(5.0);
+    layer.use_text(
+        format!("Način plačila: {}", racun.invoice.payment_method),
+        9.0,
+        base_x,
+        y,
+        standard_font,
+    );
+    let y = y - Mm(4.0);
+    layer.use_text(
+        format!("ZOI: {}", fiscal.zoi),
+        9.0,
+        base_x,
+        y,
+        standard_font,
+    );
+    let y = y - Mm(4.0);
+    layer.use_text(
+        format!("EOR: {}", fiscal.eor.as_deref().unwrap_or("")),
+        9.0,
+        base_x,
+        y,
+        standard_font,
+    );
+    //The QR code sits on the right side next to the fiscal data
+    if let Ok(code) =
+        qrcode::QrCode::with_error_correction_level(fiscal.qr_payload(), qrcode::EcLevel::M)
+    {
+        let module = 0.7;
+        let width = code.width();
+        let top = y.0 + 8.0;
+        for (index, color) in code.to_colors().iter().enumerate() {
+            if *color == qrcode::Color::Dark {
+                let x = 172.0 + (index % width) as f64 * module;
+                let y = top - (index / width) as f64 * module;
+                layer.add_shape(Line {
+                    points: vec![
+                        (Point::new(Mm(x), Mm(y)), false),
+                        (Point::new(Mm(x + module), Mm(y)), false),
+                        (Point::new(Mm(x + module), Mm(y - module)), false),
+                        (Point::new(Mm(x), Mm(y - module)), false),
+                    ],
+                    is_closed: true,
+                    has_fill: true,
+                    has_stroke: false,
+                    is_clipping_path: false,
+                });
+            }
+        }
+    }
+    y
+}
+
 pub fn render_summary_table(
     layer: &PdfLayerReference,
     racun: &Racun,
     standard_font: &IndirectFontRef,
     bold_font: &IndirectFontRef,
     y: Mm,
-    total_price: f64,
-    calculated_tax_difference: f64,
-    total_price_with_tax: f64,
+    totals: &Totals,
 ) -> Mm {
     let y = y - Mm(15.0);
-    make_line(&layer, Mm(13.0), y, Mm(197.0), y);
+    make_line(layer, Mm(13.0), y, Mm(197.0), y);
 
     //Adding text "Davčna stopnja", "Osnova za DDV", "DDV", "Znesek z DDV"
     let mut y = y - Mm(3.0);
@@ -215,43 +756,108 @@ pub fn render_summary_table(
     layer.use_text("DDV", 9.0, tax_difference_x, y, bold_font);
 
     layer.use_text("Znesek z DDV", 9.0, total_price_x, y, bold_font);
-    y = y - Mm(4.0);
-    layer.use_text(
-        format!("DDV {}%", racun.invoice.invoice_tax),
-        9.0,
-        tax_x,
-        y,
-        standard_font,
-    );
-    layer.use_text(
-        format!("{:.2}{}", total_price, racun.invoice.invoice_currency),
-        9.0,
-        base_tax_x,
-        y,
-        standard_font,
-    );
+    //One row for every VAT rate on the invoice
+    for vat_line in totals.vat_lines.iter() {
+        y -= Mm(4.0);
+        layer.use_text(
+            format!("DDV {}%", vat_line.rate),
+            9.0,
+            tax_x,
+            y,
+            standard_font,
+        );
+        layer.use_text(
+            format!("{:.2}{}", vat_line.base, currency::symbol(&racun.invoice.invoice_currency)),
+            9.0,
+            base_tax_x,
+            y,
+            standard_font,
+        );
+        layer.use_text(
+            format!(
+                "{:.2}{}",
+                vat_line.base + vat_line.vat,
+                currency::symbol(&racun.invoice.invoice_currency)
+            ),
+            9.0,
+            total_price_x,
+            y,
+            standard_font,
+        );
+        layer.use_text(
+            format!("{:.2}{}", vat_line.vat, currency::symbol(&racun.invoice.invoice_currency)),
+            9.0,
+            tax_difference_x,
+            y,
+            standard_font,
+        );
+    }
 
+    y
+}
+
+//Legal note for invoices without domestic VAT
+pub fn render_vat_note(
+    layer: &PdfLayerReference,
+    racun: &Racun,
+    standard_font: &IndirectFontRef,
+    y: Mm,
+) -> Mm {
+    match racun.invoice.vat_treatment.legal_note() {
+        Some(note) => {
+            let y = y - Mm(8.0);
+            layer.use_text(note, 9.0, Mm(15.0), y, standard_font);
+            y
+        }
+        None => y,
+    }
+}
+
+//Foreign currency invoices show the VAT summary in EUR with the rate that was used
+pub fn render_eur_summary(
+    layer: &PdfLayerReference,
+    racun: &Racun,
+    standard_font: &IndirectFontRef,
+    y: Mm,
+) -> Mm {
+    let (Some(rate), Some(totals)) = (&racun.invoice.exchange_rate, racun.invoice.totals_eur())
+    else {
+        return y;
+    };
+    let mut y = y - Mm(8.0);
     layer.use_text(
         format!(
-            "{:.2?}{}",
-            total_price_with_tax, racun.invoice.invoice_currency
+            "Preračun v EUR po referenčnem tečaju ECB z dne {}: 1 EUR = {} {}",
+            dates::format(rate.date),
+            rate.rate,
+            rate.currency
         ),
         9.0,
-        total_price_x,
+        Mm(15.0),
         y,
         standard_font,
     );
+    for vat_line in &totals.vat_lines {
+        y -= Mm(4.0);
+        layer.use_text(
+            format!(
+                "DDV {}%: osnova {:.2}€, DDV {:.2}€",
+                vat_line.rate, vat_line.base, vat_line.vat
+            ),
+            9.0,
+            Mm(15.0),
+            y,
+            standard_font,
+        );
+    }
+    y -= Mm(4.0);
     layer.use_text(
-        format!(
-            "{:.2}{}",
-            calculated_tax_difference, racun.invoice.invoice_currency
-        ),
+        format!("Skupaj z DDV: {:.2}€", totals.gross),
         9.0,
-        tax_difference_x,
+        Mm(15.0),
         y,
         standard_font,
     );
-
     y
 }
 
@@ -260,18 +866,20 @@ pub fn render_service(
     mut y: Mm,
     layer: &PdfLayerReference,
     font: &IndirectFontRef,
+    racun: &Racun,
     service: &Service,
 ) -> (Mm, Mm) {
-    //Converting it to float and getting total price of services multiplied by quantity
-    let service_by_quantity_price = service.service_price * (service.service_quantity as f64);
-    //Adding a vat percentage price to the service price
-    let new_value = add_percent(service_by_quantity_price, 0.22);
+    let vat_rate = racun.invoice.line_rate(service);
+    //Price multiplied by quantity, before the discount
+    let service_by_quantity_price = service.amount();
+    //Adding a vat percentage price to the discounted service price
+    let new_value = racun.invoice.line_gross(service);
     //Render service with a price and ddv percentage
     //Always a constant
-    let service_x = Mm(180.0);
+    let service_x = Mm(177.0);
     //Rendering price that has to be paid included with tax
     layer.use_text(
-        format!("{:.2}{}", new_value, service.service_currency),
+        format!("{:.2}{}", new_value, currency::symbol(&service.service_currency)),
         9.0,
         service_x,
         y,
@@ -279,32 +887,38 @@ pub fn render_service(
     );
     //Render service DDV percentage
     //Always a constant
-    let ddv_x = Mm(165.0);
-    //Formated text add a percentage sign to the service_tax string
-    let formated_vat = format!("{}%", service.service_tax);
-
-    layer.use_text(formated_vat, 9.0, ddv_x, y, font);
+    let ddv_x = Mm(162.0);
+    //Formated text add a percentage sign to the rate, non VAT payers have no DDV at all
+    if racun.invoice.vat_treatment != VatTreatment::NonVatPayer {
+        let formated_vat = format!("{}%", vat_rate);
+        layer.use_text(formated_vat, 9.0, ddv_x, y, font);
+    }
+    //Render service discount, the column stays empty without one
+    //Always a constant
+    let discount_x = Mm(142.0);
+    if let Some(discount) = &service.service_discount {
+        layer.use_text(
+            discount.label(currency::symbol(&service.service_currency)),
+            9.0,
+            discount_x,
+            y,
+            font,
+        );
+    }
     //Render service price
     //Always a constant
-    let price_x = Mm(145.0);
-    //Convert a float to an int
+    let price_x = Mm(122.0);
 
     let formated_price = format!(
         "{:.2}{}",
-        service_by_quantity_price, service.service_currency
+        service_by_quantity_price, currency::symbol(&service.service_currency)
     );
     layer.use_text(formated_price, 9.0, price_x, y, font);
 
     //Render service quantity
     //Always a constant
-    let quantity_x = Mm(125.0);
-    layer.use_text(
-        &service.service_quantity.to_string(),
-        9.0,
-        quantity_x,
-        y,
-        font,
-    );
+    let quantity_x = Mm(102.0);
+    layer.use_text(format_quantity(service), 9.0, quantity_x, y, font);
     for line in service.service_name.lines() {
         layer.use_text(line, 9.0, x, y, font);
         y -= Mm(4.0);
@@ -318,20 +932,19 @@ pub fn render_table_contents(
     layer: &PdfLayerReference,
     racun: &Racun,
     standard_font: &IndirectFontRef,
-) -> (Mm, f64, f64, f64) {
+) -> (Mm, Totals) {
     let mut x = Mm(15.0);
     let mut y = Mm(185.0);
-    let mut total_price = 0.0;
     //Render services with the lines above
     for service in racun.invoice.services.iter() {
-        total_price += service.service_price * (service.service_quantity as f64);
-        let (new_x, new_y) = render_service(x, y, layer, standard_font, service);
+        let (new_x, new_y) = render_service(x, y, layer, standard_font, racun, service);
         x = new_x;
         y = new_y;
     }
-    let final_table_y = render_table_end(y, layer, racun, standard_font, total_price);
-    //Render Total price , Tax price and Total price with tax
-    final_table_y //Updated y and prices to put them into the summary table
+    let totals = racun.invoice.totals();
+    let final_table_y = render_table_end(y, layer, racun, standard_font, &totals);
+    //Updated y and totals to put them into the summary table
+    (final_table_y, totals)
 }
 
 pub fn render_table_end(
@@ -339,15 +952,15 @@ pub fn render_table_end(
     layer: &PdfLayerReference,
     racun: &Racun,
     font: &IndirectFontRef,
-    total_price: f64,
-) -> (Mm, f64, f64, f64) {
+    totals: &Totals,
+) -> Mm {
     //Constant location of the Field
     let x = Mm(165.0);
     //Render total price without tax
     layer.use_text(
         format!(
             "Skupaj: {:.2}{}",
-            total_price, racun.invoice.invoice_currency
+            totals.lines_net, currency::symbol(&racun.invoice.invoice_currency)
         ),
         9.0,
         x,
@@ -356,28 +969,51 @@ pub fn render_table_end(
     );
 
     make_line(layer, Mm(165.0), y - Mm(1.0), Mm(195.0), y - Mm(1.0));
+    let mut y = y;
+    //Document discount and the base after it
+    if let Some(discount) = &racun.invoice.invoice_discount {
+        y -= Mm(4.0);
+        layer.use_text(
+            format!(
+                "Popust: {}",
+                discount.label(currency::symbol(&racun.invoice.invoice_currency))
+            ),
+            9.0,
+            x,
+            y,
+            font,
+        );
+        make_line(layer, Mm(165.0), y - Mm(1.0), Mm(195.0), y - Mm(1.0));
+        y -= Mm(4.0);
+        layer.use_text(
+            format!(
+                "Osnova: {:.2}{}",
+                totals.net, currency::symbol(&racun.invoice.invoice_currency)
+            ),
+            9.0,
+            x,
+            y,
+            font,
+        );
+        make_line(layer, Mm(165.0), y - Mm(1.0), Mm(195.0), y - Mm(1.0));
+    }
     ///////////////////////////////////////////
-    //Decrease the Y by a couple of Mm
-    let y = y - Mm(4.0);
-    //Render tax
-    //Always a constant
-    let tax_x = Mm(165.0);
-    //Calculate how much is the tax difference
-
-    let total_price_with_tax = total_price * (1.00 + (racun.invoice.invoice_tax / 100.00));
-    let calculated_tax_difference = total_price_with_tax - total_price;
-    layer.use_text(
-        format!(
-            "DDV: {:.2}{}",
-            calculated_tax_difference, racun.invoice.invoice_currency
-        ),
-        9.0,
-        tax_x,
-        y,
-        font,
-    );
+    if racun.invoice.vat_treatment != VatTreatment::NonVatPayer {
+        //Decrease the Y by a couple of Mm
+        y -= Mm(4.0);
+        //Render tax
+        //Always a constant
+        let tax_x = Mm(165.0);
+        layer.use_text(
+            format!("DDV: {:.2}{}", totals.vat, currency::symbol(&racun.invoice.invoice_currency)),
+            9.0,
+            tax_x,
+            y,
+            font,
+        );
 
-    make_line(layer, Mm(165.0), y - Mm(1.0), Mm(195.0), y - Mm(1.0));
+        make_line(layer, Mm(165.0), y - Mm(1.0), Mm(195.0), y - Mm(1.0));
+    }
 
     //To pay field
     let y = y - Mm(4.0);
@@ -385,7 +1021,7 @@ pub fn render_table_end(
     layer.use_text(
         format!(
             "Za plačilo: {:.2}{}",
-            total_price_with_tax, racun.invoice.invoice_currency
+            totals.gross, currency::symbol(&racun.invoice.invoice_currency)
         ),
         9.0,
         to_pay_x,
@@ -395,35 +1031,36 @@ pub fn render_table_end(
     //Decrease the Y by a couple of Mm
     let y = y - Mm(1.0);
     make_line(layer, Mm(165.0), y, Mm(195.0), y);
-    return (
-        y,
-        total_price,
-        calculated_tax_difference,
-        total_price_with_tax,
-    );
+    y
 }
 
 pub fn render_table_header(layer: &PdfLayerReference, racun: &Racun, bold: &IndirectFontRef) {
     //Opis
     let y = Mm(193.0);
     let mut x = Mm(15.0);
-    layer.use_text("Opis", racun.config.font_sizes.small, x, y, &bold);
+    layer.use_text("Opis", racun.config.font_sizes.small, x, y, bold);
 
     //Količina
-    x += Mm(105.0);
-    layer.use_text("Količina", racun.config.font_sizes.small, x, y, &bold);
+    x += Mm(87.0);
+    layer.use_text("Količina", racun.config.font_sizes.small, x, y, bold);
 
     //Cena
-    x += Mm(22.0);
-    layer.use_text("Cena", racun.config.font_sizes.small, x, y, &bold);
+    x += Mm(20.0);
+    layer.use_text("Cena", racun.config.font_sizes.small, x, y, bold);
+
+    //Popust
+    x += Mm(20.0);
+    layer.use_text("Popust", racun.config.font_sizes.small, x, y, bold);
 
     //DDV
-    x += Mm(22.0);
-    layer.use_text("DDV", racun.config.font_sizes.small, x, y, &bold);
+    x += Mm(20.0);
+    if racun.invoice.vat_treatment != VatTreatment::NonVatPayer {
+        layer.use_text("DDV", racun.config.font_sizes.small, x, y, bold);
+    }
 
     //Znesek
     x += Mm(15.0);
-    layer.use_text("Znesek", racun.config.font_sizes.small, x, y, &bold);
+    layer.use_text("Znesek", racun.config.font_sizes.small, x, y, bold);
 
     make_line(layer, Mm(13.0), Mm(190.0), Mm(197.0), Mm(190.0));
 }
@@ -435,39 +1072,39 @@ pub fn render_partner_header(
 ) {
     //Partner name
     layer.use_text(
-        format!("{}", racun.invoice.company.company_name),
+        racun.invoice.company.company_name.to_string(),
         racun.config.font_sizes.small,
         Mm(15.0),
         Mm(233.0),
-        &standard_font,
+        standard_font,
     );
     //Partner address
     layer.use_text(
-        format!("{}", racun.invoice.partner.partner_address),
+        racun.invoice.partner.partner_address.to_string(),
         racun.config.font_sizes.small,
         Mm(15.0),
         Mm(228.0),
-        &standard_font,
+        standard_font,
     );
     //Partner postal code with city
     layer.use_text(
-        format!("{}", racun.invoice.partner.partner_postal_code),
+        racun.invoice.partner.partner_postal_code.to_string(),
         racun.config.font_sizes.small,
         Mm(15.0),
         Mm(223.0),
-        &standard_font,
+        standard_font,
     );
 
     //Partner tax number
     layer.use_text(
         format!(
-            "ID za DDV kupca: SI {}",
-            racun.invoice.partner.partner_vat_id
+            "ID za DDV kupca: {}",
+            display_vat_id(&racun.invoice.partner.partner_vat_id)
         ),
         racun.config.font_sizes.small,
         Mm(15.0),
         Mm(202.0),
-        &standard_font,
+        standard_font,
     );
 }
 
@@ -479,36 +1116,44 @@ pub fn render_company_header(
 ) {
     //Company name
     layer.use_text(
-        format!("{}", racun.invoice.company.company_name),
+        racun.invoice.company.company_name.to_string(),
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(276.0),
-        &bold_font,
+        bold_font,
     );
     //Company address
     layer.use_text(
-        format!("{}", racun.invoice.company.company_address),
+        racun.invoice.company.company_address.to_string(),
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(271.0),
-        &standard_font,
+        standard_font,
     );
     //Company postal code with address
     layer.use_text(
-        format!("{}", racun.invoice.company.company_postal_code),
+        racun.invoice.company.company_postal_code.to_string(),
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(267.0),
-        &standard_font,
+        standard_font,
     );
 
-    //Company tax number
+    //Company tax number, companies outside of the VAT system only have a tax number
+    let tax_number = if racun.invoice.vat_treatment == VatTreatment::NonVatPayer {
+        format!("Davčna št: {}", racun.invoice.company.company_vat_id)
+    } else {
+        format!(
+            "ID za DDV: {}",
+            display_vat_id(&racun.invoice.company.company_vat_id)
+        )
+    };
     layer.use_text(
-        format!("ID za DDV: SI{}", racun.invoice.company.company_vat_id),
+        tax_number,
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(263.0),
-        &standard_font,
+        standard_font,
     );
 
     //Company bank account
@@ -517,7 +1162,7 @@ pub fn render_company_header(
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(259.0),
-        &standard_font,
+        standard_font,
     );
     //Company swift
     layer.use_text(
@@ -525,7 +1170,7 @@ pub fn render_company_header(
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(255.0),
-        &standard_font,
+        standard_font,
     );
     //Company registration number
     layer.use_text(
@@ -536,7 +1181,7 @@ pub fn render_company_header(
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(251.0),
-        &standard_font,
+        standard_font,
     );
     //Company phone
     layer.use_text(
@@ -544,21 +1189,55 @@ pub fn render_company_header(
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(247.0),
-        &standard_font,
+        standard_font,
     );
-    //Invoice number
+    //Invoice number, credit notes also name the invoice they correct
+    let title = match racun.invoice.credit_note_for {
+        Some(original) => format!(
+            "Dobropis št: {} k računu {}",
+            racun.invoice.invoice_number, original
+        ),
+        None => format!("Račun št: {}", racun.invoice.invoice_number),
+    };
     layer.use_text(
-        format!("Račun št: {}", racun.invoice.invoice_number),
+        title,
         racun.config.font_sizes.small,
         Mm(132.0),
         Mm(243.0),
-        &standard_font,
+        standard_font,
     );
 }
 
-fn add_percent(original_value: f64, percent: f64) -> f64 {
-    let percent_value = original_value * percent;
-    original_value + percent_value
+pub fn render_logo(layer: &PdfLayerReference, racun: &Racun) -> Result<(), InvoiceError> {
+    if racun.invoice.company.company_logo.is_empty() {
+        return Ok(());
+    }
+    let logo = ::image::open(&racun.invoice.company.company_logo)?;
+    //Pdf doesn't like the alpha channel
+    let logo = ::image::DynamicImage::ImageRgb8(logo.to_rgb8());
+    //Pick the dpi so the logo is always 15mm high
+    let dpi = logo.height() as f64 * 25.4 / 15.0;
+    Image::from_dynamic_image(&logo).add_to_layer(
+        layer.clone(),
+        ImageTransform {
+            translate_x: Some(Mm(15.0)),
+            translate_y: Some(Mm(279.0)),
+            dpi: Some(dpi),
+            ..Default::default()
+        },
+    );
+    Ok(())
+}
+
+//Quantity without useless decimals followed by the unit, 1.5 h or 2 kos
+pub fn format_quantity(service: &Service) -> String {
+    let quantity = format!("{:.3}", service.service_quantity);
+    let quantity = quantity.trim_end_matches('0').trim_end_matches('.');
+    if service.service_unit.is_empty() {
+        quantity.to_string()
+    } else {
+        format!("{} {}", quantity, service.service_unit)
+    }
 }
 
 pub fn render_invoice_header(
@@ -570,31 +1249,51 @@ pub fn render_invoice_header(
     layer.use_text(
         format!(
             "Datum izdaje: {}, {}",
-            racun.invoice.invoice_location, racun.invoice.invoice_date
+            racun.invoice.invoice_location,
+            dates::format(racun.invoice.invoice_date)
         ),
         racun.config.font_sizes.small,
         Mm(15.0),
         Mm(274.0),
-        &standard_font,
+        standard_font,
     );
-    //Datum opravljene storitve
+    //Datum opravljene storitve, or the period for monthly services
+    let service_label = match racun.invoice.service_period_end {
+        Some(_) => "Obdobje opr. storitve",
+        None => "Datum opr. storitve",
+    };
     layer.use_text(
-        format!("Datum opr. storitve: {}", racun.invoice.service_date),
+        format!("{}: {}", service_label, racun.invoice.service_period()),
         racun.config.font_sizes.small,
         Mm(15.0),
         Mm(270.0),
-        &standard_font,
+        standard_font,
     );
     //Rok plačila
     layer.use_text(
-        format!("Rok plačila: {}", racun.invoice.due_date),
+        format!("Rok plačila: {}", dates::format(racun.invoice.due_date)),
         racun.config.font_sizes.small,
         Mm(15.0),
         Mm(266.0),
-        &standard_font,
+        standard_font,
     );
 }
-pub fn init(racun: Racun) -> Result<(), Box<dyn Error>> {
+pub fn load_font(doc: &PdfDocumentReference, path: &str) -> Result<IndirectFontRef, InvoiceError> {
+    let file = File::open(path).map_err(|err| InvoiceError::Font(format!("{}: {}", path, err)))?;
+    doc.add_external_font(file)
+        .map_err(|err| InvoiceError::Font(format!("{}: {}", path, err)))
+}
+
+//Generates and stores the invoice. The returned warnings are about parts that failed
+//without stopping it, like an invoice queued while FURS is offline.
+pub fn init(mut racun: Racun) -> Result<Vec<String>, InvoiceError> {
+    racun.prepare()?;
+    validation::validate(&racun).check()?;
+    //Checked before fiscalization so a duplicate never reaches FURS
+    if storage::exists(racun.invoice.invoice_number) {
+        return Err(InvoiceError::DuplicateNumber(racun.invoice.invoice_number));
+    }
+    let mut warnings = Vec::new();
     let (doc, page1, layer1) = PdfDocument::new(
         racun.invoice.invoice_number.to_string(),
         Mm(210.0), //Page size A4
@@ -602,111 +1301,375 @@ pub fn init(racun: Racun) -> Result<(), Box<dyn Error>> {
         "Layer 1",
     );
     //Font entry
-    let bold_font = doc
-        .add_external_font(File::open("fonts/DejaVuSans-Bold.ttf").expect("Could't open font file"))
-        .unwrap();
-    let standard_font = doc
-        .add_external_font(File::open("fonts/DejaVuSans.ttf").expect("Could't open font file"))
-        .unwrap();
+    let bold_font = load_font(&doc, "fonts/DejaVuSans-Bold.ttf")?;
+    let standard_font = load_font(&doc, "fonts/DejaVuSans.ttf")?;
+    //Cash and card invoices need the ZOI and EOR before they are printed. Everything that
+    //can fail before the invoice is saved is done by now, so FURS never sees an invoice
+    //that isn't stored.
+    warnings.extend(fiscalize(&mut racun)?);
     let current_layer = doc.get_page(page1).get_layer(layer1);
+    //Images can't be inside of the text section
+    if let Err(e) = render_logo(&current_layer, &racun) {
+        warnings.push(format!("The logo couldn't be added: {}", e));
+    }
     //Start of text
     current_layer.begin_text_section();
     render_invoice_header(&current_layer, &racun, &standard_font);
     render_company_header(&current_layer, &racun, &standard_font, &bold_font);
     render_partner_header(&current_layer, &racun, &standard_font);
     render_table_header(&current_layer, &racun, &bold_font);
-    let (y, total_price, calculated_tax_difference, total_price_with_tax) =
-        render_table_contents(&current_layer, &racun, &standard_font);
-    let y = render_summary_table(
-        &current_layer,
-        &racun,
-        &standard_font,
-        &bold_font,
-        y,
-        total_price,
-        calculated_tax_difference,
-        total_price_with_tax,
-    );
+    let (y, totals) = render_table_contents(&current_layer, &racun, &standard_font);
+    //Non VAT payers don't show any DDV rows
+    let y = if racun.invoice.vat_treatment == VatTreatment::NonVatPayer {
+        y
+    } else {
+        render_summary_table(
+            &current_layer,
+            &racun,
+            &standard_font,
+            &bold_font,
+            y,
+            &totals,
+        )
+    };
+    let y = render_vat_note(&current_layer, &racun, &standard_font, y);
+    let y = render_eur_summary(&current_layer, &racun, &standard_font, y);
 
     //Make payment footer
     let y = render_payment_footer(&current_layer, &racun, &standard_font, y);
+    let y = render_fiscal(&current_layer, &racun, &standard_font, y);
     render_footer(&current_layer, &racun, &standard_font, y);
     //Save pdf entry and return the path to the pdf file
-    let path = save_invoice(doc, &racun);
-
-    match path {
-        Some((a, jpg_file_path)) => {
-            match export_pdf_to_jpegs(
-                a.to_str().expect("Coulnd't convert to &str"),
-                jpg_file_path.to_str().expect("Coulnd't convert to &str"),
-                None,
-                racun.invoice.invoice_number,
-            ) {
-                Ok(_) => {
-                    println!("Invoice image saved ✔");
-                    println!("Invoice saved {}", jpg_file_path.to_str().unwrap());
-                    save_to_json(&racun);
-                    println!("Invoice json saved ✔");
-                }
-                Err(e) => println!("Error saving invoice image: {}", e),
+    let (pdf_path, invoice_dir) = save_invoice(doc, &racun)?;
+    //Save the json data to output.json
+    storage::save(&racun)?;
+    //Only a stored invoice is queued, resubmitting it must not leave an output.json without a pdf
+    if FiscalQueue::pending(&racun) {
+        FiscalQueue::push(&racun)?;
+    }
+    //A missing preview image isn't fatal, the gui can render it again later
+    if let Err(e) = export_pdf_to_jpegs(
+        &pdf_path.to_string_lossy(),
+        &invoice_dir.to_string_lossy(),
+        None,
+        racun.invoice.invoice_number,
+    ) {
+        warnings.push(format!("The preview image couldn't be saved: {}", e));
+    }
+    Ok(warnings)
+}
+
+pub fn save_invoice(
+    doc: PdfDocumentReference,
+    racun: &Racun,
+) -> Result<(PathBuf, PathBuf), InvoiceError> {
+    //Firstly make a new directory in the invoice directory and the name is the invoice number
+    //Then save the invoice in that directory
+    let invoice_number_dir = storage::invoice_dir(racun.invoice.invoice_number);
+    fs::create_dir_all(storage::invoices_dir())?;
+    if let Err(e) = fs::create_dir(&invoice_number_dir) {
+        return Err(match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                InvoiceError::DuplicateNumber(racun.invoice.invoice_number)
             }
-        }
-        None => {
-            println!("Error saving invoice");
-            //Return an option of dyn error
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Error saving invoice",
-            )));
-        }
+            _ => InvoiceError::Io(e),
+        });
     }
-    //Save the json data to output.json
-    save_to_json(&racun);
-    Ok(())
+    let pdf_path = storage::pdf_path(racun.invoice.invoice_number);
+    doc.save(&mut BufWriter::new(File::create(&pdf_path)?))?;
+    Ok((pdf_path, invoice_number_dir))
 }
 
-pub fn save_to_json(racun: &Racun) {
-    let cwd = env::current_dir().expect("Couldn't get current directory");
-    let invoice_dir = cwd.join("invoices");
-    let invoice_number_dir = invoice_dir.join(racun.invoice.invoice_number.to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{furs, invoice, sample, Workspace};
 
-    if !invoice_number_dir.exists() {
-        fs::create_dir(&invoice_number_dir).expect("Couldn't create invoice directory");
+    //Cash invoices fail before FURS is asked when the certificate is missing
+    fn broken_fiscal_config(workspace: &Workspace) {
+        workspace.write(
+            "furs.json",
+            r#"{"endpoint": "http://127.0.0.1:9/v1/cash_registers", "certificatePath": "missing.p12",
+                "certificatePassword": "", "taxNumber": 12345678, "businessPremiseId": "PP1",
+                "electronicDeviceId": "B1"}"#,
+        );
     }
-    let invoice_json = invoice_number_dir.join("output.json");
-    if invoice_number_dir.exists() {
-        //Convert the struct into json string
-        let json = serde_json::to_string(&racun).expect("Couldn't convert to json");
-        //Write the json string to the file
-        let mut file = File::create(invoice_json).unwrap();
-        file.write_all(json.as_bytes()).unwrap();
-        println!("✔");
-    } else {
-        panic!("The invoice number directory doesn't exist");
+
+    #[test]
+    fn discounts_never_take_off_more_than_the_amount() {
+        assert_eq!(Discount::Percent(10.0).amount_off(99.99), 9.999);
+        assert_eq!(Discount::Amount(5.0).amount_off(50.0), 5.0);
+        assert_eq!(Discount::Amount(15.0).amount_off(10.0), 10.0);
+        assert_eq!(Discount::Percent(-5.0).amount_off(10.0), 0.0);
+        assert_eq!(Discount::Amount(5.0).amount_off(-10.0), 0.0);
     }
-}
 
-pub fn save_invoice(doc: PdfDocumentReference, racun: &Racun) -> Option<(PathBuf, PathBuf)> {
-    //Firstly make a new directory in the invoice directory and the name is the invoice number
-    //Then save the invoice in that directory
-    let cwd = env::current_dir().expect("Couldn't get current directory");
-    let invoice_dir = cwd.join("invoices");
-    let invoice_number_dir = invoice_dir.join(racun.invoice.invoice_number.to_string());
+    #[test]
+    fn totals_apply_line_and_document_discounts_per_rate() {
+        let mut racun = invoice(
+            1,
+            &[
+                ("", 3.0, 33.33, 22.0),
+                ("", 1.0, 50.0, 9.5),
+                ("", 2.0, 12.5, 22.0),
+                ("", 1.0, 10.0, 5.0),
+            ],
+        );
+        let invoice = &mut racun.invoice;
+        invoice.services[0].service_discount = Some(Discount::Percent(10.0));
+        invoice.services[1].service_discount = Some(Discount::Amount(5.0));
+        //Capped at the line amount
+        invoice.services[3].service_discount = Some(Discount::Amount(15.0));
+        invoice.invoice_discount = Some(Discount::Percent(5.0));
+        let totals = invoice.totals();
+        assert_eq!(totals.lines_net, 159.99);
+        assert_eq!(totals.document_discount, 8.0);
+        //Every base is 95 % of its lines
+        assert_eq!(
+            totals.vat_lines,
+            vec![
+                VatLine {
+                    rate: 22.0,
+                    base: 109.24,
+                    vat: 24.03
+                },
+                VatLine {
+                    rate: 9.5,
+                    base: 42.75,
+                    vat: 4.06
+                },
+                VatLine {
+                    rate: 5.0,
+                    base: 0.0,
+                    vat: 0.0
+                },
+            ]
+        );
+        assert_eq!(totals.net, 151.99);
+        assert_eq!(totals.vat, 28.09);
+        assert_eq!(totals.gross, 180.08);
+        assert_eq!(totals.paid, 0.0);
+        assert_eq!(totals.outstanding, 180.08);
+    }
 
-    if !invoice_dir.exists() {
-        // println!("Creating invoice directory");
-        fs::create_dir(invoice_dir).expect("❌");
-        print!("Created invoice directory ✔");
+    #[test]
+    fn a_document_discount_amount_lowers_every_rate_by_the_same_share() {
+        let mut racun = invoice(1, &[("", 1.0, 100.0, 22.0), ("", 1.0, 100.0, 9.5)]);
+        racun.invoice.invoice_discount = Some(Discount::Amount(20.0));
+        racun.invoice.mark_paid(racun.invoice.invoice_date);
+        let totals = racun.invoice.totals();
+        assert_eq!(totals.lines_net, 200.0);
+        assert_eq!(totals.document_discount, 20.0);
+        let bases: Vec<(f64, f64, f64)> = totals
+            .vat_lines
+            .iter()
+            .map(|line| (line.rate, line.base, line.vat))
+            .collect();
+        assert_eq!(bases, vec![(22.0, 90.0, 19.8), (9.5, 90.0, 8.55)]);
+        assert_eq!(totals.gross, 208.35);
+        assert_eq!(totals.paid, 208.35);
+        assert_eq!(totals.outstanding, 0.0);
     }
-    if invoice_number_dir.exists() {
-        None
-    } else {
-        fs::create_dir(&invoice_number_dir).expect("The invoice number directory already exists");
-        let pdf_path =
-            invoice_number_dir.join(format!("racun {}.pdf", racun.invoice.invoice_number));
-        doc.save(&mut BufWriter::new(File::create(&pdf_path).unwrap()))
-            .expect("Couldn't save pdf file");
-        println!("✔");
-        Some((pdf_path, invoice_number_dir))
+
+    //Treatment and partner VAT ID of an invoice with lines at 22 % and 9.5 %
+    fn treated(treatment: VatTreatment, vat_id: &str) -> Invoice {
+        let mut racun = invoice(1, &[("", 1.0, 100.0, 22.0), ("", 1.0, 50.0, 9.5)]);
+        racun.invoice.vat_treatment = treatment;
+        racun.invoice.partner.partner_vat_id = vat_id.to_string();
+        racun.invoice
+    }
+
+    #[test]
+    fn reverse_charge_needs_a_partner_from_another_member_state() {
+        let issues = |vat_id| treated(VatTreatment::ReverseCharge, vat_id).vat_issues();
+        assert_eq!(
+            issues(" "),
+            vec!["Reverse charge needs the partner's VAT ID".to_string()]
+        );
+        assert_eq!(
+            issues("SI12345679"),
+            vec!["Reverse charge needs a partner from another EU member state".to_string()]
+        );
+        assert_eq!(
+            issues("US123456"),
+            vec!["Reverse charge is only for partners inside the EU, use export".to_string()]
+        );
+        assert!(issues("DE123456789").is_empty());
+    }
+
+    #[test]
+    fn export_and_exempt_lines_are_not_charged_vat() {
+        let export = treated(VatTreatment::Export, "US123456");
+        assert!(export.vat_issues().is_empty());
+        assert_eq!(
+            treated(VatTreatment::Export, "DE123456789").vat_issues(),
+            vec!["Export is only for partners outside of the EU".to_string()]
+        );
+        let exempt = treated(
+            VatTreatment::Exempt {
+                article: "42. členom ZDDV-1".to_string(),
+            },
+            "",
+        );
+        assert!(exempt.vat_issues().is_empty());
+        assert_eq!(
+            treated(
+                VatTreatment::Exempt {
+                    article: " ".to_string()
+                },
+                ""
+            )
+            .vat_issues(),
+            vec!["Exempt invoices need the article of the exemption".to_string()]
+        );
+        for invoice in [&export, &exempt] {
+            assert!(!invoice.vat_treatment.charges_vat());
+            //The 22 % and 9.5 % lines end up in one line without VAT
+            let totals = invoice.totals();
+            assert_eq!(
+                totals.vat_lines,
+                vec![VatLine {
+                    rate: 0.0,
+                    base: 150.0,
+                    vat: 0.0
+                }]
+            );
+            assert_eq!(totals.gross, 150.0);
+            assert_eq!(invoice.line_gross(&invoice.services[0]), 100.0);
+        }
+        assert!(export.vat_treatment.legal_note().unwrap().contains("izvoz"));
+        assert_eq!(
+            exempt.vat_treatment.legal_note().unwrap(),
+            "Oproščeno plačila DDV v skladu z 42. členom ZDDV-1."
+        );
+    }
+
+    #[test]
+    fn non_vat_payers_issue_0_percent_lines_with_the_legal_note() {
+        let invoice = treated(VatTreatment::NonVatPayer, "");
+        assert!(invoice.vat_issues().is_empty());
+        assert!(!invoice.vat_treatment.charges_vat());
+        assert_eq!(invoice.line_rate(&invoice.services[0]), 0.0);
+        let totals = invoice.totals();
+        assert_eq!(totals.vat, 0.0);
+        assert_eq!(totals.gross, 150.0);
+        assert_eq!(
+            invoice.vat_treatment.legal_note().unwrap(),
+            "DDV ni obračunan na podlagi prvega odstavka 94. člena ZDDV-1."
+        );
+        //Domestic invoices charge VAT and need no note
+        let domestic = treated(VatTreatment::Domestic, "");
+        assert!(domestic.vat_treatment.charges_vat());
+        assert_eq!(domestic.vat_treatment.legal_note(), None);
+        assert_eq!(domestic.totals().vat, 26.75);
+    }
+
+    #[test]
+    fn generates_the_pdf_and_stores_the_invoice() {
+        let _workspace = Workspace::with_fonts();
+        let warnings = init(sample()).unwrap();
+        assert!(storage::pdf_path(12).exists());
+        assert_eq!(storage::load(12).unwrap().invoice.invoice_number, 12);
+        //The preview needs pdfium, which may be missing, but nothing else may fail
+        assert!(warnings.iter().all(|warning| warning.contains("preview")));
+    }
+
+    #[test]
+    fn a_missing_logo_is_a_warning() {
+        let _workspace = Workspace::with_fonts();
+        let mut racun = sample();
+        racun.invoice.company.company_logo = "missing.png".to_string();
+        let warnings = init(racun).unwrap();
+        assert!(warnings.iter().any(|warning| warning.contains("logo")));
+        assert!(storage::exists(12));
+    }
+
+    #[test]
+    fn invalid_invoices_are_not_stored() {
+        let _workspace = Workspace::with_fonts();
+        let mut racun = sample();
+        racun.invoice.company.company_iban = "SI56 1234 1234 1234 123".to_string();
+        assert!(matches!(init(racun), Err(InvoiceError::Validation(_))));
+        assert!(!storage::exists(12));
+    }
+
+    #[test]
+    fn duplicates_are_refused_before_fiscalization() {
+        let workspace = Workspace::with_fonts();
+        init(sample()).unwrap();
+        broken_fiscal_config(&workspace);
+        let mut racun = sample();
+        racun.invoice.payment_method = PaymentMethod::Cash;
+        assert!(matches!(
+            init(racun),
+            Err(InvoiceError::DuplicateNumber(12))
+        ));
+    }
+
+    #[test]
+    fn missing_fonts_stop_rendering() {
+        let _workspace = Workspace::new();
+        assert!(matches!(init(sample()), Err(InvoiceError::Font(_))));
+        assert!(!storage::exists(12));
+    }
+
+    #[test]
+    fn cash_invoices_that_cannot_be_rendered_never_reach_furs() {
+        let workspace = Workspace::new();
+        workspace.write("furs.p12", furs::certificate());
+        let (address, received) = furs::mock();
+        workspace.write(
+            "furs.json",
+            serde_json::to_string(&furs::config(&address)).unwrap(),
+        );
+        let mut racun = sample();
+        racun.invoice.payment_method = PaymentMethod::Cash;
+        assert!(matches!(init(racun.clone()), Err(InvoiceError::Font(_))));
+        assert!(received.lock().unwrap().is_empty());
+        assert!(FiscalQueue::load().unwrap().invoices.is_empty());
+        assert!(!storage::exists(12));
+
+        //Offline invoices aren't queued either
+        workspace.write(
+            "furs.json",
+            serde_json::to_string(&furs::config(&furs::offline())).unwrap(),
+        );
+        assert!(matches!(init(racun), Err(InvoiceError::Font(_))));
+        assert!(FiscalQueue::load().unwrap().invoices.is_empty());
+    }
+
+    #[test]
+    fn offline_cash_invoices_are_queued_once_stored() {
+        let workspace = Workspace::with_fonts();
+        workspace.write("furs.p12", furs::certificate());
+        workspace.write(
+            "furs.json",
+            serde_json::to_string(&furs::config(&furs::offline())).unwrap(),
+        );
+        let mut racun = sample();
+        racun.invoice.payment_method = PaymentMethod::Cash;
+        let warnings = init(racun).unwrap();
+        assert!(warnings.iter().any(|warning| warning.contains("queued")));
+        let queued = FiscalQueue::load().unwrap().invoices;
+        assert_eq!(queued.len(), 1);
+        assert!(storage::exists(queued[0].invoice.invoice_number));
+    }
+
+    #[test]
+    fn io_errors_are_returned() {
+        let workspace = Workspace::with_fonts();
+        //A file where the invoices folder should be
+        workspace.write("invoices", "");
+        assert!(matches!(init(sample()), Err(InvoiceError::Io(_))));
+    }
+
+    #[test]
+    fn fiscal_errors_stop_the_invoice() {
+        let workspace = Workspace::with_fonts();
+        broken_fiscal_config(&workspace);
+        let mut racun = sample();
+        racun.invoice.payment_method = PaymentMethod::Cash;
+        assert!(matches!(init(racun), Err(InvoiceError::Fiscal(_))));
+        assert!(!storage::exists(12));
     }
 }
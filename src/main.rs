@@ -1,9 +1,29 @@
 use gui::entry;
+mod api;
+mod batch;
+mod cli;
+mod currency;
+mod dates;
+mod email;
+mod error;
+mod fiscal;
 mod gui;
 mod invoicer;
+mod registry;
 mod render;
+mod reporting;
+#[cfg(feature = "discord")]
 mod rpc;
+mod schema;
+mod storage;
+mod validation;
+#[cfg(test)]
+mod test_support;
 fn main() {
+    //Any argument means the command line is used instead of the GUI
+    if std::env::args().len() > 1 {
+        std::process::exit(cli::run());
+    }
     // let fresh_racun = Racun::parse_from_file();
 
     //Todo sign field and add a base64 for a sign.
@@ -11,7 +31,6 @@ fn main() {
     //Gui entry
     entry();
     //2.Save the json file everytime you type something or change something. Maybe use json only for the current data that is inside the gui and database for saving it..
-    //3.Discord RPC intergration
     //4.Database soon or later.
     //5. PDF viewer inside the gui.
 }
@@ -0,0 +1,566 @@
+use chrono::NaiveDate;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs::{self, read_to_string},
+    path::Path,
+};
+
+use crate::currency::{self, ExchangeRate};
+use crate::error::InvoiceError;
+use crate::invoicer::{Company, Invoice, InvoiceStructure, Partner, Racun, Service, VatTreatment};
+
+const PARTNERS_FILE: &str = "partners.json";
+const COMPANIES_FILE: &str = "companies.json";
+const CATALOG_FILE: &str = "catalog.json";
+const RATES_FILE: &str = "exchange_rates.json";
+const ACCOUNTS_FILE: &str = "accounts.json";
+
+//Registry files live next to data.json, a missing file is just an empty registry
+fn load_file<T: Default + DeserializeOwned>(path: &str) -> Result<T, InvoiceError> {
+    if !Path::new(path).exists() {
+        return Ok(T::default());
+    }
+    let data = read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+fn save_file<T: Serialize>(path: &str, value: &T) -> Result<(), InvoiceError> {
+    let json = serde_json::to_string_pretty(value)?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PartnerBook {
+    pub partners: Vec<Partner>,
+}
+
+impl PartnerBook {
+    pub fn load() -> Result<Self, InvoiceError> {
+        load_file(PARTNERS_FILE)
+    }
+
+    pub fn save(&self) -> Result<(), InvoiceError> {
+        save_file(PARTNERS_FILE, self)
+    }
+
+    //Case insensitive search over name, VAT ID, contact person, email and address
+    pub fn search(&self, query: &str) -> Vec<(usize, &Partner)> {
+        let query = query.trim().to_lowercase();
+        self.partners
+            .iter()
+            .enumerate()
+            .filter(|(_, partner)| {
+                query.is_empty()
+                    || [
+                        &partner.partner_name,
+                        &partner.partner_vat_id,
+                        &partner.partner_contact_person,
+                        &partner.partner_email,
+                        &partner.partner_address,
+                    ]
+                    .iter()
+                    .any(|field| field.to_lowercase().contains(&query))
+            })
+            .collect()
+    }
+
+    //Replaces the partner with the same VAT ID (or name when there is no VAT ID), otherwise adds it
+    pub fn upsert(&mut self, partner: Partner) -> usize {
+        let existing = self.partners.iter().position(|p| {
+            if partner.partner_vat_id.is_empty() {
+                p.partner_vat_id.is_empty() && p.partner_name == partner.partner_name
+            } else {
+                p.partner_vat_id == partner.partner_vat_id
+            }
+        });
+        match existing {
+            Some(index) => {
+                self.partners[index] = partner;
+                index
+            }
+            None => {
+                self.partners.push(partner);
+                self.partners.len() - 1
+            }
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.partners.len() {
+            self.partners.remove(index);
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BankAccount {
+    pub iban: String,
+    pub bank_name: String,
+    pub swift: String,
+}
+
+impl BankAccount {
+    pub fn apply(&self, racun: &mut Racun) {
+        racun.invoice.company.company_iban = self.iban.clone();
+        racun.invoice.company.company_bankname = self.bank_name.clone();
+        racun.invoice.company.company_swift = self.swift.clone();
+    }
+}
+
+//A legal entity that issues invoices, the invoice gets a copy of the company data
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CompanyProfile {
+    pub profile_name: String,
+    pub company: Company,
+    pub bank_accounts: Vec<BankAccount>,
+    pub vat_payer: bool,
+    pub next_invoice_number: i32,
+    pub template: InvoiceStructure,
+}
+
+impl CompanyProfile {
+    //Makes a profile out of an existing invoice, used to seed the profiles from data.json
+    pub fn from_racun(racun: &Racun) -> Self {
+        let company = racun.invoice.company.clone();
+        Self {
+            profile_name: company.company_name.clone(),
+            bank_accounts: vec![BankAccount {
+                iban: company.company_iban.clone(),
+                bank_name: company.company_bankname.clone(),
+                swift: company.company_swift.clone(),
+            }],
+            company,
+            vat_payer: true,
+            next_invoice_number: racun.invoice.invoice_number + 1,
+            template: racun.config.clone(),
+        }
+    }
+
+    //Fills the draft with this profile's company data, template and next invoice number
+    pub fn apply(&self, racun: &mut Racun) {
+        racun.invoice.company_profile = self.profile_name.clone();
+        racun.invoice.company = self.company.clone();
+        racun.invoice.invoice_number = self.next_invoice_number;
+        racun.config = self.template.clone();
+        if !self.vat_payer {
+            racun.invoice.vat_treatment = VatTreatment::NonVatPayer;
+        } else if racun.invoice.vat_treatment == VatTreatment::NonVatPayer {
+            racun.invoice.vat_treatment = VatTreatment::Domestic;
+        }
+        if let Some(account) = self.bank_accounts.first() {
+            account.apply(racun);
+        }
+    }
+
+    //Invoices made before profiles existed only have the company name
+    pub fn owns(&self, racun: &Racun) -> bool {
+        if racun.invoice.company_profile.is_empty() {
+            racun.invoice.company.company_name == self.company.company_name
+        } else {
+            racun.invoice.company_profile == self.profile_name
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CompanyProfiles {
+    pub active_profile: String,
+    pub profiles: Vec<CompanyProfile>,
+}
+
+impl CompanyProfiles {
+    pub fn load() -> Result<Self, InvoiceError> {
+        load_file(COMPANIES_FILE)
+    }
+
+    pub fn save(&self) -> Result<(), InvoiceError> {
+        save_file(COMPANIES_FILE, self)
+    }
+
+    pub fn active(&self) -> Option<&CompanyProfile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.profile_name == self.active_profile)
+            .or_else(|| self.profiles.first())
+    }
+
+    pub fn get_mut(&mut self, profile_name: &str) -> Option<&mut CompanyProfile> {
+        self.profiles
+            .iter_mut()
+            .find(|profile| profile.profile_name == profile_name)
+    }
+
+    //Moves the profile's numbering sequence past the number that was just used
+    pub fn use_number(&mut self, profile_name: &str, invoice_number: i32) {
+        if let Some(profile) = self.get_mut(profile_name) {
+            if invoice_number >= profile.next_invoice_number {
+                profile.next_invoice_number = invoice_number + 1;
+            }
+        }
+    }
+}
+
+//Reusable product or service that can be picked into an invoice
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CatalogItem {
+    pub code: String,
+    pub description: String,
+    pub unit: String,
+    pub unit_price: f64,
+    pub vat_rate: f64,
+    #[serde(default)]
+    pub goods: bool,
+}
+
+impl CatalogItem {
+    pub fn to_service(&self, currency: &str) -> Service {
+        Service {
+            service_code: self.code.clone(),
+            service_name: self.description.clone(),
+            service_quantity: 1.0,
+            service_unit: self.unit.clone(),
+            service_price: self.unit_price,
+            service_tax: self.vat_rate,
+            service_currency: currency.to_string(),
+            service_discount: None,
+            service_goods: self.goods,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Catalog {
+    pub items: Vec<CatalogItem>,
+}
+
+impl Catalog {
+    pub fn load() -> Result<Self, InvoiceError> {
+        load_file(CATALOG_FILE)
+    }
+
+    pub fn save(&self) -> Result<(), InvoiceError> {
+        save_file(CATALOG_FILE, self)
+    }
+
+    pub fn search(&self, query: &str) -> Vec<&CatalogItem> {
+        let query = query.trim().to_lowercase();
+        self.items
+            .iter()
+            .filter(|item| {
+                query.is_empty()
+                    || item.code.to_lowercase().contains(&query)
+                    || item.description.to_lowercase().contains(&query)
+            })
+            .collect()
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RateAccount {
+    pub rate: f64,
+    pub account: String,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemAccount {
+    pub code: String,
+    pub account: String,
+}
+
+//Accounts the journal export posts to. Revenue goes to the catalog item's account, then the
+//VAT rate's, then the default one. Without accounts.json the Slovenian chart of accounts is used.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ChartOfAccounts {
+    pub receivable: String,
+    pub bank: String,
+    pub revenue: String,
+    pub revenue_by_rate: Vec<RateAccount>,
+    pub revenue_by_item: Vec<ItemAccount>,
+    pub vat_output: String,
+    pub vat_output_by_rate: Vec<RateAccount>,
+}
+
+impl Default for ChartOfAccounts {
+    fn default() -> Self {
+        let rate = |rate: f64, account: &str| RateAccount {
+            rate,
+            account: account.to_string(),
+        };
+        Self {
+            receivable: "120".to_string(),
+            bank: "110".to_string(),
+            revenue: "760".to_string(),
+            revenue_by_rate: Vec::new(),
+            revenue_by_item: Vec::new(),
+            vat_output: "260".to_string(),
+            vat_output_by_rate: vec![rate(22.0, "2600"), rate(9.5, "2601"), rate(5.0, "2602")],
+        }
+    }
+}
+
+impl ChartOfAccounts {
+    pub fn load() -> Result<Self, InvoiceError> {
+        load_file(ACCOUNTS_FILE)
+    }
+
+    pub fn revenue_account(&self, service_code: &str, rate: f64) -> &str {
+        self.revenue_by_item
+            .iter()
+            .find(|item| !service_code.is_empty() && item.code == service_code)
+            .map(|item| item.account.as_str())
+            .or_else(|| find_rate(&self.revenue_by_rate, rate))
+            .unwrap_or(&self.revenue)
+    }
+
+    pub fn vat_account(&self, rate: f64) -> &str {
+        find_rate(&self.vat_output_by_rate, rate).unwrap_or(&self.vat_output)
+    }
+}
+
+fn find_rate(accounts: &[RateAccount], rate: f64) -> Option<&str> {
+    accounts
+        .iter()
+        .find(|account| account.rate == rate)
+        .map(|account| account.account.as_str())
+}
+
+//Imported ECB reference rates, so foreign currency invoices can be issued offline
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeRates {
+    pub rates: Vec<ExchangeRate>,
+}
+
+impl ExchangeRates {
+    pub fn load() -> Result<Self, InvoiceError> {
+        load_file(RATES_FILE)
+    }
+
+    pub fn save(&self) -> Result<(), InvoiceError> {
+        save_file(RATES_FILE, self)
+    }
+
+    //Adds the rates from an ECB XML file, rates that are already known are replaced. Returns how many were new
+    pub fn import_ecb(&mut self, path: &Path) -> Result<usize, InvoiceError> {
+        let imported = currency::parse_ecb_xml(&read_to_string(path)?)?;
+        let mut added = 0;
+        for rate in imported {
+            match self
+                .rates
+                .iter_mut()
+                .find(|known| known.currency == rate.currency && known.date == rate.date)
+            {
+                Some(known) => *known = rate,
+                None => {
+                    self.rates.push(rate);
+                    added += 1;
+                }
+            }
+        }
+        self.rates
+            .sort_by(|a, b| a.currency.cmp(&b.currency).then(a.date.cmp(&b.date)));
+        Ok(added)
+    }
+
+    //The ECB doesn't publish on weekends and holidays, the last rate before the date counts then
+    pub fn lookup(&self, currency: &str, date: NaiveDate) -> Option<&ExchangeRate> {
+        self.rates
+            .iter()
+            .filter(|rate| rate.currency == currency && rate.date <= date)
+            .max_by_key(|rate| rate.date)
+    }
+
+    //Newest rate of every currency
+    pub fn latest(&self) -> Vec<&ExchangeRate> {
+        let mut latest: Vec<&ExchangeRate> = Vec::new();
+        for rate in &self.rates {
+            match latest
+                .iter_mut()
+                .find(|known| known.currency == rate.currency)
+            {
+                Some(known) if known.date < rate.date => *known = rate,
+                Some(_) => {}
+                None => latest.push(rate),
+            }
+        }
+        latest
+    }
+
+    //Sets the rate for the invoice date on foreign currency invoices, EUR invoices don't need one
+    pub fn apply(&self, invoice: &mut Invoice) {
+        if invoice.invoice_currency == currency::EUR {
+            invoice.exchange_rate = None;
+        } else {
+            invoice.exchange_rate = self
+                .lookup(&invoice.invoice_currency, invoice.invoice_date)
+                .cloned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{manifest_dir, sample, Workspace};
+
+    fn date(value: &str) -> NaiveDate {
+        crate::dates::parse(value).unwrap()
+    }
+
+    fn ecb_rates() -> ExchangeRates {
+        let mut rates = ExchangeRates::default();
+        let path = manifest_dir().join("src/currency/fixtures/eurofxref-hist.xml");
+        assert_eq!(rates.import_ecb(&path).unwrap(), 6);
+        rates
+    }
+
+    fn partner(name: &str, vat_id: &str) -> Partner {
+        Partner {
+            partner_name: name.to_string(),
+            partner_vat_id: vat_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn book() -> PartnerBook {
+        let mut acme = partner("Acme d.o.o.", "SI12345678");
+        acme.partner_address = "Slovenska cesta 1, Ljubljana".to_string();
+        acme.partner_contact_person = "Ana Novak".to_string();
+        let mut globex = partner("Globex GmbH", "DE123456789");
+        globex.partner_email = "billing@globex.example".to_string();
+        PartnerBook {
+            partners: vec![acme, globex, partner("Janez Kranjc", "")],
+        }
+    }
+
+    #[test]
+    fn partner_search_matches_any_field_ignoring_case() {
+        let book = book();
+        let found = |query| {
+            book.search(query)
+                .iter()
+                .map(|(index, _)| *index)
+                .collect::<Vec<usize>>()
+        };
+        assert_eq!(found(""), vec![0, 1, 2]);
+        assert_eq!(found("  acme "), vec![0]);
+        assert_eq!(found("de1234"), vec![1]);
+        assert_eq!(found("ANA NOVAK"), vec![0]);
+        assert_eq!(found("globex.example"), vec![1]);
+        assert_eq!(found("ljubljana"), vec![0]);
+        assert_eq!(found("gmbh"), vec![1]);
+        assert!(found("Initech").is_empty());
+    }
+
+    #[test]
+    fn upsert_replaces_by_vat_id_or_by_name_without_one() {
+        let mut book = book();
+        //Same VAT ID, the renamed partner replaces the old one
+        assert_eq!(book.upsert(partner("Acme Group d.o.o.", "SI12345678")), 0);
+        assert_eq!(book.partners[0].partner_name, "Acme Group d.o.o.");
+        //Same name but a different VAT ID is a new partner
+        assert_eq!(book.upsert(partner("Globex GmbH", "ATU12345678")), 3);
+        //Without a VAT ID the name decides
+        let mut janez = partner("Janez Kranjc", "");
+        janez.partner_email = "janez@example.com".to_string();
+        assert_eq!(book.upsert(janez), 2);
+        assert_eq!(book.partners[2].partner_email, "janez@example.com");
+        //A VAT ID on one side only never matches
+        assert_eq!(book.upsert(partner("Janez Kranjc", "SI87654321")), 4);
+        assert_eq!(book.partners.len(), 5);
+    }
+
+    #[test]
+    fn partners_are_saved_next_to_data_json() {
+        let _workspace = Workspace::new();
+        assert!(PartnerBook::load().unwrap().partners.is_empty());
+        book().save().unwrap();
+        let names: Vec<String> = PartnerBook::load()
+            .unwrap()
+            .partners
+            .into_iter()
+            .map(|partner| partner.partner_name)
+            .collect();
+        assert_eq!(names, vec!["Acme d.o.o.", "Globex GmbH", "Janez Kranjc"]);
+    }
+
+    #[test]
+    fn imported_rates_are_sorted_and_replaced() {
+        let mut rates = ecb_rates();
+        let order: Vec<(&str, NaiveDate)> = rates
+            .rates
+            .iter()
+            .map(|rate| (rate.currency.as_str(), rate.date))
+            .collect();
+        assert_eq!(order[0], ("CHF", date("2024-05-16")));
+        assert_eq!(order[5], ("USD", date("2024-05-17")));
+        //The same file again adds nothing
+        let path = manifest_dir().join("src/currency/fixtures/eurofxref-hist.xml");
+        assert_eq!(rates.import_ecb(&path).unwrap(), 0);
+        assert_eq!(rates.rates.len(), 6);
+        let latest: Vec<(&str, f64)> = rates
+            .latest()
+            .iter()
+            .map(|rate| (rate.currency.as_str(), rate.rate))
+            .collect();
+        assert_eq!(
+            latest,
+            vec![("CHF", 0.9875), ("GBP", 0.85553), ("USD", 1.0866)]
+        );
+    }
+
+    #[test]
+    fn lookup_takes_the_last_rate_on_or_before_the_date() {
+        let rates = ecb_rates();
+        let rate = |day| rates.lookup("USD", date(day)).map(|rate| rate.rate);
+        assert_eq!(rate("2024-05-16"), Some(1.0855));
+        assert_eq!(rate("2024-05-17"), Some(1.0866));
+        //Saturday, the ECB publishes nothing
+        assert_eq!(rate("2024-05-18"), Some(1.0866));
+        assert_eq!(rate("2024-05-15"), None);
+        assert!(rates.lookup("JPY", date("2024-05-17")).is_none());
+    }
+
+    #[test]
+    fn invoices_get_the_rate_of_their_date() {
+        let rates = ecb_rates();
+        let mut racun = sample();
+        racun.invoice.invoice_date = date("2024-05-19");
+        rates.apply(&mut racun.invoice);
+        assert_eq!(racun.invoice.exchange_rate, None);
+
+        racun.invoice.set_currency("GBP");
+        rates.apply(&mut racun.invoice);
+        assert_eq!(
+            racun.invoice.exchange_rate,
+            Some(ExchangeRate {
+                currency: "GBP".to_string(),
+                date: date("2024-05-17"),
+                rate: 0.85553,
+            })
+        );
+
+        //No rate from before the invoice date
+        racun.invoice.invoice_date = date("2024-05-01");
+        rates.apply(&mut racun.invoice);
+        assert_eq!(racun.invoice.exchange_rate, None);
+    }
+
+    #[test]
+    fn rates_are_saved_next_to_data_json() {
+        let _workspace = Workspace::new();
+        assert!(ExchangeRates::load().unwrap().rates.is_empty());
+        ecb_rates().save().unwrap();
+        assert_eq!(ExchangeRates::load().unwrap().rates, ecb_rates().rates);
+    }
+}
@@ -1,12 +1,31 @@
 use image::ImageFormat;
 use pdfium_render::prelude::*;
-use pdfium_render::{prelude::PdfiumError, render_config::PdfRenderConfig};
+use pdfium_render::render_config::PdfRenderConfig;
+use std::path::Path;
+
+use crate::error::InvoiceError;
+use crate::storage;
+
+//Renders the jpg of a stored invoice again, from the cli or when the gui can't find it
+pub fn render_preview(invoice_number: i32) -> Result<(), InvoiceError> {
+    let pdf = storage::pdf_path(invoice_number);
+    if !pdf.exists() {
+        return Err(InvoiceError::NotFound(invoice_number));
+    }
+    export_pdf_to_jpegs(
+        &pdf.to_string_lossy(),
+        &storage::invoice_dir(invoice_number).to_string_lossy(),
+        None,
+        invoice_number,
+    )
+}
+
 pub fn export_pdf_to_jpegs(
     path: &str,
     file_path: &str,
     password: Option<&str>,
     invoice_number: i32,
-) -> Result<(), PdfiumError> {
+) -> Result<(), InvoiceError> {
     // Renders each page in the PDF file at the given path to a separate JPEG file.
 
     // Bind to a Pdfium library in the same directory as our Rust executable;
@@ -30,17 +49,17 @@ pub fn export_pdf_to_jpegs(
 
     // ... then render each page to a bitmap image, saving each image to a JPEG file.
 
-    for (_, page) in document.pages().iter().enumerate() {
+    for page in document.pages().iter() {
         page.render_with_config(&render_config)?
             .as_image() // Renders this page to an image::DynamicImage...
             .as_rgba8() // ... then converts it to an image::Image...
-            .ok_or(PdfiumError::ImageError)?
+            .ok_or(InvoiceError::render(
+                "Page couldn't be converted to an image",
+            ))?
             .save_with_format(
-                format!("{}\\račun {}.jpg", file_path, invoice_number),
+                Path::new(file_path).join(format!("račun {}.jpg", invoice_number)),
                 ImageFormat::Jpeg,
-            ) // ... and saves it to a file.
-            .map_err(|_| PdfiumError::ImageError)?;
+            )?; // ... and saves it to a file.
     }
-    println!("Done✔✔✔✔");
     Ok(())
 }
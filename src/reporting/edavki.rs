@@ -0,0 +1,313 @@
+//eDavki XML documents for "Uvoz dokumenta". The DDV-O only has the output VAT fields filled,
+//the rest of the return is completed in eDavki before it is submitted.
+use super::eu::{Oss, Rpo};
+use super::vat::DdvO;
+use super::Period;
+use crate::invoicer::{Company, TaxpayerType};
+
+const DDV_O_NAMESPACE: &str = "http://edavki.durs.si/Documents/Schemas/DDV_O_2.xsd";
+const RP_O_NAMESPACE: &str = "http://edavki.durs.si/Documents/Schemas/RP_O_2.xsd";
+const OSS_NAMESPACE: &str = "http://edavki.durs.si/Documents/Schemas/DDV_OSS_1.xsd";
+const EDP_NAMESPACE: &str = "http://edavki.durs.si/Documents/Schemas/EDP-Common-1.xsd";
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+//Tax number is the VAT ID without the SI prefix
+fn tax_number(company: &Company) -> String {
+    let vat_id = company.company_vat_id.trim().replace(' ', "");
+    vat_id.strip_prefix("SI").unwrap_or(&vat_id).to_string()
+}
+
+//Sole traders (s.p.) file as natural persons
+fn taxpayer_type(company: &Company) -> &'static str {
+    match company.company_taxpayer_type {
+        TaxpayerType::LegalPerson => "PO",
+        TaxpayerType::SoleTrader => "FO",
+    }
+}
+
+//Envelope with the taxpayer header around the document body
+fn envelope(namespace: &str, company: &Company, body: &str) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!(
+        "<Envelope xmlns=\"{}\" xmlns:edp=\"{}\">\n",
+        namespace, EDP_NAMESPACE
+    );
+    xml += "  <edp:Header>\n    <edp:taxpayer>\n";
+    xml += &format!(
+        "      <edp:taxNumber>{}</edp:taxNumber>\n",
+        escape(&tax_number(company))
+    );
+    xml += &format!(
+        "      <edp:taxpayerType>{}</edp:taxpayerType>\n",
+        taxpayer_type(company)
+    );
+    xml += &format!(
+        "      <edp:name>{}</edp:name>\n",
+        escape(&company.company_name)
+    );
+    xml += &format!(
+        "      <edp:address1>{}</edp:address1>\n",
+        escape(&company.company_address)
+    );
+    xml += &format!(
+        "      <edp:city>{}</edp:city>\n",
+        escape(&company.company_postal_code)
+    );
+    xml += "    </edp:taxpayer>\n";
+    xml += "    <edp:Workflow>\n      <edp:DocumentWorkflowID>O</edp:DocumentWorkflowID>\n";
+    xml += "    </edp:Workflow>\n  </edp:Header>\n";
+    xml += "  <edp:AttachmentList/>\n  <edp:Signatures/>\n  <body>\n    <edp:bodyContent/>\n";
+    xml += body;
+    xml += "  </body>\n</Envelope>\n";
+    xml
+}
+
+fn period(period: &Period) -> String {
+    format!(
+        "      <PeriodStart>{}</PeriodStart>\n      <PeriodEnd>{}</PeriodEnd>\n",
+        period.from, period.to
+    )
+}
+
+pub fn ddv_o_xml(ddv_o: &DdvO, company: &Company) -> String {
+    let mut body = String::from("    <DDV_O>\n");
+    body += &period(&ddv_o.period);
+    for (field, _, value) in ddv_o.fields() {
+        body += &format!("      <Znesek{0}>{1:.2}</Znesek{0}>\n", field, value);
+    }
+    body += "    </DDV_O>\n";
+    envelope(DDV_O_NAMESPACE, company, &body)
+}
+
+//Triangular transactions (42. člen) aren't recorded on invoices, they are always 0
+pub fn rp_o_xml(rpo: &Rpo, company: &Company) -> String {
+    let mut body = String::from("    <RP_O>\n");
+    body += &period(&rpo.period);
+    for line in &rpo.lines {
+        body += "      <Vrstica>\n";
+        body += &format!(
+            "        <KodaDrzave>{}</KodaDrzave>\n",
+            escape(&line.country)
+        );
+        body += &format!("        <IdZaDDV>{}</IdZaDDV>\n", escape(&line.vat_id));
+        body += &format!("        <VrednostBlaga>{:.2}</VrednostBlaga>\n", line.goods);
+        body += "        <VrednostTristranskihPoslov>0.00</VrednostTristranskihPoslov>\n";
+        body += &format!(
+            "        <VrednostStoritev>{:.2}</VrednostStoritev>\n",
+            line.services
+        );
+        body += "      </Vrstica>\n";
+    }
+    body += "    </RP_O>\n";
+    envelope(RP_O_NAMESPACE, company, &body)
+}
+
+pub fn oss_xml(oss: &Oss, company: &Company) -> String {
+    let mut body = String::from("    <OSS>\n");
+    body += &period(&oss.period);
+    for line in &oss.lines {
+        body += "      <Dobava>\n";
+        body += &format!(
+            "        <DrzavaClanica>{}</DrzavaClanica>\n",
+            escape(&line.country)
+        );
+        body += &format!("        <Stopnja>{}</Stopnja>\n", line.rate);
+        body += &format!("        <Osnova>{:.2}</Osnova>\n", line.base);
+        body += &format!("        <DDV>{:.2}</DDV>\n", line.vat);
+        body += "      </Dobava>\n";
+    }
+    body += &format!("      <SkupajDDV>{:.2}</SkupajDDV>\n", oss.total_vat());
+    body += "    </OSS>\n";
+    envelope(OSS_NAMESPACE, company, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporting::eu::{OssLine, RpoLine};
+    use crate::test_support::sample;
+    use chrono::NaiveDate;
+    use xml::reader::{EventReader, XmlEvent};
+
+    fn company(taxpayer_type: TaxpayerType) -> Company {
+        Company {
+            company_name: "Kovač & Partner s.p.".to_string(),
+            company_vat_id: "SI 1234 5679".to_string(),
+            company_taxpayer_type: taxpayer_type,
+            ..sample().invoice.company
+        }
+    }
+
+    fn quarter() -> Period {
+        "2024-Q2".parse().unwrap()
+    }
+
+    fn ddv_o() -> String {
+        let ddv_o = DdvO {
+            period: quarter(),
+            p11: 1250.0,
+            p12: 300.0,
+            p15: 80.5,
+            p21: 220.0,
+            p22: 23.75,
+            p22a: 0.0,
+        };
+        ddv_o_xml(&ddv_o, &company(TaxpayerType::SoleTrader))
+    }
+
+    fn rp_o() -> String {
+        let rpo = Rpo {
+            period: quarter(),
+            company: company(TaxpayerType::LegalPerson),
+            lines: vec![
+                RpoLine {
+                    country: "AT".to_string(),
+                    vat_id: "U12345678".to_string(),
+                    goods: 0.0,
+                    services: 1500.0,
+                },
+                RpoLine {
+                    country: "DE".to_string(),
+                    vat_id: "123456789".to_string(),
+                    goods: 420.1,
+                    services: -50.0,
+                },
+            ],
+        };
+        rp_o_xml(&rpo, &rpo.company)
+    }
+
+    fn oss() -> String {
+        let oss = Oss {
+            period: quarter(),
+            company: company(TaxpayerType::LegalPerson),
+            lines: vec![
+                OssLine {
+                    country: "AT".to_string(),
+                    rate: 20.0,
+                    base: 100.0,
+                    vat: 20.0,
+                },
+                OssLine {
+                    country: "HR".to_string(),
+                    rate: 25.0,
+                    base: 40.0,
+                    vat: 10.0,
+                },
+                OssLine {
+                    country: "HR".to_string(),
+                    rate: 13.0,
+                    base: 10.0,
+                    vat: 1.3,
+                },
+            ],
+        };
+        oss_xml(&oss, &oss.company)
+    }
+
+    //Element, namespace and text of every element, in document order
+    fn elements(xml: &str) -> Vec<(String, String, String)> {
+        let mut elements = Vec::new();
+        let mut open: Vec<usize> = Vec::new();
+        for event in EventReader::from_str(xml) {
+            match event.unwrap_or_else(|err| panic!("not well-formed: {}", err)) {
+                XmlEvent::StartElement { name, .. } => {
+                    open.push(elements.len());
+                    elements.push((
+                        name.local_name,
+                        name.namespace.unwrap_or_default(),
+                        String::new(),
+                    ));
+                }
+                XmlEvent::Characters(text) => {
+                    elements[*open.last().unwrap()].2 += &text;
+                }
+                XmlEvent::EndElement { .. } => {
+                    open.pop();
+                }
+                _ => {}
+            }
+        }
+        elements
+    }
+
+    //The XSDs aren't bundled, this checks the types they give the fields: the envelope in
+    //the EDP namespace, decimals with two places, ISO dates and the code lists
+    fn check(xml: &str, namespace: &str, document: &str) {
+        let elements = elements(xml);
+        assert_eq!(elements[0].0, "Envelope");
+        let decimal = |text: &str| {
+            let (whole, cents) = text.split_once('.').unwrap_or((text, ""));
+            let whole = whole.strip_prefix('-').unwrap_or(whole);
+            !whole.is_empty()
+                && whole.chars().all(|c| c.is_ascii_digit())
+                && cents.len() == 2
+                && cents.chars().all(|c| c.is_ascii_digit())
+        };
+        let mut in_header = true;
+        for (name, element_namespace, text) in &elements {
+            if name == "body" {
+                in_header = false;
+            }
+            let expected = if in_header && name != "Envelope" || name == "bodyContent" {
+                EDP_NAMESPACE
+            } else {
+                namespace
+            };
+            assert_eq!(element_namespace, expected, "{}", name);
+            match name.as_str() {
+                "taxNumber" => assert!(text.len() == 8 && text.chars().all(|c| c.is_ascii_digit())),
+                "taxpayerType" => assert!(["FO", "PO"].contains(&text.as_str()), "{}", text),
+                "PeriodStart" | "PeriodEnd" => {
+                    assert!(
+                        NaiveDate::parse_from_str(text, "%Y-%m-%d").is_ok(),
+                        "{}",
+                        text
+                    )
+                }
+                "KodaDrzave" | "DrzavaClanica" => {
+                    assert!(text.len() == 2 && text.chars().all(|c| c.is_ascii_uppercase()))
+                }
+                "Stopnja" => assert!(text.parse::<f64>().is_ok(), "{}", text),
+                name if name.starts_with("Znesek")
+                    || name.starts_with("Vrednost")
+                    || ["Osnova", "DDV", "SkupajDDV"].contains(&name) =>
+                {
+                    assert!(decimal(text), "{} {}", name, text)
+                }
+                _ => {}
+            }
+        }
+        assert!(elements.iter().any(|(name, _, _)| name == document));
+    }
+
+    #[test]
+    fn documents_match_the_golden_files() {
+        assert_eq!(ddv_o(), include_str!("fixtures/ddv_o.xml"));
+        assert_eq!(rp_o(), include_str!("fixtures/rp_o.xml"));
+        assert_eq!(oss(), include_str!("fixtures/oss.xml"));
+    }
+
+    #[test]
+    fn documents_follow_the_schemas() {
+        check(&ddv_o(), DDV_O_NAMESPACE, "DDV_O");
+        check(&rp_o(), RP_O_NAMESPACE, "RP_O");
+        check(&oss(), OSS_NAMESPACE, "OSS");
+    }
+
+    #[test]
+    fn the_taxpayer_type_is_the_company_setting() {
+        //Not guessed from "s.p." in the name
+        assert_eq!(taxpayer_type(&company(TaxpayerType::LegalPerson)), "PO");
+        assert_eq!(taxpayer_type(&company(TaxpayerType::SoleTrader)), "FO");
+        assert_eq!(tax_number(&company(TaxpayerType::LegalPerson)), "12345679");
+    }
+}
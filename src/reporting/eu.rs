@@ -0,0 +1,322 @@
+//Recapitulative statement (RP-O) of reverse charge supplies per partner VAT ID and the OSS
+//return per member state and rate. Amounts are in EUR, credit notes lower them.
+use std::collections::BTreeMap;
+
+use super::{eur_totals, report_company, Period};
+use crate::error::InvoiceError;
+use crate::invoicer::{vat_id_country, Company, Racun, VatTreatment};
+use crate::registry::CompanyProfile;
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RpoLine {
+    pub country: String,
+    //Without the country prefix
+    pub vat_id: String,
+    pub goods: f64,
+    pub services: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Rpo {
+    pub period: Period,
+    pub company: Company,
+    //By country, then VAT ID
+    pub lines: Vec<RpoLine>,
+}
+
+impl Rpo {
+    pub fn total(&self) -> (f64, f64) {
+        self.lines
+            .iter()
+            .fold((0.0, 0.0), |(goods, services), line| {
+                (goods + line.goods, services + line.services)
+            })
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct OssLine {
+    pub country: String,
+    pub rate: f64,
+    pub base: f64,
+    pub vat: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Oss {
+    pub period: Period,
+    pub company: Company,
+    //By country, highest rate first
+    pub lines: Vec<OssLine>,
+}
+
+impl Oss {
+    pub fn total_vat(&self) -> f64 {
+        self.lines.iter().map(|line| line.vat).sum()
+    }
+}
+
+//Invoices of the period with the given treatment, in EUR with the sign of credit notes
+fn selected<'a>(
+    invoices: &'a [Racun],
+    period: Period,
+    profile: Option<&CompanyProfile>,
+    treatment: fn(&VatTreatment) -> bool,
+) -> Result<Vec<&'a Racun>, InvoiceError> {
+    let selected: Vec<&Racun> = invoices
+        .iter()
+        .filter(|racun| period.contains(racun.invoice.invoice_date))
+        .filter(|racun| treatment(&racun.invoice.vat_treatment))
+        .filter(|racun| profile.is_none_or(|profile| profile.owns(racun)))
+        .collect();
+    let missing_rates: Vec<String> = selected
+        .iter()
+        .filter(|racun| eur_totals(&racun.invoice).is_none())
+        .map(|racun| {
+            format!(
+                "Invoice {} in {} has no exchange rate to EUR",
+                racun.invoice.invoice_number, racun.invoice.invoice_currency
+            )
+        })
+        .collect();
+    if !missing_rates.is_empty() {
+        return Err(InvoiceError::Validation(missing_rates));
+    }
+    Ok(selected)
+}
+
+pub fn rpo(
+    invoices: &[Racun],
+    period: Period,
+    profile: Option<&CompanyProfile>,
+) -> Result<Rpo, InvoiceError> {
+    let selected = selected(invoices, period, profile, |treatment| {
+        *treatment == VatTreatment::ReverseCharge
+    })?;
+    let mut lines: BTreeMap<(String, String), RpoLine> = BTreeMap::new();
+    for racun in &selected {
+        let invoice = &racun.invoice;
+        let vat_id = invoice.partner.partner_vat_id.trim().replace(' ', "");
+        let country = vat_id_country(&vat_id).unwrap_or_default().to_string();
+        let number = vat_id[country.len()..].to_string();
+        let line = lines
+            .entry((country.clone(), number.clone()))
+            .or_insert_with(|| RpoLine {
+                country,
+                vat_id: number,
+                ..Default::default()
+            });
+        //The document discount is spread over the lines by the share of the net
+        let totals = invoice.totals();
+        let eur = eur_totals(invoice).unwrap_or_default();
+        let share = if totals.lines_net != 0.0 {
+            eur.net / totals.lines_net
+        } else {
+            0.0
+        };
+        for service in &invoice.services {
+            let amount = service.net_amount() * share * invoice.sign();
+            if service.service_goods {
+                line.goods += amount;
+            } else {
+                line.services += amount;
+            }
+        }
+    }
+    Ok(Rpo {
+        period,
+        company: report_company(profile, &selected),
+        lines: lines.into_values().collect(),
+    })
+}
+
+pub fn oss(
+    invoices: &[Racun],
+    period: Period,
+    profile: Option<&CompanyProfile>,
+) -> Result<Oss, InvoiceError> {
+    let selected = selected(invoices, period, profile, |treatment| {
+        matches!(treatment, VatTreatment::Oss { .. })
+    })?;
+    let mut lines: Vec<OssLine> = Vec::new();
+    for racun in &selected {
+        let invoice = &racun.invoice;
+        let VatTreatment::Oss { country } = &invoice.vat_treatment else {
+            continue;
+        };
+        let totals = eur_totals(invoice)
+            .unwrap_or_default()
+            .signed(invoice.sign());
+        for vat in totals.vat_lines {
+            match lines
+                .iter_mut()
+                .find(|line| &line.country == country && line.rate == vat.rate)
+            {
+                Some(line) => {
+                    line.base += vat.base;
+                    line.vat += vat.vat;
+                }
+                None => lines.push(OssLine {
+                    country: country.clone(),
+                    rate: vat.rate,
+                    base: vat.base,
+                    vat: vat.vat,
+                }),
+            }
+        }
+    }
+    lines.sort_by(|a, b| a.country.cmp(&b.country).then(b.rate.total_cmp(&a.rate)));
+    Ok(Oss {
+        period,
+        company: report_company(profile, &selected),
+        lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invoicer::{round2, Discount};
+    use crate::test_support::invoice;
+
+    fn may() -> Period {
+        "2024-05".parse().unwrap()
+    }
+
+    //Reverse charge invoice to the partner with lines of (goods, net)
+    fn reverse_charge(number: i32, vat_id: &str, lines: &[(bool, f64)]) -> Racun {
+        let rows: Vec<(&str, f64, f64, f64)> =
+            lines.iter().map(|&(_, net)| ("", 1.0, net, 22.0)).collect();
+        let mut racun = invoice(number, &rows);
+        racun.invoice.vat_treatment = VatTreatment::ReverseCharge;
+        racun.invoice.partner.partner_vat_id = vat_id.to_string();
+        for (service, &(goods, _)) in racun.invoice.services.iter_mut().zip(lines) {
+            service.service_goods = goods;
+        }
+        racun
+    }
+
+    fn oss_invoice(number: i32, country: &str, lines: &[(f64, f64)]) -> Racun {
+        let rows: Vec<(&str, f64, f64, f64)> = lines
+            .iter()
+            .map(|&(net, rate)| ("", 1.0, net, rate))
+            .collect();
+        let mut racun = invoice(number, &rows);
+        racun.invoice.vat_treatment = VatTreatment::Oss {
+            country: country.to_string(),
+        };
+        racun
+    }
+
+    fn rpo_lines(rpo: &Rpo) -> Vec<(&str, &str, f64, f64)> {
+        rpo.lines
+            .iter()
+            .map(|line| {
+                (
+                    line.country.as_str(),
+                    line.vat_id.as_str(),
+                    round2(line.goods),
+                    round2(line.services),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rpo_groups_by_country_and_vat_id() {
+        let invoices = [
+            reverse_charge(1, "DE 123 456 789", &[(true, 100.0), (false, 200.0)]),
+            reverse_charge(2, "DE123456789", &[(false, 50.0)]),
+            reverse_charge(3, " AT U12345678 ", &[(false, 300.0)]),
+            //Domestic invoices aren't in the RP-O
+            invoice(4, &[("", 1.0, 1000.0, 22.0)]),
+        ];
+        let rpo = rpo(&invoices, may(), None).unwrap();
+        assert_eq!(
+            rpo_lines(&rpo),
+            vec![
+                ("AT", "U12345678", 0.0, 300.0),
+                ("DE", "123456789", 100.0, 250.0)
+            ]
+        );
+        assert_eq!(rpo.total(), (100.0, 550.0));
+    }
+
+    #[test]
+    fn rpo_spreads_the_document_discount_over_goods_and_services() {
+        let mut racun = reverse_charge(1, "DE123456789", &[(true, 100.0), (false, 200.0)]);
+        racun.invoice.invoice_discount = Some(Discount::Amount(30.0));
+        let rpo = rpo(&[racun], may(), None).unwrap();
+        assert_eq!(rpo_lines(&rpo), vec![("DE", "123456789", 90.0, 180.0)]);
+    }
+
+    #[test]
+    fn credit_notes_lower_the_rpo_line() {
+        let mut credit_note = reverse_charge(2, "DE123456789", &[(false, 50.0)]);
+        credit_note.invoice.credit_note_for = Some(1);
+        let invoices = [
+            reverse_charge(1, "DE123456789", &[(true, 100.0), (false, 200.0)]),
+            credit_note,
+        ];
+        let rpo = rpo(&invoices, may(), None).unwrap();
+        assert_eq!(rpo_lines(&rpo), vec![("DE", "123456789", 100.0, 150.0)]);
+    }
+
+    #[test]
+    fn oss_lines_are_per_country_and_rate() {
+        let mut credit_note = oss_invoice(4, "AT", &[(10.0, 20.0)]);
+        credit_note.invoice.credit_note_for = Some(2);
+        let invoices = [
+            oss_invoice(1, "DE", &[(100.0, 19.0)]),
+            oss_invoice(2, "AT", &[(100.0, 20.0), (50.0, 10.0)]),
+            oss_invoice(3, "AT", &[(10.0, 20.0)]),
+            credit_note,
+        ];
+        let oss = oss(&invoices, may(), None).unwrap();
+        let lines: Vec<(&str, f64, f64, f64)> = oss
+            .lines
+            .iter()
+            .map(|line| {
+                (
+                    line.country.as_str(),
+                    line.rate,
+                    round2(line.base),
+                    round2(line.vat),
+                )
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("AT", 20.0, 100.0, 20.0),
+                ("AT", 10.0, 50.0, 5.0),
+                ("DE", 19.0, 100.0, 19.0)
+            ]
+        );
+        assert_eq!(round2(oss.total_vat()), 44.0);
+    }
+
+    #[test]
+    fn reports_only_take_the_profile_invoices() {
+        let mut own = reverse_charge(1, "DE123456789", &[(false, 100.0)]);
+        own.invoice.company_profile = "Second".to_string();
+        let other = reverse_charge(2, "DE123456789", &[(false, 200.0)]);
+        let mut own_oss = oss_invoice(3, "AT", &[(10.0, 20.0)]);
+        own_oss.invoice.company_profile = "Second".to_string();
+        let other_oss = oss_invoice(4, "AT", &[(20.0, 20.0)]);
+        let mut profile = CompanyProfile::from_racun(&own);
+        profile.profile_name = "Second".to_string();
+        profile.company.company_name = "SECOND d.o.o.".to_string();
+        let invoices = [own, other, own_oss, other_oss];
+
+        let rpo = rpo(&invoices, may(), Some(&profile)).unwrap();
+        assert_eq!(rpo_lines(&rpo), vec![("DE", "123456789", 0.0, 100.0)]);
+        assert_eq!(rpo.company.company_name, "SECOND d.o.o.");
+        let oss = oss(&invoices, may(), Some(&profile)).unwrap();
+        assert_eq!(oss.lines.len(), 1);
+        assert_eq!(oss.lines[0].base, 10.0);
+        //Without a profile both are reported
+        let all = super::rpo(&invoices, may(), None).unwrap();
+        assert_eq!(all.total(), (0.0, 300.0));
+    }
+}
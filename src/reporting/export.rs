@@ -0,0 +1,483 @@
+//KIR as CSV, XLSX or a landscape PDF with the DDV-O figures at the end, RP-O and OSS as CSV
+//and the journal as CSV or a Ledger journal
+use printpdf::*;
+use rust_xlsxwriter::{Format, Workbook};
+
+use super::eu::{Oss, Rpo};
+use super::journal::JournalEntry;
+use super::vat::{DdvO, Kir, KirAmounts, KirRow};
+use crate::dates;
+use crate::error::InvoiceError;
+use crate::invoicer::{display_vat_id, load_font, make_line};
+
+const HEADERS: [&str; 22] = [
+    "Zap. št.",
+    "Datum vpisa",
+    "Št. listine",
+    "Datum listine",
+    "Datum dobave",
+    "Kupec",
+    "Sedež kupca",
+    "ID za DDV kupca",
+    "Dobropis k",
+    "Vrednost z DDV",
+    "Oproščeno",
+    "Dobave v EU",
+    "Izvoz",
+    "Dobave VEM/OSS",
+    "Osnova 22 %",
+    "DDV 22 %",
+    "Osnova 9,5 %",
+    "DDV 9,5 %",
+    "Osnova 5 %",
+    "DDV 5 %",
+    "Osnova druge",
+    "DDV druge",
+];
+//Columns before the amounts
+const TEXT_COLUMNS: usize = 9;
+
+enum Cell {
+    Text(String),
+    Amount(f64),
+}
+
+impl Cell {
+    fn text(&self) -> String {
+        match self {
+            Cell::Text(text) => text.clone(),
+            Cell::Amount(amount) => format!("{:.2}", amount),
+        }
+    }
+}
+
+fn amount_cells(amounts: &KirAmounts) -> Vec<Cell> {
+    let mut cells = vec![
+        Cell::Amount(amounts.gross),
+        Cell::Amount(amounts.exempt),
+        Cell::Amount(amounts.eu_supplies),
+        Cell::Amount(amounts.export),
+        Cell::Amount(amounts.oss),
+    ];
+    for (base, vat) in amounts.base.iter().zip(amounts.vat.iter()) {
+        cells.push(Cell::Amount(*base));
+        cells.push(Cell::Amount(*vat));
+    }
+    cells
+}
+
+fn row_cells(row: &KirRow) -> Vec<Cell> {
+    let mut cells = vec![
+        Cell::Text(row.serial.to_string()),
+        Cell::Text(dates::format(row.entry_date)),
+        Cell::Text(row.invoice_number.to_string()),
+        Cell::Text(dates::format(row.invoice_date)),
+        Cell::Text(row.service_date.clone()),
+        Cell::Text(row.partner_name.clone()),
+        Cell::Text(row.partner_address.clone()),
+        Cell::Text(row.partner_vat_id.clone()),
+        Cell::Text(row.credit_note_for.map_or(String::new(), |n| n.to_string())),
+    ];
+    cells.extend(amount_cells(&row.amounts));
+    cells
+}
+
+fn total_cells(kir: &Kir) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = (0..TEXT_COLUMNS)
+        .map(|_| Cell::Text(String::new()))
+        .collect();
+    cells[0] = Cell::Text("Skupaj".to_string());
+    cells.extend(amount_cells(&kir.totals()));
+    cells
+}
+
+fn company_line(kir: &Kir) -> String {
+    let company = &kir.company;
+    format!(
+        "{}, {}, {}, ID za DDV {}",
+        company.company_name,
+        company.company_address,
+        company.company_postal_code,
+        display_vat_id(&company.company_vat_id)
+    )
+}
+
+pub fn kir_csv(kir: &Kir) -> Result<String, InvoiceError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(HEADERS)?;
+    for row in &kir.rows {
+        writer.write_record(row_cells(row).iter().map(Cell::text))?;
+    }
+    writer.write_record(total_cells(kir).iter().map(Cell::text))?;
+    csv_string(writer)
+}
+
+fn csv_string(writer: csv::Writer<Vec<u8>>) -> Result<String, InvoiceError> {
+    let data = writer
+        .into_inner()
+        .map_err(|err| InvoiceError::Io(err.into_error()))?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+pub fn rpo_csv(rpo: &Rpo) -> Result<String, InvoiceError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "Koda države",
+        "ID za DDV",
+        "Vrednost dobav blaga",
+        "Tristranski posli",
+        "Vrednost storitev",
+    ])?;
+    for line in &rpo.lines {
+        writer.write_record([
+            line.country.clone(),
+            line.vat_id.clone(),
+            format!("{:.2}", line.goods),
+            format!("{:.2}", 0.0),
+            format!("{:.2}", line.services),
+        ])?;
+    }
+    let (goods, services) = rpo.total();
+    writer.write_record([
+        "Skupaj".to_string(),
+        String::new(),
+        format!("{:.2}", goods),
+        format!("{:.2}", 0.0),
+        format!("{:.2}", services),
+    ])?;
+    csv_string(writer)
+}
+
+pub fn oss_csv(oss: &Oss) -> Result<String, InvoiceError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["Država članica", "Stopnja", "Osnova", "DDV"])?;
+    for line in &oss.lines {
+        writer.write_record([
+            line.country.clone(),
+            line.rate.to_string(),
+            format!("{:.2}", line.base),
+            format!("{:.2}", line.vat),
+        ])?;
+    }
+    writer.write_record([
+        "Skupaj".to_string(),
+        String::new(),
+        String::new(),
+        format!("{:.2}", oss.total_vat()),
+    ])?;
+    csv_string(writer)
+}
+
+//One row per posting, rows of an entry share its number
+pub fn journal_csv(entries: &[JournalEntry]) -> Result<String, InvoiceError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "entry",
+        "date",
+        "reference",
+        "description",
+        "account",
+        "debit",
+        "credit",
+    ])?;
+    for (number, entry) in entries.iter().enumerate() {
+        for posting in &entry.postings {
+            writer.write_record([
+                (number + 1).to_string(),
+                entry.date.to_string(),
+                entry.reference.clone(),
+                entry.description.clone(),
+                posting.account.clone(),
+                format!("{:.2}", posting.debit()),
+                format!("{:.2}", posting.credit()),
+            ])?;
+        }
+    }
+    csv_string(writer)
+}
+
+//Plain text journal for Ledger and hledger, the account codes are the account names
+pub fn journal_ledger(entries: &[JournalEntry]) -> String {
+    let mut journal = String::new();
+    for entry in entries {
+        journal += &format!(
+            "{} * ({}) {}\n",
+            entry.date.format("%Y/%m/%d"),
+            entry.reference,
+            entry.description
+        );
+        for posting in &entry.postings {
+            journal += &format!(
+                "    {:<20} {:>14.2} EUR\n",
+                posting.account,
+                posting.amount as f64 / 100.0
+            );
+        }
+        journal += "\n";
+    }
+    journal
+}
+
+pub fn kir_xlsx(kir: &Kir) -> Result<Vec<u8>, InvoiceError> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let amount = Format::new().set_num_format("#,##0.00");
+    let total = Format::new().set_bold().set_num_format("#,##0.00");
+    let sheet = workbook.add_worksheet();
+    sheet.set_name("KIR")?;
+    sheet.set_landscape();
+    sheet.write_string_with_format(0, 0, "Knjiga izdanih računov", &bold)?;
+    sheet.write_string(1, 0, company_line(kir))?;
+    sheet.write_string(2, 0, format!("Obdobje {}", kir.period))?;
+    let header_row = 4;
+    for (column, header) in HEADERS.iter().enumerate() {
+        sheet.write_string_with_format(header_row, column as u16, *header, &bold)?;
+        let width = if column == 5 || column == 6 { 30 } else { 13 };
+        sheet.set_column_width(column as u16, width)?;
+    }
+    sheet.set_freeze_panes(header_row + 1, 0)?;
+    let rows = kir
+        .rows
+        .iter()
+        .map(|row| (row_cells(row), &amount))
+        .chain(std::iter::once((total_cells(kir), &total)));
+    for (index, (cells, format)) in rows.enumerate() {
+        let row = header_row + 1 + index as u32;
+        for (column, cell) in cells.iter().enumerate() {
+            match cell {
+                Cell::Text(text) => sheet.write_string(row, column as u16, text)?,
+                Cell::Amount(value) => {
+                    sheet.write_number_with_format(row, column as u16, *value, format)?
+                }
+            };
+        }
+    }
+    let ddv_o = workbook.add_worksheet();
+    ddv_o.set_name("DDV-O")?;
+    ddv_o.set_column_width(1, 50)?;
+    ddv_o.set_column_width(2, 14)?;
+    ddv_o.write_string_with_format(0, 0, format!("DDV-O {}", kir.period), &bold)?;
+    for (index, (field, label, value)) in kir.ddv_o().fields().iter().enumerate() {
+        let row = 2 + index as u32;
+        ddv_o.write_string(row, 0, *field)?;
+        ddv_o.write_string(row, 1, *label)?;
+        ddv_o.write_number_with_format(row, 2, *value, &amount)?;
+    }
+    Ok(workbook.save_to_buffer()?)
+}
+
+//Landscape A4, in mm
+const PAGE_WIDTH: f64 = 297.0;
+const PAGE_HEIGHT: f64 = 210.0;
+const MARGIN: f64 = 5.0;
+const ROW_HEIGHT: f64 = 3.6;
+const FONT_SIZE: f64 = 6.0;
+//Width of a 6pt DejaVu Sans character, good enough to cut long names
+const CHAR_WIDTH: f64 = 1.2;
+const WIDTHS: [f64; 22] = [
+    8.0, 14.0, 10.0, 14.0, 16.0, 22.0, 20.0, 16.0, 10.0, 12.0, 12.0, 12.0, 12.0, 12.0, 12.0, 12.0,
+    12.0, 12.0, 12.0, 12.0, 12.0, 12.0,
+];
+
+fn fit(text: &str, width: f64) -> String {
+    let chars = (width / CHAR_WIDTH) as usize;
+    if text.chars().count() <= chars {
+        text.to_string()
+    } else {
+        text.chars()
+            .take(chars.saturating_sub(1))
+            .collect::<String>()
+            + "…"
+    }
+}
+
+struct KirPdf {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    font: IndirectFontRef,
+    bold_font: IndirectFontRef,
+    y: f64,
+    page: usize,
+}
+
+impl KirPdf {
+    fn new_page(&mut self) {
+        let (page, layer) = self
+            .doc
+            .add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.page += 1;
+        self.y = PAGE_HEIGHT - MARGIN - 4.0;
+    }
+
+    fn text(&self, text: &str, size: f64, x: f64, font: &IndirectFontRef) {
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), font);
+    }
+
+    fn rule(&self) {
+        let y = Mm(self.y - 1.0);
+        make_line(&self.layer, Mm(MARGIN), y, Mm(PAGE_WIDTH - MARGIN), y);
+    }
+
+    //Amounts are right aligned in their column
+    fn cells(&mut self, cells: &[Cell], bold: bool) {
+        let font = if bold {
+            self.bold_font.clone()
+        } else {
+            self.font.clone()
+        };
+        let mut x = MARGIN;
+        for (cell, width) in cells.iter().zip(WIDTHS) {
+            let text = fit(&cell.text(), width - 1.0);
+            let offset = match cell {
+                Cell::Text(_) => 0.0,
+                Cell::Amount(_) => width - 1.0 - text.chars().count() as f64 * CHAR_WIDTH,
+            };
+            self.text(&text, FONT_SIZE, x + offset, &font);
+            x += width;
+        }
+        self.y -= ROW_HEIGHT;
+    }
+
+    fn header(&mut self, kir: &Kir) {
+        let bold_font = self.bold_font.clone();
+        let font = self.font.clone();
+        self.text("Knjiga izdanih računov", 11.0, MARGIN, &bold_font);
+        self.text(
+            &format!("Stran {}", self.page),
+            FONT_SIZE,
+            PAGE_WIDTH - MARGIN - 12.0,
+            &font,
+        );
+        self.y -= 5.0;
+        self.text(&company_line(kir), 8.0, MARGIN, &font);
+        self.y -= 4.0;
+        self.text(&format!("Obdobje {}", kir.period), 8.0, MARGIN, &font);
+        self.y -= 6.0;
+        let headers: Vec<Cell> = HEADERS
+            .iter()
+            .map(|header| Cell::Text(header.to_string()))
+            .collect();
+        self.cells(&headers, true);
+        self.rule();
+        self.y -= 1.0;
+    }
+
+    fn ensure_space(&mut self, kir: &Kir, height: f64) {
+        if self.y - height < MARGIN {
+            self.new_page();
+            self.header(kir);
+        }
+    }
+
+    fn ddv_o(&mut self, ddv_o: &DdvO) {
+        let bold_font = self.bold_font.clone();
+        let font = self.font.clone();
+        self.y -= 4.0;
+        self.text("Podatki za DDV-O", 9.0, MARGIN, &bold_font);
+        self.y -= 5.0;
+        for (field, label, value) in ddv_o.fields() {
+            self.text(field, 8.0, MARGIN, &font);
+            self.text(label, 8.0, MARGIN + 10.0, &font);
+            self.text(&format!("{:.2}", value), 8.0, MARGIN + 100.0, &font);
+            self.y -= 4.0;
+        }
+    }
+}
+
+pub fn kir_pdf(kir: &Kir) -> Result<Vec<u8>, InvoiceError> {
+    let (doc, page, layer) = PdfDocument::new(
+        "Knjiga izdanih računov",
+        Mm(PAGE_WIDTH),
+        Mm(PAGE_HEIGHT),
+        "Layer 1",
+    );
+    let font = load_font(&doc, "fonts/DejaVuSans.ttf")?;
+    let bold_font = load_font(&doc, "fonts/DejaVuSans-Bold.ttf")?;
+    let layer = doc.get_page(page).get_layer(layer);
+    let mut pdf = KirPdf {
+        doc,
+        layer,
+        font,
+        bold_font,
+        y: PAGE_HEIGHT - MARGIN - 4.0,
+        page: 1,
+    };
+    pdf.header(kir);
+    for row in &kir.rows {
+        pdf.ensure_space(kir, ROW_HEIGHT);
+        pdf.cells(&row_cells(row), false);
+    }
+    pdf.ensure_space(kir, ROW_HEIGHT + 1.0);
+    pdf.rule();
+    pdf.y -= 1.0;
+    pdf.cells(&total_cells(kir), true);
+    //The DDV-O block needs about 40mm
+    pdf.ensure_space(kir, 40.0);
+    pdf.ddv_o(&kir.ddv_o());
+    Ok(pdf.doc.save_to_bytes()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporting::journal::Posting;
+    use chrono::NaiveDate;
+
+    fn posting(account: &str, amount: i64) -> Posting {
+        Posting {
+            account: account.to_string(),
+            amount,
+        }
+    }
+
+    //An invoice with 22 % VAT and its payment
+    fn entries() -> Vec<JournalEntry> {
+        let entries = vec![
+            JournalEntry {
+                date: NaiveDate::from_ymd_opt(2024, 5, 10).unwrap(),
+                reference: "Račun 7".to_string(),
+                description: "Kovač d.o.o.".to_string(),
+                postings: vec![
+                    posting("120", 12200),
+                    posting("760", -10000),
+                    posting("2600", -2200),
+                ],
+            },
+            JournalEntry {
+                date: NaiveDate::from_ymd_opt(2024, 5, 20).unwrap(),
+                reference: "Plačilo 7".to_string(),
+                description: "Kovač d.o.o.".to_string(),
+                postings: vec![posting("110", 12200), posting("120", -12200)],
+            },
+        ];
+        assert!(entries.iter().all(JournalEntry::is_balanced));
+        entries
+    }
+
+    #[test]
+    fn journal_csv_matches_the_golden_file() {
+        assert_eq!(
+            journal_csv(&entries()).unwrap(),
+            include_str!("fixtures/journal.csv")
+        );
+    }
+
+    #[test]
+    fn journal_ledger_matches_the_golden_file() {
+        let ledger = journal_ledger(&entries());
+        assert_eq!(ledger, include_str!("fixtures/journal.ledger"));
+        //Every transaction sums to zero, which is what ledger and hledger check on import
+        for transaction in ledger.split("\n\n").filter(|text| !text.is_empty()) {
+            let cents: i64 = transaction
+                .lines()
+                .skip(1)
+                .map(|line| {
+                    let amount = line.split_whitespace().nth(1).unwrap();
+                    (amount.parse::<f64>().unwrap() * 100.0).round() as i64
+                })
+                .sum();
+            assert_eq!(cents, 0, "{}", transaction);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+//Double-entry journal of issued invoices and their payments, in EUR. Amounts are kept in
+//cents while posting so every entry balances to the cent.
+use chrono::NaiveDate;
+
+use super::{eur_totals, Period};
+use crate::error::InvoiceError;
+use crate::invoicer::{PaymentStatus, Racun};
+use crate::registry::{ChartOfAccounts, CompanyProfile};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub account: String,
+    //In cents, positive is a debit and negative a credit
+    pub amount: i64,
+}
+
+impl Posting {
+    pub fn debit(&self) -> f64 {
+        self.amount.max(0) as f64 / 100.0
+    }
+
+    pub fn credit(&self) -> f64 {
+        (-self.amount).max(0) as f64 / 100.0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub date: NaiveDate,
+    pub reference: String,
+    pub description: String,
+    pub postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    pub fn is_balanced(&self) -> bool {
+        self.postings
+            .iter()
+            .map(|posting| posting.amount)
+            .sum::<i64>()
+            == 0
+    }
+
+    //Postings to the same account are merged, empty ones left out
+    fn post(&mut self, account: &str, amount: i64) {
+        if amount == 0 {
+            return;
+        }
+        match self
+            .postings
+            .iter_mut()
+            .find(|posting| posting.account == account)
+        {
+            Some(posting) => posting.amount += amount,
+            None => self.postings.push(Posting {
+                account: account.to_string(),
+                amount,
+            }),
+        }
+    }
+}
+
+fn cents(amount: f64) -> i64 {
+    (amount * 100.0).round() as i64
+}
+
+//Splits the amount by the weights, the last share takes what rounding left over
+fn split(amount: i64, weights: &[f64]) -> Vec<i64> {
+    let total: f64 = weights.iter().sum();
+    let mut shares = Vec::with_capacity(weights.len());
+    let mut left = amount;
+    for (index, weight) in weights.iter().enumerate() {
+        let share = if index + 1 == weights.len() {
+            left
+        } else if total != 0.0 {
+            (amount as f64 * weight / total).round() as i64
+        } else {
+            0
+        };
+        left -= share;
+        shares.push(share);
+    }
+    shares
+}
+
+//Revenue per account and VAT per rate are credited, the receivable is debited with their sum.
+//Credit notes come out with every side swapped.
+fn invoice_entry(racun: &Racun, chart: &ChartOfAccounts) -> Option<JournalEntry> {
+    let invoice = &racun.invoice;
+    let totals = eur_totals(invoice)?;
+    let sign = if invoice.credit_note_for.is_some() {
+        -1
+    } else {
+        1
+    };
+    let (kind, reference) = match invoice.credit_note_for {
+        Some(original) => ("Dobropis", format!(" k računu {}", original)),
+        None => ("Račun", String::new()),
+    };
+    let mut entry = JournalEntry {
+        date: invoice.invoice_date,
+        reference: format!("{} {}{}", kind, invoice.invoice_number, reference),
+        description: invoice.partner.partner_name.clone(),
+        postings: Vec::new(),
+    };
+    let mut receivable = 0;
+    for line in &totals.vat_lines {
+        let services: Vec<_> = invoice
+            .services
+            .iter()
+            .filter(|service| invoice.line_rate(service) == line.rate)
+            .collect();
+        let weights: Vec<f64> = services
+            .iter()
+            .map(|service| service.net_amount())
+            .collect();
+        let base = cents(line.base) * sign;
+        for (service, amount) in services.iter().zip(split(base, &weights)) {
+            entry.post(
+                chart.revenue_account(&service.service_code, line.rate),
+                -amount,
+            );
+        }
+        let vat = cents(line.vat) * sign;
+        entry.post(chart.vat_account(line.rate), -vat);
+        receivable += base + vat;
+    }
+    entry.postings.insert(
+        0,
+        Posting {
+            account: chart.receivable.clone(),
+            amount: receivable,
+        },
+    );
+    Some(entry)
+}
+
+//The bank is debited and the receivable credited with what the invoice entry debited
+fn payment_entry(
+    invoice_entry: &JournalEntry,
+    racun: &Racun,
+    chart: &ChartOfAccounts,
+) -> Option<JournalEntry> {
+    let invoice = &racun.invoice;
+    if invoice.status != PaymentStatus::PAID {
+        return None;
+    }
+    let amount = invoice_entry.postings.first()?.amount;
+    let mut entry = JournalEntry {
+        date: invoice.paid_date?,
+        reference: format!("Plačilo {}", invoice.invoice_number),
+        description: invoice.partner.partner_name.clone(),
+        postings: Vec::new(),
+    };
+    entry.post(&chart.bank, amount);
+    entry.post(&chart.receivable, -amount);
+    Some(entry)
+}
+
+//Invoices issued and payments received in the period, ordered by date. Paid invoices without
+//a paid date have no payment entry.
+pub fn journal(
+    invoices: &[Racun],
+    period: Period,
+    profile: Option<&CompanyProfile>,
+    chart: &ChartOfAccounts,
+) -> Result<Vec<JournalEntry>, InvoiceError> {
+    let mut entries = Vec::new();
+    let mut problems = Vec::new();
+    for racun in invoices.iter().filter(|racun| {
+        let invoice = &racun.invoice;
+        (period.contains(invoice.invoice_date)
+            || invoice.paid_date.is_some_and(|date| period.contains(date)))
+            && profile.is_none_or(|profile| profile.owns(racun))
+    }) {
+        let invoice = &racun.invoice;
+        let Some(entry) = invoice_entry(racun, chart) else {
+            problems.push(format!(
+                "Invoice {} in {} has no exchange rate to EUR",
+                invoice.invoice_number, invoice.invoice_currency
+            ));
+            continue;
+        };
+        let payment = payment_entry(&entry, racun, chart);
+        if period.contains(entry.date) {
+            entries.push(entry);
+        }
+        entries.extend(payment.filter(|payment| period.contains(payment.date)));
+    }
+    for entry in entries.iter().filter(|entry| !entry.is_balanced()) {
+        problems.push(format!("{} doesn't balance", entry.reference));
+    }
+    if !problems.is_empty() {
+        return Err(InvoiceError::Validation(problems));
+    }
+    //Stable, so an invoice paid on the day it was issued stays before its payment
+    entries.sort_by_key(|entry| entry.date);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invoicer::{Discount, VatTreatment};
+    use crate::registry::ItemAccount;
+    use crate::test_support::invoice;
+
+    fn date(value: &str) -> NaiveDate {
+        crate::dates::parse(value).unwrap()
+    }
+
+    fn may() -> Period {
+        "2024-05".parse().unwrap()
+    }
+
+    fn chart() -> ChartOfAccounts {
+        ChartOfAccounts {
+            revenue_by_item: vec![ItemAccount {
+                code: "CONSULTING".to_string(),
+                account: "7601".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn postings(entry: &JournalEntry) -> Vec<(&str, i64)> {
+        entry
+            .postings
+            .iter()
+            .map(|posting| (posting.account.as_str(), posting.amount))
+            .collect()
+    }
+
+    //Journal of May, every entry has to balance in cents
+    fn entries(invoices: &[Racun]) -> Vec<JournalEntry> {
+        let entries = journal(invoices, may(), None, &chart()).unwrap();
+        for entry in &entries {
+            let sum: i64 = entry.postings.iter().map(|posting| posting.amount).sum();
+            assert_eq!(sum, 0, "{} doesn't balance: {:?}", entry.reference, entry);
+            assert!(entry.is_balanced());
+        }
+        entries
+    }
+
+    #[test]
+    fn domestic_invoices_credit_revenue_and_vat_per_rate() {
+        let racun = invoice(
+            1,
+            &[
+                ("CONSULTING", 2.5, 60.0, 22.0),
+                ("", 1.0, 100.0, 22.0),
+                ("", 3.0, 9.99, 9.5),
+            ],
+        );
+        let gross = cents(racun.invoice.totals().gross);
+        let entries = entries(&[racun]);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].reference, "Račun 1");
+        assert_eq!(
+            postings(&entries[0]),
+            vec![
+                ("120", gross),
+                ("7601", -15000),
+                ("760", -12997),
+                ("2600", -5500),
+                ("2601", -285),
+            ]
+        );
+        assert_eq!(gross, 15000 + 12997 + 5500 + 285);
+    }
+
+    #[test]
+    fn reverse_charge_has_no_vat() {
+        let mut racun = invoice(2, &[("", 1.0, 1000.0, 22.0), ("", 2.0, 12.345, 22.0)]);
+        racun.invoice.vat_treatment = VatTreatment::ReverseCharge;
+        let entries = entries(&[racun]);
+        assert_eq!(
+            postings(&entries[0]),
+            vec![("120", 102469), ("760", -102469)]
+        );
+    }
+
+    #[test]
+    fn oss_vat_is_posted_at_the_member_state_rate() {
+        let mut racun = invoice(3, &[("", 1.0, 49.99, 20.0), ("", 1.0, 10.0, 10.0)]);
+        racun.invoice.vat_treatment = VatTreatment::Oss {
+            country: "AT".to_string(),
+        };
+        let entries = entries(&[racun]);
+        //No account for 20 % or 10 % in the chart, both go to the default VAT account
+        assert_eq!(
+            postings(&entries[0]),
+            vec![("120", 7099), ("760", -5999), ("260", -1100)]
+        );
+    }
+
+    #[test]
+    fn discounts_are_split_over_the_lines_to_the_cent() {
+        let mut racun = invoice(
+            4,
+            &[
+                ("CONSULTING", 1.0, 33.33, 22.0),
+                ("", 1.0, 33.33, 22.0),
+                ("", 1.0, 33.34, 22.0),
+            ],
+        );
+        racun.invoice.services[1].service_discount = Some(Discount::Percent(15.0));
+        racun.invoice.invoice_discount = Some(Discount::Amount(7.77));
+        let totals = racun.invoice.totals();
+        let entries = entries(&[racun]);
+        let revenue: i64 = entries[0]
+            .postings
+            .iter()
+            .filter(|posting| posting.account.starts_with("760"))
+            .map(|posting| posting.amount)
+            .sum();
+        assert_eq!(revenue, -cents(totals.net));
+        assert_eq!(entries[0].postings[0].amount, cents(totals.gross));
+    }
+
+    #[test]
+    fn credit_notes_swap_every_side() {
+        let invoice_entry = entries(&[invoice(5, &[("", 1.0, 80.0, 22.0)])]);
+        let mut credit_note = invoice(6, &[("", 1.0, 80.0, 22.0)]);
+        credit_note.invoice.credit_note_for = Some(5);
+        let entries = entries(&[credit_note]);
+        assert_eq!(entries[0].reference, "Dobropis 6 k računu 5");
+        let swapped: Vec<(&str, i64)> = postings(&invoice_entry[0])
+            .into_iter()
+            .map(|(account, amount)| (account, -amount))
+            .collect();
+        assert_eq!(postings(&entries[0]), swapped);
+    }
+
+    #[test]
+    fn payments_move_the_receivable_to_the_bank() {
+        let mut paid = invoice(7, &[("", 1.0, 100.0, 22.0)]);
+        paid.invoice.mark_paid(date("2024-05-20"));
+        //Issued in April, paid in May
+        let mut earlier = invoice(8, &[("", 1.0, 50.0, 9.5)]);
+        earlier.invoice.invoice_date = date("2024-04-28");
+        earlier.invoice.mark_paid(date("2024-05-02"));
+        let entries = entries(&[paid, earlier]);
+        let references: Vec<&str> = entries
+            .iter()
+            .map(|entry| entry.reference.as_str())
+            .collect();
+        assert_eq!(references, vec!["Plačilo 8", "Račun 7", "Plačilo 7"]);
+        assert_eq!(postings(&entries[0]), vec![("110", 5475), ("120", -5475)]);
+        assert_eq!(postings(&entries[2]), vec![("110", 12200), ("120", -12200)]);
+    }
+
+    #[test]
+    fn invoices_without_an_exchange_rate_stop_the_journal() {
+        let mut racun = invoice(9, &[("", 1.0, 100.0, 22.0)]);
+        racun.invoice.set_currency("USD");
+        assert!(matches!(
+            journal(&[racun], may(), None, &chart()),
+            Err(InvoiceError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn split_keeps_every_cent() {
+        assert_eq!(split(100, &[1.0, 1.0, 1.0]), vec![33, 33, 34]);
+        assert_eq!(split(-100, &[1.0, 1.0, 1.0]), vec![-33, -33, -34]);
+        assert_eq!(split(5, &[0.0, 0.0]), vec![0, 5]);
+    }
+}
@@ -0,0 +1,388 @@
+//Figures for the dashboard and the tax reports worked out from the stored invoices. Everything
+//is in EUR, foreign currency invoices are converted with the rate stored on them.
+use chrono::{Datelike, NaiveDate};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use crate::currency;
+use crate::dates;
+use crate::error::InvoiceError;
+use crate::invoicer::{Company, Invoice, PaymentStatus, Racun, Totals};
+use crate::registry::CompanyProfile;
+
+pub mod edavki;
+pub mod eu;
+pub mod export;
+pub mod journal;
+pub mod vat;
+
+const TOP_PARTNERS: usize = 10;
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct VatSummary {
+    pub rate: f64,
+    pub base: f64,
+    pub vat: f64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Dashboard {
+    //Net revenue by (year, month) and by year
+    pub months: BTreeMap<(i32, u32), f64>,
+    pub years: BTreeMap<i32, f64>,
+    //Gross amounts
+    pub paid: f64,
+    pub outstanding: f64,
+    //Net revenue of the best partners, largest first
+    pub top_partners: Vec<(String, f64)>,
+    //Highest rate first
+    pub vat: Vec<VatSummary>,
+    //Over the paid invoices that have a paid date
+    pub average_days_to_payment: Option<f64>,
+    pub invoices: usize,
+    //Foreign currency invoices without an exchange rate
+    pub skipped: usize,
+}
+
+impl Dashboard {
+    pub fn years(&self) -> impl Iterator<Item = i32> + '_ {
+        self.years.keys().copied()
+    }
+
+    //Net revenue of every month of the year, months without invoices are 0
+    pub fn monthly(&self, year: i32) -> [f64; 12] {
+        let mut months = [0.0; 12];
+        for (month, revenue) in months.iter_mut().enumerate() {
+            *revenue = self
+                .months
+                .get(&(year, month as u32 + 1))
+                .copied()
+                .unwrap_or_default();
+        }
+        months
+    }
+}
+
+//Reporting period, both days included
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Period {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl Period {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.to
+    }
+
+    fn month(year: i32, month: u32) -> Option<Self> {
+        let from = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let next = if month == 12 {
+            NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+        } else {
+            NaiveDate::from_ymd_opt(year, month + 1, 1)?
+        };
+        Some(Self {
+            from,
+            to: next.pred_opt()?,
+        })
+    }
+}
+
+//"2024" a year, "2024-05" a month, "2024-Q2" a quarter or "2024-04-01..2024-06-30"
+impl FromStr for Period {
+    type Err = InvoiceError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let invalid = || {
+            InvoiceError::validation(format!(
+                "'{}' is not a period, use 2024, 2024-05, 2024-Q2 or 2024-04-01..2024-06-30",
+                value
+            ))
+        };
+        if let Some((from, to)) = value.split_once("..") {
+            let from = dates::parse(from).ok_or_else(invalid)?;
+            let to = dates::parse(to).ok_or_else(invalid)?;
+            if to < from {
+                return Err(invalid());
+            }
+            return Ok(Self { from, to });
+        }
+        let (year, rest) = value.split_once('-').unwrap_or((value, ""));
+        let year: i32 = year.parse().map_err(|_| invalid())?;
+        let period = if rest.is_empty() {
+            Period::month(year, 1)
+                .zip(Period::month(year, 12))
+                .map(|(first, last)| Self {
+                    from: first.from,
+                    to: last.to,
+                })
+        } else if let Some(quarter) = rest.strip_prefix(['Q', 'q']) {
+            let quarter: u32 = quarter.parse().map_err(|_| invalid())?;
+            if !(1..=4).contains(&quarter) {
+                return Err(invalid());
+            }
+            Period::month(year, quarter * 3 - 2)
+                .zip(Period::month(year, quarter * 3))
+                .map(|(first, last)| Self {
+                    from: first.from,
+                    to: last.to,
+                })
+        } else {
+            let month: u32 = rest.parse().map_err(|_| invalid())?;
+            Period::month(year, month)
+        };
+        period.ok_or_else(invalid)
+    }
+}
+
+impl Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} - {}",
+            dates::format(self.from),
+            dates::format(self.to)
+        )
+    }
+}
+
+//Company on the report header, the profile's or the one of the first invoice
+pub fn report_company(profile: Option<&CompanyProfile>, invoices: &[&Racun]) -> Company {
+    match (profile, invoices.first()) {
+        (Some(profile), _) => profile.company.clone(),
+        (None, Some(racun)) => racun.invoice.company.clone(),
+        (None, None) => Company::default(),
+    }
+}
+
+//Totals in EUR, None when a foreign currency invoice has no rate
+pub fn eur_totals(invoice: &Invoice) -> Option<Totals> {
+    if invoice.invoice_currency == currency::EUR {
+        Some(invoice.totals())
+    } else {
+        invoice.totals_eur()
+    }
+}
+
+//Credit notes are refunds, they don't say how fast partners pay
+pub fn days_to_payment(invoice: &Invoice) -> Option<i64> {
+    if invoice.status != PaymentStatus::PAID || invoice.credit_note_for.is_some() {
+        return None;
+    }
+    invoice
+        .paid_date
+        .map(|paid: NaiveDate| (paid - invoice.invoice_date).num_days())
+}
+
+pub fn dashboard(invoices: &[Racun]) -> Dashboard {
+    let mut dashboard = Dashboard::default();
+    let mut partners: BTreeMap<&str, f64> = BTreeMap::new();
+    let mut payment_days = Vec::new();
+    for racun in invoices {
+        let invoice = &racun.invoice;
+        let Some(totals) = eur_totals(invoice) else {
+            dashboard.skipped += 1;
+            continue;
+        };
+        let totals = totals.signed(invoice.sign());
+        dashboard.invoices += 1;
+        let date = invoice.invoice_date;
+        *dashboard
+            .months
+            .entry((date.year(), date.month()))
+            .or_default() += totals.net;
+        *dashboard.years.entry(date.year()).or_default() += totals.net;
+        dashboard.paid += totals.paid;
+        dashboard.outstanding += totals.outstanding;
+        *partners
+            .entry(invoice.partner.partner_name.as_str())
+            .or_default() += totals.net;
+        for line in &totals.vat_lines {
+            match dashboard.vat.iter_mut().find(|vat| vat.rate == line.rate) {
+                Some(vat) => {
+                    vat.base += line.base;
+                    vat.vat += line.vat;
+                }
+                None => dashboard.vat.push(VatSummary {
+                    rate: line.rate,
+                    base: line.base,
+                    vat: line.vat,
+                }),
+            }
+        }
+        payment_days.extend(days_to_payment(invoice));
+    }
+    let mut top_partners: Vec<(String, f64)> = partners
+        .into_iter()
+        .map(|(name, revenue)| (name.to_string(), revenue))
+        .collect();
+    top_partners.sort_by(|a, b| b.1.total_cmp(&a.1));
+    top_partners.truncate(TOP_PARTNERS);
+    dashboard.top_partners = top_partners;
+    dashboard.vat.sort_by(|a, b| b.rate.total_cmp(&a.rate));
+    if !payment_days.is_empty() {
+        dashboard.average_days_to_payment =
+            Some(payment_days.iter().sum::<i64>() as f64 / payment_days.len() as f64);
+    }
+    dashboard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::ExchangeRate;
+    use crate::test_support::sample;
+
+    fn date(value: &str) -> NaiveDate {
+        dates::parse(value).unwrap()
+    }
+
+    //One line of `net` EUR at 22 %, unpaid
+    fn invoice(number: i32, partner: &str, invoice_date: &str, net: f64) -> Racun {
+        let mut racun = sample();
+        let invoice = &mut racun.invoice;
+        invoice.invoice_number = number;
+        invoice.partner.partner_name = partner.to_string();
+        invoice.invoice_date = date(invoice_date);
+        invoice.status = PaymentStatus::UNPAID;
+        invoice.paid_date = None;
+        invoice.services.truncate(1);
+        invoice.services[0].service_price = net;
+        invoice.services[0].service_quantity = 1.0;
+        invoice.services[0].service_tax = 22.0;
+        racun
+    }
+
+    fn paid(mut racun: Racun, paid_date: &str) -> Racun {
+        racun.invoice.mark_paid(date(paid_date));
+        racun
+    }
+
+    fn credit_note(mut racun: Racun, corrects: i32) -> Racun {
+        racun.invoice.credit_note_for = Some(corrects);
+        racun
+    }
+
+    fn in_usd(mut racun: Racun, rate: Option<f64>) -> Racun {
+        racun.invoice.set_currency("USD");
+        racun.invoice.exchange_rate = rate.map(|rate| ExchangeRate {
+            currency: "USD".to_string(),
+            date: racun.invoice.invoice_date,
+            rate,
+        });
+        racun
+    }
+
+    #[test]
+    fn revenue_is_summed_per_month_and_year() {
+        let dashboard = dashboard(&[
+            invoice(1, "Acme", "2024-01-15", 100.0),
+            invoice(2, "Acme", "2024-01-20", 50.0),
+            invoice(3, "Beta", "2024-03-01", 200.0),
+            invoice(4, "Beta", "2025-02-01", 300.0),
+            credit_note(invoice(5, "Acme", "2024-03-10", 20.0), 3),
+        ]);
+        assert_eq!(dashboard.invoices, 5);
+        assert_eq!(
+            dashboard.months,
+            BTreeMap::from([((2024, 1), 150.0), ((2024, 3), 180.0), ((2025, 2), 300.0)])
+        );
+        assert_eq!(
+            dashboard.years,
+            BTreeMap::from([(2024, 330.0), (2025, 300.0)])
+        );
+        assert_eq!(dashboard.years().collect::<Vec<_>>(), vec![2024, 2025]);
+        let mut months = [0.0; 12];
+        months[0] = 150.0;
+        months[2] = 180.0;
+        assert_eq!(dashboard.monthly(2024), months);
+        assert_eq!(dashboard.monthly(2023), [0.0; 12]);
+    }
+
+    #[test]
+    fn foreign_currencies_are_converted_or_skipped() {
+        let dashboard = dashboard(&[
+            invoice(1, "Acme", "2024-01-15", 100.0),
+            in_usd(invoice(2, "Acme", "2024-01-20", 125.0), Some(1.25)),
+            in_usd(invoice(3, "Beta", "2024-01-25", 500.0), None),
+        ]);
+        assert_eq!(dashboard.invoices, 2);
+        assert_eq!(dashboard.skipped, 1);
+        assert_eq!(dashboard.years[&2024], 200.0);
+        assert_eq!(dashboard.outstanding, 244.0);
+        assert_eq!(dashboard.top_partners, vec![("Acme".to_string(), 200.0)]);
+    }
+
+    #[test]
+    fn paid_and_outstanding_amounts_are_gross() {
+        let dashboard = dashboard(&[
+            paid(invoice(1, "Acme", "2024-01-01", 100.0), "2024-01-11"),
+            paid(invoice(2, "Acme", "2024-01-01", 50.0), "2024-01-31"),
+            invoice(3, "Beta", "2024-02-01", 200.0),
+            credit_note(invoice(4, "Beta", "2024-02-10", 50.0), 3),
+        ]);
+        assert_eq!(dashboard.paid, 183.0);
+        assert_eq!(dashboard.outstanding, 183.0);
+        //Unpaid invoices and credit notes don't count
+        assert_eq!(dashboard.average_days_to_payment, Some(20.0));
+    }
+
+    #[test]
+    fn top_partners_are_the_ten_largest() {
+        let mut invoices: Vec<Racun> = (1..=12)
+            .map(|number| {
+                invoice(
+                    number,
+                    &format!("Partner {}", number),
+                    "2024-05-01",
+                    number as f64 * 10.0,
+                )
+            })
+            .collect();
+        //Partner 1 also has the most valuable invoice, a credit note lowers partner 12
+        invoices.push(invoice(13, "Partner 1", "2024-05-02", 1000.0));
+        invoices.push(credit_note(
+            invoice(14, "Partner 12", "2024-05-03", 115.0),
+            12,
+        ));
+        let dashboard = dashboard(&invoices);
+        let top: Vec<(&str, f64)> = dashboard
+            .top_partners
+            .iter()
+            .map(|(name, revenue)| (name.as_str(), *revenue))
+            .collect();
+        assert_eq!(top.len(), TOP_PARTNERS);
+        assert_eq!(top[0], ("Partner 1", 1010.0));
+        assert_eq!(top[1], ("Partner 11", 110.0));
+        assert_eq!(top[9], ("Partner 3", 30.0));
+        assert!(!top.iter().any(|(name, _)| *name == "Partner 12"));
+    }
+
+    #[test]
+    fn vat_is_collected_per_rate() {
+        let mut reduced = invoice(2, "Beta", "2024-01-02", 100.0);
+        reduced.invoice.services[0].service_tax = 9.5;
+        let dashboard = dashboard(&[
+            invoice(1, "Acme", "2024-01-01", 100.0),
+            reduced,
+            invoice(3, "Acme", "2024-01-03", 50.0),
+        ]);
+        assert_eq!(
+            dashboard.vat,
+            vec![
+                VatSummary {
+                    rate: 22.0,
+                    base: 150.0,
+                    vat: 33.0
+                },
+                VatSummary {
+                    rate: 9.5,
+                    base: 100.0,
+                    vat: 9.5
+                },
+            ]
+        );
+        assert_eq!(dashboard.average_days_to_payment, None);
+    }
+}
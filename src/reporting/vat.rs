@@ -0,0 +1,338 @@
+//Knjiga izdanih računov (KIR) and the output side of the DDV-O return. Credit notes are
+//entered with negative amounts, foreign currency invoices in EUR at their stored rate.
+use chrono::NaiveDate;
+
+use super::{eur_totals, report_company, Period};
+use crate::error::InvoiceError;
+use crate::invoicer::{display_vat_id, Company, Racun, VatTreatment};
+use crate::registry::CompanyProfile;
+
+//Rates with their own KIR columns, anything else goes to the last one
+pub const RATES: [f64; 3] = [22.0, 9.5, 5.0];
+pub const RATE_COLUMNS: usize = RATES.len() + 1;
+
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct KirAmounts {
+    pub gross: f64,
+    //Exempt without the right to deduct, also what non VAT payers issue
+    pub exempt: f64,
+    //Reverse charge supplies to other EU countries
+    pub eu_supplies: f64,
+    pub export: f64,
+    //Net of supplies to EU consumers taxed in their member state, declared in the OSS return
+    pub oss: f64,
+    pub base: [f64; RATE_COLUMNS],
+    pub vat: [f64; RATE_COLUMNS],
+}
+
+impl KirAmounts {
+    fn add(&mut self, other: &KirAmounts) {
+        self.gross += other.gross;
+        self.exempt += other.exempt;
+        self.eu_supplies += other.eu_supplies;
+        self.export += other.export;
+        self.oss += other.oss;
+        for column in 0..RATE_COLUMNS {
+            self.base[column] += other.base[column];
+            self.vat[column] += other.vat[column];
+        }
+    }
+}
+
+fn rate_column(rate: f64) -> usize {
+    RATES
+        .iter()
+        .position(|known| *known == rate)
+        .unwrap_or(RATES.len())
+}
+
+#[derive(Debug, Clone)]
+pub struct KirRow {
+    pub serial: usize,
+    pub entry_date: NaiveDate,
+    pub invoice_number: i32,
+    pub invoice_date: NaiveDate,
+    pub service_date: String,
+    pub partner_name: String,
+    pub partner_address: String,
+    pub partner_vat_id: String,
+    pub credit_note_for: Option<i32>,
+    pub amounts: KirAmounts,
+}
+
+#[derive(Debug, Clone)]
+pub struct Kir {
+    pub period: Period,
+    pub company: Company,
+    pub rows: Vec<KirRow>,
+}
+
+impl Kir {
+    pub fn totals(&self) -> KirAmounts {
+        let mut totals = KirAmounts::default();
+        for row in &self.rows {
+            totals.add(&row.amounts);
+        }
+        totals
+    }
+
+    pub fn ddv_o(&self) -> DdvO {
+        let totals = self.totals();
+        DdvO {
+            period: self.period,
+            p11: totals.base.iter().sum::<f64>() + totals.export,
+            p12: totals.eu_supplies,
+            p15: totals.exempt,
+            p21: totals.vat[0],
+            p22: totals.vat[1],
+            p22a: totals.vat[2],
+        }
+    }
+}
+
+//Output side of the DDV-O, field names follow the numbering on the form. Input VAT and the
+//resulting obligation (fields 31 onwards) come from received invoices and are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DdvO {
+    pub period: Period,
+    //Taxable supplies in Slovenia and exports
+    pub p11: f64,
+    //Supplies to other EU countries
+    pub p12: f64,
+    //Exempt supplies without the right to deduct
+    pub p15: f64,
+    //VAT at 22%, 9.5% and 5%
+    pub p21: f64,
+    pub p22: f64,
+    pub p22a: f64,
+}
+
+impl DdvO {
+    pub fn fields(&self) -> [(&'static str, &'static str, f64); 6] {
+        [
+            ("11", "Dobave blaga in storitev", self.p11),
+            (
+                "12",
+                "Dobave blaga in storitev v druge države članice EU",
+                self.p12,
+            ),
+            (
+                "15",
+                "Oproščene dobave brez pravice do odbitka DDV",
+                self.p15,
+            ),
+            ("21", "DDV po stopnji 22 %", self.p21),
+            ("22", "DDV po stopnji 9,5 %", self.p22),
+            ("22a", "DDV po stopnji 5 %", self.p22a),
+        ]
+    }
+}
+
+fn kir_amounts(racun: &Racun) -> Option<KirAmounts> {
+    let invoice = &racun.invoice;
+    let totals = eur_totals(invoice)?.signed(invoice.sign());
+    let mut amounts = KirAmounts {
+        gross: totals.gross,
+        ..Default::default()
+    };
+    match invoice.vat_treatment {
+        VatTreatment::Domestic => {
+            for line in &totals.vat_lines {
+                if line.rate == 0.0 {
+                    amounts.exempt += line.base;
+                } else {
+                    let column = rate_column(line.rate);
+                    amounts.base[column] += line.base;
+                    amounts.vat[column] += line.vat;
+                }
+            }
+        }
+        VatTreatment::ReverseCharge => amounts.eu_supplies = totals.net,
+        VatTreatment::Export => amounts.export = totals.net,
+        VatTreatment::Oss { .. } => amounts.oss = totals.net,
+        VatTreatment::Exempt { .. } | VatTreatment::NonVatPayer => amounts.exempt = totals.net,
+    }
+    Some(amounts)
+}
+
+//Invoices issued in the period, only the profile's when one is given
+pub fn kir(
+    invoices: &[Racun],
+    period: Period,
+    profile: Option<&CompanyProfile>,
+) -> Result<Kir, InvoiceError> {
+    let mut selected: Vec<&Racun> = invoices
+        .iter()
+        .filter(|racun| period.contains(racun.invoice.invoice_date))
+        .filter(|racun| profile.is_none_or(|profile| profile.owns(racun)))
+        .collect();
+    selected.sort_by_key(|racun| (racun.invoice.invoice_date, racun.invoice.invoice_number));
+    let mut rows = Vec::new();
+    let mut missing_rates = Vec::new();
+    for racun in &selected {
+        let invoice = &racun.invoice;
+        let Some(amounts) = kir_amounts(racun) else {
+            missing_rates.push(format!(
+                "Invoice {} in {} has no exchange rate to EUR",
+                invoice.invoice_number, invoice.invoice_currency
+            ));
+            continue;
+        };
+        let partner = &invoice.partner;
+        rows.push(KirRow {
+            serial: rows.len() + 1,
+            entry_date: invoice.invoice_date,
+            invoice_number: invoice.invoice_number,
+            invoice_date: invoice.invoice_date,
+            service_date: invoice.service_period(),
+            partner_name: partner.partner_name.clone(),
+            partner_address: format!(
+                "{}, {}",
+                partner.partner_address, partner.partner_postal_code
+            ),
+            partner_vat_id: if partner.partner_vat_id.trim().is_empty() {
+                String::new()
+            } else {
+                display_vat_id(&partner.partner_vat_id)
+            },
+            credit_note_for: invoice.credit_note_for,
+            amounts,
+        });
+    }
+    if !missing_rates.is_empty() {
+        return Err(InvoiceError::Validation(missing_rates));
+    }
+    Ok(Kir {
+        period,
+        company: report_company(profile, &selected),
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::ExchangeRate;
+    use crate::invoicer::round2;
+    use crate::test_support::invoice;
+
+    fn may() -> Period {
+        "2024-05".parse().unwrap()
+    }
+
+    fn amounts(racun: Racun) -> KirAmounts {
+        let kir = kir(&[racun], may(), None).unwrap();
+        assert_eq!(kir.rows.len(), 1);
+        kir.rows[0].amounts.clone()
+    }
+
+    //Lines at every KIR rate, an unknown 8 % and an exempt one
+    fn domestic() -> Racun {
+        invoice(
+            1,
+            &[
+                ("", 1.0, 100.0, 22.0),
+                ("", 2.0, 50.0, 9.5),
+                ("", 1.0, 40.0, 5.0),
+                ("", 1.0, 10.0, 8.0),
+                ("", 1.0, 30.0, 0.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn domestic_lines_go_to_the_column_of_their_rate() {
+        let amounts = amounts(domestic());
+        assert_eq!(amounts.base, [100.0, 100.0, 40.0, 10.0]);
+        assert_eq!(amounts.vat, [22.0, 9.5, 2.0, 0.8]);
+        //0 % lines are exempt
+        assert_eq!(amounts.exempt, 30.0);
+        assert_eq!(round2(amounts.gross), 314.3);
+        assert_eq!(amounts.eu_supplies + amounts.export + amounts.oss, 0.0);
+    }
+
+    #[test]
+    fn ddv_o_adds_up_the_kir() {
+        let mut reverse_charge = invoice(2, &[("", 1.0, 1000.0, 22.0)]);
+        reverse_charge.invoice.vat_treatment = VatTreatment::ReverseCharge;
+        reverse_charge.invoice.partner.partner_vat_id = "DE123456789".to_string();
+        let mut export = invoice(3, &[("", 4.0, 125.0, 22.0)]);
+        export.invoice.vat_treatment = VatTreatment::Export;
+        let kir = kir(&[domestic(), reverse_charge, export], may(), None).unwrap();
+        assert_eq!(kir.rows[1].amounts.eu_supplies, 1000.0);
+        assert_eq!(kir.rows[1].amounts.base, [0.0; RATE_COLUMNS]);
+        assert_eq!(kir.rows[1].partner_vat_id, "DE123456789");
+        assert_eq!(kir.rows[2].amounts.export, 500.0);
+        assert_eq!(
+            kir.ddv_o(),
+            DdvO {
+                period: may(),
+                //Every taxable base, the unknown rate's too, and the export
+                p11: 750.0,
+                p12: 1000.0,
+                p15: 30.0,
+                p21: 22.0,
+                p22: 9.5,
+                p22a: 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn credit_notes_are_negative_rows() {
+        let mut credit_note = invoice(4, &[("", 1.0, 100.0, 22.0)]);
+        credit_note.invoice.credit_note_for = Some(1);
+        let kir = kir(&[credit_note], may(), None).unwrap();
+        let row = &kir.rows[0];
+        assert_eq!(row.credit_note_for, Some(1));
+        assert_eq!(row.amounts.base[0], -100.0);
+        assert_eq!(row.amounts.vat[0], -22.0);
+        assert_eq!(row.amounts.gross, -122.0);
+        assert_eq!(kir.ddv_o().p21, -22.0);
+    }
+
+    #[test]
+    fn foreign_currency_invoices_are_converted_at_their_rate() {
+        let mut racun = invoice(5, &[("", 1.0, 200.0, 22.0)]);
+        racun.invoice.set_currency("USD");
+        racun.invoice.exchange_rate = Some(ExchangeRate {
+            currency: "USD".to_string(),
+            date: racun.invoice.invoice_date,
+            rate: 2.0,
+        });
+        let amounts = amounts(racun);
+        assert_eq!(amounts.base[0], 100.0);
+        assert_eq!(amounts.vat[0], 22.0);
+    }
+
+    #[test]
+    fn invoices_without_an_exchange_rate_are_reported() {
+        let mut racun = invoice(6, &[("", 1.0, 100.0, 22.0)]);
+        racun.invoice.set_currency("USD");
+        match kir(&[racun], may(), None) {
+            Err(InvoiceError::Validation(errors)) => {
+                assert_eq!(
+                    errors,
+                    vec!["Invoice 6 in USD has no exchange rate to EUR".to_string()]
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_invoices_of_the_period_are_listed_in_order() {
+        let mut april = invoice(7, &[("", 1.0, 10.0, 22.0)]);
+        april.invoice.invoice_date = crate::dates::parse("2024-04-30").unwrap();
+        let mut late = invoice(8, &[("", 1.0, 10.0, 22.0)]);
+        late.invoice.invoice_date = crate::dates::parse("2024-05-31").unwrap();
+        let early = invoice(9, &[("", 1.0, 10.0, 22.0)]);
+        let kir = kir(&[april, late, early], may(), None).unwrap();
+        let rows: Vec<(usize, i32)> = kir
+            .rows
+            .iter()
+            .map(|row| (row.serial, row.invoice_number))
+            .collect();
+        assert_eq!(rows, vec![(1, 9), (2, 8)]);
+    }
+}
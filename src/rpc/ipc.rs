@@ -0,0 +1,373 @@
+//Discord's local IPC protocol: frames of a little endian opcode and length followed by JSON,
+//over the discord-ipc-N socket on Linux and macOS or the named pipe on Windows.
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+pub const HANDSHAKE: u32 = 0;
+pub const FRAME: u32 = 1;
+pub const CLOSE: u32 = 2;
+pub const PING: u32 = 3;
+pub const PONG: u32 = 4;
+
+//Discord tries the sockets 0 to 9, one per running client
+const SOCKETS: u32 = 10;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(unix)]
+type Socket = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+type Socket = std::fs::File;
+
+//None when the other side closed the connection
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<(u32, Value)>> {
+    let mut header = [0u8; 8];
+    match reader.read_exact(&mut header) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    read_payload(reader, header).map(Some)
+}
+
+fn read_payload(reader: &mut impl Read, header: [u8; 8]) -> io::Result<(u32, Value)> {
+    let opcode = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload)?;
+    let payload = serde_json::from_slice(&payload).unwrap_or(Value::Null);
+    Ok((opcode, payload))
+}
+
+pub fn write_frame(writer: &mut impl Write, opcode: u32, payload: &Value) -> io::Result<()> {
+    let payload = payload.to_string();
+    let mut frame = Vec::with_capacity(payload.len() + 8);
+    frame.extend_from_slice(&opcode.to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload.as_bytes());
+    writer.write_all(&frame)
+}
+
+//Same lookup as the Discord client: XDG_RUNTIME_DIR, TMPDIR, then the temp folder
+#[cfg(unix)]
+pub fn socket_folder() -> std::path::PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .or_else(|| std::env::var_os("TMPDIR"))
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+#[cfg(unix)]
+fn open(index: u32) -> io::Result<Socket> {
+    let socket = Socket::connect(socket_folder().join(format!("discord-ipc-{}", index)))?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.set_write_timeout(Some(TIMEOUT))?;
+    Ok(socket)
+}
+
+#[cfg(windows)]
+fn open(index: u32) -> io::Result<Socket> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(format!(r"\\.\pipe\discord-ipc-{}", index))
+}
+
+fn nonce() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}-{}", now.as_secs(), now.subsec_nanos())
+}
+
+//The message Discord sends with a close frame or an error event
+fn refused(payload: &Value) -> io::Error {
+    let message = payload["message"]
+        .as_str()
+        .or_else(|| payload["data"]["message"].as_str())
+        .unwrap_or("Discord closed the connection");
+    io::Error::new(io::ErrorKind::ConnectionRefused, message.to_string())
+}
+
+pub struct Connection {
+    socket: Socket,
+}
+
+impl Connection {
+    //Connects to the first running Discord client and completes the handshake
+    pub fn open(client_id: u64) -> io::Result<Self> {
+        let mut error = io::Error::new(io::ErrorKind::NotFound, "Discord is not running");
+        for index in 0..SOCKETS {
+            match open(index) {
+                Ok(socket) => {
+                    let mut connection = Self { socket };
+                    connection.handshake(client_id)?;
+                    return Ok(connection);
+                }
+                //A socket file left behind by Discord refuses connections
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                    ) => {}
+                Err(err) => error = err,
+            }
+        }
+        Err(error)
+    }
+
+    fn handshake(&mut self, client_id: u64) -> io::Result<()> {
+        write_frame(
+            &mut self.socket,
+            HANDSHAKE,
+            &json!({ "v": 1, "client_id": client_id.to_string() }),
+        )?;
+        match self.reply()? {
+            (FRAME, payload) if payload["evt"] == "READY" => Ok(()),
+            (_, payload) => Err(refused(&payload)),
+        }
+    }
+
+    //Pings are answered on the way, None closes the connection
+    fn reply(&mut self) -> io::Result<(u32, Value)> {
+        loop {
+            match read_frame(&mut self.socket)? {
+                Some((PING, payload)) => write_frame(&mut self.socket, PONG, &payload)?,
+                Some((CLOSE, payload)) => return Err(refused(&payload)),
+                Some(frame) => return Ok(frame),
+                None => return Err(io::ErrorKind::ConnectionAborted.into()),
+            }
+        }
+    }
+
+    //None clears the activity
+    pub fn set_activity(&mut self, activity: Option<Value>) -> io::Result<()> {
+        let nonce = nonce();
+        write_frame(
+            &mut self.socket,
+            FRAME,
+            &json!({
+                "cmd": "SET_ACTIVITY",
+                "args": { "pid": std::process::id(), "activity": activity },
+                "nonce": nonce,
+            }),
+        )?;
+        loop {
+            let (_, payload) = self.reply()?;
+            if payload["evt"] == "ERROR" {
+                return Err(refused(&payload));
+            }
+            //Events that aren't the answer are skipped
+            if payload["nonce"] == nonce.as_str() {
+                return Ok(());
+            }
+        }
+    }
+
+    //Notices Discord quitting between updates. Named pipes can't be polled, there it shows
+    //with the next update.
+    #[cfg(unix)]
+    pub fn is_open(&mut self) -> bool {
+        let mut header = [0u8; 8];
+        if self.socket.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let read = self.socket.read(&mut header);
+        if self.socket.set_nonblocking(false).is_err() {
+            return false;
+        }
+        match read {
+            Ok(0) => false,
+            //A ping or Discord closing came in between updates
+            Ok(read) => {
+                let frame = self
+                    .socket
+                    .read_exact(&mut header[read..])
+                    .and_then(|_| read_payload(&mut self.socket, header));
+                match frame {
+                    Ok((PING, payload)) => write_frame(&mut self.socket, PONG, &payload).is_ok(),
+                    Ok((opcode, _)) => opcode != CLOSE,
+                    Err(_) => false,
+                }
+            }
+            Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn is_open(&mut self) -> bool {
+        true
+    }
+
+    //Tells Discord the app is leaving so the activity goes away at once
+    pub fn close(mut self) {
+        let _ = write_frame(&mut self.socket, CLOSE, &json!({}));
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::rpc::mock;
+    use crate::test_support::Workspace;
+    use std::{
+        io::Cursor,
+        os::unix::net::UnixListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    type Frames = Arc<Mutex<Vec<(u32, Value)>>>;
+
+    //Discord is looked for in the workspace
+    fn discord_folder(workspace: &Workspace) {
+        std::env::set_var("XDG_RUNTIME_DIR", &workspace.path);
+    }
+
+    //Frames the mock got from the app
+    fn start_mock(workspace: &Workspace) -> Frames {
+        discord_folder(workspace);
+        let listener = mock::bind(&workspace.path).unwrap();
+        let frames: Frames = Arc::default();
+        let received = frames.clone();
+        thread::spawn(move || {
+            mock::listen(listener, move |opcode, payload| {
+                received.lock().unwrap().push((opcode, payload.clone()))
+            })
+        });
+        frames
+    }
+
+    //Discord that answers the handshake, then every SET_ACTIVITY with the given frames
+    fn start_fake(workspace: &Workspace, answer: fn(&Value) -> Vec<(u32, Value)>) {
+        discord_folder(workspace);
+        let listener = UnixListener::bind(workspace.path.join("discord-ipc-0")).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Ok(Some((opcode, payload))) = read_frame(&mut stream) {
+                let frames = match opcode {
+                    HANDSHAKE => vec![(FRAME, json!({ "cmd": "DISPATCH", "evt": "READY" }))],
+                    FRAME => answer(&payload),
+                    _ => Vec::new(),
+                };
+                for (opcode, payload) in frames {
+                    write_frame(&mut stream, opcode, &payload).unwrap();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let payload = json!({ "cmd": "SET_ACTIVITY", "args": { "pid": 1 }, "nonce": "1-2" });
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, FRAME, &payload).unwrap();
+        write_frame(&mut buffer, CLOSE, &json!({})).unwrap();
+        let length = payload.to_string().len() as u32;
+        assert_eq!(buffer[..4], FRAME.to_le_bytes());
+        assert_eq!(buffer[4..8], length.to_le_bytes());
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((FRAME, payload)));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((CLOSE, json!({}))));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn cut_off_frames_are_errors() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, FRAME, &json!({ "cmd": "SET_ACTIVITY" })).unwrap();
+        buffer.truncate(buffer.len() - 3);
+        assert!(read_frame(&mut Cursor::new(buffer)).is_err());
+    }
+
+    #[test]
+    fn activities_are_set_and_cleared() {
+        let workspace = Workspace::new();
+        let frames = start_mock(&workspace);
+        let mut connection = Connection::open(1234).unwrap();
+        let activity = json!({ "details": "Browsing 3 invoices", "timestamps": { "start": 1 } });
+        connection.set_activity(Some(activity.clone())).unwrap();
+        connection.set_activity(None).unwrap();
+        assert!(connection.is_open());
+        connection.close();
+        //The mock reads the close frame on its own thread
+        for _ in 0..100 {
+            if frames.lock().unwrap().len() == 4 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let frames = frames.lock().unwrap();
+        let opcodes: Vec<u32> = frames.iter().map(|(opcode, _)| *opcode).collect();
+        assert_eq!(opcodes, vec![HANDSHAKE, FRAME, FRAME, CLOSE]);
+        assert_eq!(frames[0].1, json!({ "v": 1, "client_id": "1234" }));
+        assert_eq!(frames[1].1["cmd"], "SET_ACTIVITY");
+        assert_eq!(frames[1].1["args"]["pid"], std::process::id());
+        assert_eq!(frames[1].1["args"]["activity"], activity);
+        assert!(frames[2].1["args"]["activity"].is_null());
+        assert_ne!(frames[1].1["nonce"], frames[2].1["nonce"]);
+    }
+
+    #[test]
+    fn answers_are_matched_by_nonce() {
+        let workspace = Workspace::new();
+        start_fake(&workspace, |request| {
+            vec![
+                (PING, json!({ "ping": 1 })),
+                (FRAME, json!({ "evt": "ACTIVITY_JOIN", "nonce": null })),
+                (FRAME, json!({ "cmd": "SET_ACTIVITY", "nonce": "another" })),
+                (
+                    FRAME,
+                    json!({ "cmd": "SET_ACTIVITY", "nonce": request["nonce"] }),
+                ),
+            ]
+        });
+        let mut connection = Connection::open(1).unwrap();
+        connection.set_activity(None).unwrap();
+        connection.set_activity(None).unwrap();
+    }
+
+    #[test]
+    fn errors_from_discord_are_returned() {
+        let workspace = Workspace::new();
+        start_fake(&workspace, |request| {
+            vec![(
+                FRAME,
+                json!({
+                    "evt": "ERROR",
+                    "data": { "code": 4000, "message": "Invalid activity" },
+                    "nonce": request["nonce"],
+                }),
+            )]
+        });
+        let mut connection = Connection::open(1).unwrap();
+        let err = connection.set_activity(Some(json!({}))).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid activity");
+    }
+
+    #[test]
+    fn refused_handshakes_are_errors() {
+        let workspace = Workspace::new();
+        discord_folder(&workspace);
+        let listener = UnixListener::bind(workspace.path.join("discord-ipc-0")).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_frame(&mut stream).unwrap();
+            let refusal = json!({ "code": 4000, "message": "Invalid Client ID" });
+            write_frame(&mut stream, CLOSE, &refusal).unwrap();
+        });
+        let err = Connection::open(1).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(err.to_string(), "Invalid Client ID");
+    }
+
+    #[test]
+    fn without_discord_there_is_nothing_to_connect_to() {
+        let workspace = Workspace::new();
+        discord_folder(&workspace);
+        let err = Connection::open(1).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
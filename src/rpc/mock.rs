@@ -0,0 +1,95 @@
+//Local stand-in for the Discord client's IPC socket. It completes the handshake, answers
+//SET_ACTIVITY and prints every activity it gets. Discord looks for discord-ipc-0 in
+//XDG_RUNTIME_DIR, so start the app with XDG_RUNTIME_DIR set to the mock's folder.
+use std::{
+    fs, io,
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use super::ipc::{read_frame, write_frame, CLOSE, FRAME, HANDSHAKE, PING, PONG};
+
+//Answers one client until it disconnects, every frame it sends goes to `received`
+fn handle(mut stream: UnixStream, mut received: impl FnMut(u32, &Value)) -> io::Result<()> {
+    while let Some((opcode, payload)) = read_frame(&mut stream)? {
+        received(opcode, &payload);
+        match opcode {
+            HANDSHAKE => write_frame(
+                &mut stream,
+                FRAME,
+                &json!({
+                    "cmd": "DISPATCH",
+                    "evt": "READY",
+                    "data": { "v": 1, "user": { "id": "0", "username": "mock" } },
+                    "nonce": null,
+                }),
+            )?,
+            FRAME => write_frame(
+                &mut stream,
+                FRAME,
+                &json!({
+                    "cmd": payload["cmd"],
+                    "data": payload["args"]["activity"],
+                    "evt": null,
+                    "nonce": payload["nonce"],
+                }),
+            )?,
+            PING => write_frame(&mut stream, PONG, &payload)?,
+            CLOSE => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+//Answers every client on its own thread
+pub fn listen(
+    listener: UnixListener,
+    received: impl Fn(u32, &Value) + Clone + Send + 'static,
+) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let received = received.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, received) {
+                eprintln!("Discord IPC mock error: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+//The socket in the folder, one left behind by an earlier run is replaced
+pub fn bind(folder: &Path) -> io::Result<UnixListener> {
+    fs::create_dir_all(folder)?;
+    let path = folder.join("discord-ipc-0");
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    UnixListener::bind(&path)
+}
+
+pub fn serve(folder: &Path) -> io::Result<()> {
+    let listener = bind(folder)?;
+    println!(
+        "Discord IPC mock listening on {}",
+        folder.join("discord-ipc-0").display()
+    );
+    listen(listener, |opcode, payload| {
+        let activity = &payload["args"]["activity"];
+        match (opcode, payload["cmd"].as_str()) {
+            (HANDSHAKE, _) => println!("Handshake from client {}", payload["client_id"]),
+            (FRAME, Some("SET_ACTIVITY")) if activity.is_null() => println!("Activity cleared"),
+            (FRAME, Some("SET_ACTIVITY")) => match activity["state"].as_str() {
+                Some(state) => println!("Activity: {} | {}", activity["details"], state),
+                None => println!("Activity: {}", activity["details"]),
+            },
+            (FRAME, command) => println!("Command {}", command.unwrap_or("without a name")),
+            (CLOSE, _) => println!("Client disconnected"),
+            _ => {}
+        }
+    })
+}
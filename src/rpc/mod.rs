@@ -1,54 +1,574 @@
-use std::{fmt::Debug, fs};
+//Discord Rich Presence. The connection lives on its own thread that tries again with a growing
+//delay while Discord isn't running, the GUI only tells it what the app is doing.
+use std::{
+    fmt::{self, Display},
+    fs::{self, read_to_string},
+    path::Path,
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use discord_rpc_client::Client;
 use serde::{Deserialize, Serialize};
-use serde_json;
+use serde_json::{json, Value};
 
-#[derive(Debug, PartialEq)]
-pub enum Status {
-    CONNECTED,
-    DISCONNECTED,
-    CLEARED,
+use crate::error::InvoiceError;
+use ipc::Connection;
+
+pub mod ipc;
+#[cfg(unix)]
+pub mod mock;
+
+const CONFIG_FILE: &str = "rpc.json";
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+const LAST_RETRY: Duration = Duration::from_secs(120);
+//How often an idle connection is checked
+const POLL: Duration = Duration::from_secs(5);
+
+fn enabled() -> bool {
+    true
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+//Settings of the presence, read from rpc.json and changed in the Discord window
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
-pub struct ClientId {
-    client_id: u64,
+pub struct RpcConfig {
+    #[serde(default)]
+    pub client_id: u64, //Of the Discord application, 0 until one is set
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub privacy: bool, //Hides partner names and amounts
 }
 
-impl ClientId {
-    fn new() -> Self {
-        //fs::read_to_string("rpc.json").expect("Something went wrong reading the file");
-        let content = fs::read_to_string("rpc.json").unwrap();
-        let serialized: ClientId = serde_json::from_str(&content).unwrap();
-        //println!("Client id: {}", serialized.client_id);
+impl Default for RpcConfig {
+    fn default() -> Self {
         Self {
-            client_id: serialized.client_id,
+            client_id: 0,
+            enabled: enabled(),
+            privacy: false,
+        }
+    }
+}
+
+impl RpcConfig {
+    //A missing rpc.json is the same as one without a client ID
+    pub fn load() -> Result<Self, InvoiceError> {
+        if !Path::new(CONFIG_FILE).exists() {
+            return Ok(Self::default());
+        }
+        let data = read_to_string(CONFIG_FILE)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub fn save(&self) -> Result<(), InvoiceError> {
+        fs::write(CONFIG_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn active(&self) -> bool {
+        self.enabled && self.client_id != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Disabled,
+    NotConfigured,
+    Connecting,
+    Connected,
+    //Waiting for the delay before the next attempt
+    Retrying { error: String, delay: Duration },
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Disabled => write!(f, "Off"),
+            Status::NotConfigured => write!(f, "No clientId in rpc.json"),
+            Status::Connecting => write!(f, "Connecting"),
+            Status::Connected => write!(f, "Connected"),
+            Status::Retrying { error, delay } => {
+                write!(f, "{}, trying again in {}s", error, delay.as_secs())
+            }
         }
     }
 }
-impl Debug for DiscordRPC {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("DiscordRPC")
-            .field("status", &self.status)
-            .finish()
+
+//What the app is doing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Presence {
+    Browsing(usize),
+    Creating {
+        partner: String,
+    },
+    Viewing {
+        number: i32,
+        partner: String,
+        amount: String,
+    },
+}
+
+impl Presence {
+    fn details(&self) -> String {
+        match self {
+            Presence::Browsing(1) => "Browsing 1 invoice".to_string(),
+            Presence::Browsing(count) => format!("Browsing {} invoices", count),
+            Presence::Creating { .. } => "Creating invoice".to_string(),
+            Presence::Viewing { number, .. } => format!("Viewing invoice #{}", number),
+        }
+    }
+
+    //Partner and amount, left out in privacy mode
+    fn state(&self) -> Option<String> {
+        let state = match self {
+            Presence::Browsing(_) => return None,
+            Presence::Creating { partner } => partner.trim().to_string(),
+            Presence::Viewing {
+                partner, amount, ..
+            } => [partner.trim(), amount.trim()]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" · "),
+        };
+        (!state.is_empty()).then_some(state)
+    }
+
+    fn activity(&self, privacy: bool, started: u64) -> Value {
+        let mut activity = json!({
+            "details": self.details(),
+            "timestamps": { "start": started },
+        });
+        if let Some(state) = self.state().filter(|_| !privacy) {
+            activity["state"] = json!(state);
+        }
+        activity
     }
 }
 
+enum Update {
+    Presence(Presence),
+    Config(RpcConfig),
+}
+
+//Handle of the presence thread, which stops when this is dropped
 pub struct DiscordRPC {
-    pub client: Client,
-    pub status: Status,
+    sender: Sender<Update>,
+    presence: Option<Presence>,
+    status: Arc<Mutex<Status>>,
+}
+
+impl DiscordRPC {
+    pub fn start(config: RpcConfig) -> Self {
+        let (sender, receiver) = channel();
+        let status = Arc::new(Mutex::new(Status::Disabled));
+        let session = Session::new(config, status.clone());
+        thread::spawn(move || session.run(receiver));
+        Self {
+            sender,
+            presence: None,
+            status,
+        }
+    }
+
+    //Called every frame, only a changed presence is sent
+    pub fn update(&mut self, presence: Presence) {
+        if self.presence.as_ref() == Some(&presence) {
+            return;
+        }
+        self.presence = Some(presence.clone());
+        let _ = self.sender.send(Update::Presence(presence));
+    }
+
+    pub fn configure(&self, config: RpcConfig) {
+        let _ = self.sender.send(Update::Config(config));
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
 }
-pub trait RPC {
-    fn new() -> DiscordRPC;
+
+struct Session {
+    config: RpcConfig,
+    presence: Option<Presence>,
+    connection: Option<Connection>,
+    //Activity Discord was last given
+    shown: Option<Value>,
+    retry: Duration,
+    next_attempt: Instant,
+    started: u64,
+    status: Arc<Mutex<Status>>,
 }
 
-impl RPC for DiscordRPC {
-    fn new() -> Self {
+impl Session {
+    fn new(config: RpcConfig, status: Arc<Mutex<Status>>) -> Self {
         Self {
-            client: Client::new(ClientId::new().client_id),
-            status: Status::CONNECTED,
+            config,
+            presence: None,
+            connection: None,
+            shown: None,
+            retry: FIRST_RETRY,
+            next_attempt: Instant::now(),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            status,
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.config.active()
+    }
+
+    fn run(mut self, receiver: Receiver<Update>) {
+        loop {
+            self.step();
+            let wait = if !self.active() {
+                Duration::MAX
+            } else if self.connection.is_some() {
+                POLL
+            } else {
+                self.next_attempt.saturating_duration_since(Instant::now())
+            };
+            match receiver.recv_timeout(wait) {
+                Ok(update) => {
+                    self.apply(update);
+                    //Changes made while connecting are skipped, only the newest is shown
+                    while let Ok(update) = receiver.try_recv() {
+                        self.apply(update);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Presence(presence) => self.presence = Some(presence),
+            Update::Config(config) => {
+                let previous = std::mem::replace(&mut self.config, config);
+                //Another application or turning it on again connects right away
+                if self.config.client_id != previous.client_id || !previous.active() {
+                    self.disconnect();
+                    self.retry = FIRST_RETRY;
+                    self.next_attempt = Instant::now();
+                }
+            }
+        }
+    }
+
+    fn set_status(&self, status: Status) {
+        *self.status.lock().unwrap_or_else(|err| err.into_inner()) = status;
+    }
+
+    //Clears the activity before leaving so it doesn't stay behind in Discord
+    fn disconnect(&mut self) {
+        if let Some(mut connection) = self.connection.take() {
+            let _ = connection.set_activity(None);
+            connection.close();
+        }
+        self.shown = None;
+    }
+
+    fn failed(&mut self, error: String) {
+        self.connection = None;
+        self.shown = None;
+        self.next_attempt = Instant::now() + self.retry;
+        self.set_status(Status::Retrying {
+            error,
+            delay: self.retry,
+        });
+        self.retry = (self.retry * 2).min(LAST_RETRY);
+    }
+
+    fn step(&mut self) {
+        if !self.active() {
+            self.disconnect();
+            self.set_status(if self.config.enabled {
+                Status::NotConfigured
+            } else {
+                Status::Disabled
+            });
+            return;
+        }
+        if self
+            .connection
+            .as_mut()
+            .is_some_and(|connection| !connection.is_open())
+        {
+            self.failed("Discord closed the connection".to_string());
+        }
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            None if Instant::now() < self.next_attempt => return,
+            None => {
+                self.set_status(Status::Connecting);
+                match Connection::open(self.config.client_id) {
+                    Ok(connection) => {
+                        self.retry = FIRST_RETRY;
+                        self.set_status(Status::Connected);
+                        self.connection.insert(connection)
+                    }
+                    Err(err) => return self.failed(err.to_string()),
+                }
+            }
+        };
+        let activity = self
+            .presence
+            .as_ref()
+            .map(|presence| presence.activity(self.config.privacy, self.started));
+        if activity == self.shown {
+            return;
+        }
+        match connection.set_activity(activity.clone()) {
+            Ok(()) => self.shown = activity,
+            Err(err) => self.failed(err.to_string()),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::Workspace;
+    use ipc::{read_frame, write_frame, CLOSE, FRAME, HANDSHAKE};
+    use std::os::unix::net::UnixListener;
+
+    type Frames = Arc<Mutex<Vec<(u32, Value)>>>;
+
+    fn config(client_id: u64, enabled: bool, privacy: bool) -> RpcConfig {
+        RpcConfig {
+            client_id,
+            enabled,
+            privacy,
+        }
+    }
+
+    fn session(config: RpcConfig) -> Session {
+        Session::new(config, Arc::new(Mutex::new(Status::Disabled)))
+    }
+
+    fn status(session: &Session) -> Status {
+        session.status.lock().unwrap().clone()
+    }
+
+    fn viewing() -> Presence {
+        Presence::Viewing {
+            number: 12,
+            partner: "Acme d.o.o.".to_string(),
+            amount: "185.71 €".to_string(),
+        }
+    }
+
+    //Discord is looked for in the workspace
+    fn discord_folder(workspace: &Workspace) {
+        std::env::set_var("XDG_RUNTIME_DIR", &workspace.path);
+    }
+
+    //Frames the mock got from the app
+    fn start_mock(workspace: &Workspace) -> Frames {
+        discord_folder(workspace);
+        let listener = mock::bind(&workspace.path).unwrap();
+        let frames: Frames = Arc::default();
+        let received = frames.clone();
+        thread::spawn(move || {
+            mock::listen(listener, move |opcode, payload| {
+                received.lock().unwrap().push((opcode, payload.clone()))
+            })
+        });
+        frames
+    }
+
+    //The mock reads on its own thread
+    fn wait_for(frames: &Frames, count: usize) -> Vec<(u32, Value)> {
+        for _ in 0..200 {
+            if frames.lock().unwrap().len() >= count {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        frames.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn presence_is_off_without_a_client_id_or_when_disabled() {
+        let mut disabled = session(config(42, false, false));
+        disabled.step();
+        assert_eq!(status(&disabled), Status::Disabled);
+        assert_eq!(status(&disabled).to_string(), "Off");
+        let mut unconfigured = session(config(0, true, false));
+        unconfigured.step();
+        assert_eq!(status(&unconfigured), Status::NotConfigured);
+    }
+
+    #[test]
+    fn retries_back_off_from_two_seconds_to_two_minutes() {
+        let workspace = Workspace::new();
+        discord_folder(&workspace);
+        let mut session = session(config(42, true, false));
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            session.next_attempt = Instant::now();
+            session.step();
+            let Status::Retrying { error, delay } = status(&session) else {
+                panic!("{:?}", status(&session));
+            };
+            assert_eq!(error, "Discord is not running");
+            delays.push(delay.as_secs());
+        }
+        assert_eq!(delays, vec![2, 4, 8, 16, 32, 64, 120, 120]);
+        //Nothing is tried before the delay is over
+        let waiting = session.next_attempt;
+        session.step();
+        assert_eq!(session.next_attempt, waiting);
+    }
+
+    #[test]
+    fn connecting_is_shown_until_the_handshake_is_done() {
+        let workspace = Workspace::new();
+        discord_folder(&workspace);
+        let listener = UnixListener::bind(workspace.path.join("discord-ipc-0")).unwrap();
+        let (answer, handshake) = channel::<()>();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_frame(&mut stream).unwrap();
+            handshake.recv().unwrap();
+            let ready = json!({ "cmd": "DISPATCH", "evt": "READY" });
+            write_frame(&mut stream, FRAME, &ready).unwrap();
+            //Kept open until the session is done
+            while let Ok(Some(_)) = read_frame(&mut stream) {}
+        });
+        let mut session = session(config(42, true, false));
+        let status = session.status.clone();
+        let connecting = thread::spawn(move || {
+            session.step();
+            session
+        });
+        for _ in 0..200 {
+            if *status.lock().unwrap() == Status::Connecting {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
         }
+        assert_eq!(*status.lock().unwrap(), Status::Connecting);
+        answer.send(()).unwrap();
+        let session = connecting.join().unwrap();
+        assert_eq!(*status.lock().unwrap(), Status::Connected);
+        assert!(session.connection.is_some());
+    }
+
+    #[test]
+    fn connected_sessions_show_the_presence() {
+        let workspace = Workspace::new();
+        discord_folder(&workspace);
+        let mut session = session(config(42, true, false));
+        session.step();
+        assert!(matches!(status(&session), Status::Retrying { .. }));
+        let frames = start_mock(&workspace);
+        session.apply(Update::Presence(viewing()));
+        session.next_attempt = Instant::now();
+        session.step();
+        assert_eq!(status(&session), Status::Connected);
+        assert_eq!(session.retry, FIRST_RETRY);
+        let received = wait_for(&frames, 2);
+        assert_eq!(received[0].1["client_id"], "42");
+        let activity = &received[1].1["args"]["activity"];
+        assert_eq!(activity["details"], "Viewing invoice #12");
+        assert_eq!(activity["state"], "Acme d.o.o. · 185.71 €");
+        //An unchanged presence isn't sent again
+        session.step();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(frames.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn privacy_mode_hides_partner_and_amount() {
+        let shown = viewing().activity(false, 7);
+        assert_eq!(shown["state"], "Acme d.o.o. · 185.71 €");
+        let private = viewing().activity(true, 7);
+        assert_eq!(
+            private,
+            json!({ "details": "Viewing invoice #12", "timestamps": { "start": 7 } })
+        );
+        let creating = Presence::Creating {
+            partner: " ".to_string(),
+        };
+        assert!(creating.activity(false, 7).get("state").is_none());
+        assert_eq!(Presence::Browsing(1).details(), "Browsing 1 invoice");
+        assert_eq!(Presence::Browsing(3).details(), "Browsing 3 invoices");
+    }
+
+    #[test]
+    fn turning_privacy_on_updates_the_shown_activity() {
+        let workspace = Workspace::new();
+        let frames = start_mock(&workspace);
+        let mut session = session(config(42, true, false));
+        session.apply(Update::Presence(viewing()));
+        session.step();
+        session.apply(Update::Config(config(42, true, true)));
+        session.step();
+        //The same connection, only the activity changed
+        let received = wait_for(&frames, 3);
+        let opcodes: Vec<u32> = received.iter().map(|(opcode, _)| *opcode).collect();
+        assert_eq!(opcodes, vec![HANDSHAKE, FRAME, FRAME]);
+        let activity = &received[2].1["args"]["activity"];
+        assert_eq!(activity["details"], "Viewing invoice #12");
+        assert!(activity.get("state").is_none());
+    }
+
+    #[test]
+    fn config_changes_compare_against_the_previous_config() {
+        let workspace = Workspace::new();
+        let frames = start_mock(&workspace);
+        let mut session = session(config(42, true, false));
+        session.step();
+        assert_eq!(status(&session), Status::Connected);
+
+        //Another application clears the activity and connects again right away
+        session.retry = Duration::from_secs(32);
+        session.apply(Update::Config(config(43, true, false)));
+        assert!(session.connection.is_none());
+        assert_eq!(session.retry, FIRST_RETRY);
+        assert!(session.next_attempt <= Instant::now());
+        let received = wait_for(&frames, 3);
+        assert!(received[1].1["args"]["activity"].is_null());
+        assert_eq!(received[2].0, CLOSE);
+        session.step();
+        assert_eq!(status(&session), Status::Connected);
+        assert_eq!(wait_for(&frames, 4)[3].1["client_id"], "43");
+
+        //Turning it off disconnects
+        session.apply(Update::Config(config(43, false, false)));
+        session.step();
+        assert_eq!(status(&session), Status::Disabled);
+        assert!(session.connection.is_none());
+
+        //Turning it on again skips the wait left from earlier failures
+        session.retry = Duration::from_secs(64);
+        session.next_attempt = Instant::now() + Duration::from_secs(64);
+        session.apply(Update::Config(config(43, true, false)));
+        assert_eq!(session.retry, FIRST_RETRY);
+        assert!(session.next_attempt <= Instant::now());
+        session.step();
+        assert_eq!(status(&session), Status::Connected);
+
+        //The same application stays connected
+        session.retry = Duration::from_secs(8);
+        session.apply(Update::Config(config(43, true, false)));
+        assert!(session.connection.is_some());
+        assert_eq!(session.retry, Duration::from_secs(8));
     }
 }
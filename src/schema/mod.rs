@@ -0,0 +1,210 @@
+//Stored invoices carry a schemaVersion. Documents are upgraded as plain JSON one version at
+//a time before serde sees them, so a change to Invoice only needs a new migration at the end
+//of MIGRATIONS instead of breaking every output.json written before it.
+use schemars::gen::SchemaSettings;
+use serde::Serializer;
+use serde_json::Value;
+
+use crate::currency;
+use crate::dates;
+use crate::error::InvoiceError;
+use crate::invoicer::Racun;
+
+//Version 1 is the original data.json shape without a schemaVersion field
+pub const SCHEMA_VERSION: u32 = 3;
+
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+//MIGRATIONS[n] upgrades a version n + 1 document to version n + 2
+const MIGRATIONS: [Migration; 2] = [iso_dates, iso_currencies];
+
+//Documents without the field are from before versioning
+pub fn version(value: &Value) -> u32 {
+    value
+        .get("schemaVersion")
+        .and_then(Value::as_u64)
+        .map_or(1, |version| (version as u32).max(1))
+}
+
+pub fn upgrade(mut value: Value) -> Result<Value, InvoiceError> {
+    let version = version(&value);
+    if version > SCHEMA_VERSION {
+        return Err(InvoiceError::validation(format!(
+            "Invoice has schema version {}, this version of the app reads up to {}",
+            version, SCHEMA_VERSION
+        )));
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        migration(&mut value).map_err(|err| {
+            InvoiceError::validation(format!(
+                "Upgrading from schema version {}: {}",
+                from + 1,
+                err
+            ))
+        })?;
+    }
+    if let Some(object) = value.as_object_mut() {
+        object.insert("schemaVersion".to_string(), SCHEMA_VERSION.into());
+    }
+    Ok(value)
+}
+
+pub fn from_value(value: Value) -> Result<Racun, InvoiceError> {
+    Ok(serde_json::from_value(upgrade(value)?)?)
+}
+
+pub fn from_str(data: &str) -> Result<Racun, InvoiceError> {
+    from_value(serde_json::from_str(data)?)
+}
+
+//For #[serde(serialize_with)] on Racun::schema_version
+pub fn write_version<S: Serializer>(_: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u32(SCHEMA_VERSION)
+}
+
+//JSON Schema of the current version, published as invoice.schema.json
+pub fn json_schema() -> Value {
+    let schema = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Racun>();
+    serde_json::to_value(schema).unwrap_or_default()
+}
+
+//Definitions of Racun and everything in it for the components of openapi.json
+pub fn openapi_schemas() -> serde_json::Map<String, Value> {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    generator.subschema_for::<Racun>();
+    generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap_or_default()))
+        .collect()
+}
+
+//Version 1 to 2: dates like 21.05.2022 or d/m/y become ISO dates
+fn iso_dates(value: &mut Value) -> Result<(), String> {
+    let Some(invoice) = value.get_mut("invoice").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    for field in ["invoiceDate", "serviceDate", "servicePeriodEnd", "dueDate"] {
+        let Some(date) = invoice.get_mut(field) else {
+            continue;
+        };
+        let Some(text) = date.as_str() else {
+            continue;
+        };
+        if text.trim().is_empty() {
+            continue;
+        }
+        let parsed = dates::parse(text).ok_or_else(|| format!("'{}' is not a date", text))?;
+        *date = Value::String(parsed.to_string());
+    }
+    Ok(())
+}
+
+//Version 2 to 3: currencies like € or EURO become ISO 4217 codes, unknown ones are left for validation
+fn iso_currencies(value: &mut Value) -> Result<(), String> {
+    let Some(invoice) = value.get_mut("invoice").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    let mut fields: Vec<&mut Value> = Vec::new();
+    for (key, field) in invoice.iter_mut() {
+        match key.as_str() {
+            "invoiceCurrency" => fields.push(field),
+            "company" => fields.extend(field.get_mut("companyCurrency")),
+            "partner" => fields.extend(field.get_mut("partnerCurrency")),
+            "services" => {
+                for service in field.as_array_mut().into_iter().flatten() {
+                    fields.extend(service.get_mut("serviceCurrency"));
+                }
+            }
+            _ => {}
+        }
+    }
+    for field in fields {
+        if let Some(code) = field.as_str().and_then(currency::normalize) {
+            *field = Value::String(code.to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Documents as each version wrote them and what they look like after upgrading
+    const FIXTURES: [(&str, &str, &str); 3] = [
+        (
+            "v1",
+            include_str!("fixtures/v1.json"),
+            include_str!("fixtures/v1.expected.json"),
+        ),
+        (
+            "v2",
+            include_str!("fixtures/v2.json"),
+            include_str!("fixtures/v2.expected.json"),
+        ),
+        (
+            "v3",
+            include_str!("fixtures/v3.json"),
+            include_str!("fixtures/v3.expected.json"),
+        ),
+    ];
+
+    fn parse(data: &str) -> Value {
+        serde_json::from_str(data).unwrap()
+    }
+
+    #[test]
+    fn fixtures_have_their_version() {
+        for (index, (name, fixture, _)) in FIXTURES.iter().enumerate() {
+            assert_eq!(version(&parse(fixture)), index as u32 + 1, "{}", name);
+        }
+        assert_eq!(FIXTURES.len(), SCHEMA_VERSION as usize);
+    }
+
+    #[test]
+    fn fixtures_upgrade_to_the_expected_documents() {
+        for (name, fixture, expected) in FIXTURES {
+            let upgraded = upgrade(parse(fixture)).unwrap();
+            assert_eq!(upgraded, parse(expected), "{}", name);
+            assert_eq!(version(&upgraded), SCHEMA_VERSION, "{}", name);
+        }
+    }
+
+    #[test]
+    fn fixtures_are_read_as_invoices() {
+        for (name, fixture, _) in FIXTURES {
+            let racun = from_str(fixture).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(racun.invoice.invoice_number, 12, "{}", name);
+            assert_eq!(
+                racun.invoice.invoice_date.to_string(),
+                "2022-05-21",
+                "{}",
+                name
+            );
+            assert_eq!(racun.invoice.company.company_currency, "EUR", "{}", name);
+        }
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let mut document = parse(FIXTURES[2].1);
+        document["schemaVersion"] = (SCHEMA_VERSION + 1).into();
+        assert!(matches!(
+            upgrade(document),
+            Err(InvoiceError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn broken_dates_name_the_version_they_failed_in() {
+        let mut document = parse(FIXTURES[0].1);
+        document["invoice"]["invoiceDate"] = "someday".into();
+        let Err(InvoiceError::Validation(errors)) = upgrade(document) else {
+            panic!("the date was accepted");
+        };
+        assert!(errors[0].contains("schema version 1"), "{:?}", errors);
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    env,
+    fs::{self, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use crate::error::InvoiceError;
+use crate::invoicer::Racun;
+use crate::schema::{self, SCHEMA_VERSION};
+
+//Every invoice lives in invoices/<number>/ with its output.json, pdf and jpg
+pub fn invoices_dir() -> PathBuf {
+    env::current_dir()
+        .unwrap_or_else(|_| PathBuf::from("."))
+        .join("invoices")
+}
+
+pub fn invoice_dir(invoice_number: i32) -> PathBuf {
+    invoices_dir().join(invoice_number.to_string())
+}
+
+pub fn json_path(invoice_number: i32) -> PathBuf {
+    invoice_dir(invoice_number).join("output.json")
+}
+
+//Invoice number of anything inside invoices/<number>/
+pub fn number_from_path(path: &Path) -> Option<i32> {
+    path.strip_prefix(invoices_dir())
+        .ok()?
+        .components()
+        .next()?
+        .as_os_str()
+        .to_str()?
+        .parse()
+        .ok()
+}
+
+pub fn pdf_path(invoice_number: i32) -> PathBuf {
+    invoice_dir(invoice_number).join(format!("racun {}.pdf", invoice_number))
+}
+
+//Preview rendered from the pdf, missing when pdfium isn't installed
+pub fn image_path(invoice_number: i32) -> PathBuf {
+    invoice_dir(invoice_number).join(format!("račun {}.jpg", invoice_number))
+}
+
+//Every attempt to email the invoice, see email::SentRecord
+pub fn sent_log_path(invoice_number: i32) -> PathBuf {
+    invoice_dir(invoice_number).join("sent.json")
+}
+
+pub fn exists(invoice_number: i32) -> bool {
+    invoice_dir(invoice_number).exists()
+}
+
+pub fn load(invoice_number: i32) -> Result<Racun, InvoiceError> {
+    if !exists(invoice_number) {
+        return Err(InvoiceError::NotFound(invoice_number));
+    }
+    let data = read_to_string(json_path(invoice_number))?;
+    schema::from_str(&data)
+}
+
+//Written next to output.json and renamed over it, readers never see half of a file
+pub fn save(racun: &Racun) -> Result<(), InvoiceError> {
+    let path = json_path(racun.invoice.invoice_number);
+    let temporary = path.with_extension("json.tmp");
+    fs::create_dir_all(invoice_dir(racun.invoice.invoice_number))?;
+    fs::write(&temporary, serde_json::to_string(racun)?)?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+//All stored invoices sorted by number with the folders that couldn't be read.
+//Folders without output.json are still being written and are skipped quietly.
+pub fn scan() -> (Vec<Racun>, Vec<(PathBuf, InvoiceError)>) {
+    let entries = match fs::read_dir(invoices_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return (Vec::new(), Vec::new()),
+    };
+    let mut invoices = Vec::new();
+    let mut errors = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path().join("output.json");
+        if !path.exists() {
+            continue;
+        }
+        let parsed = read_to_string(&path)
+            .map_err(InvoiceError::from)
+            .and_then(|data| schema::from_str(&data));
+        match parsed {
+            Ok(racun) => invoices.push(racun),
+            Err(err) => errors.push((path, err)),
+        }
+    }
+    invoices.sort_by_key(|racun: &Racun| racun.invoice.invoice_number);
+    (invoices, errors)
+}
+
+//Like scan without the broken invoices
+pub fn load_all() -> Vec<Racun> {
+    scan().0
+}
+
+pub fn delete(invoice_number: i32) -> Result<(), InvoiceError> {
+    let dir = invoice_dir(invoice_number);
+    if !dir.exists() {
+        return Err(InvoiceError::NotFound(invoice_number));
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+//Rewrites stored invoices from older schema versions in the current one and returns
+//how many were changed. Invoices that can't be read are left for scan to report.
+pub fn migrate() -> Result<usize, InvoiceError> {
+    let entries = match fs::read_dir(invoices_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(0),
+    };
+    let mut migrated = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path().join("output.json");
+        let Ok(data) = read_to_string(&path) else {
+            continue;
+        };
+        let Ok(value) = serde_json::from_str::<serde_json::Value>(&data) else {
+            continue;
+        };
+        if schema::version(&value) == SCHEMA_VERSION {
+            continue;
+        }
+        let Ok(racun) = schema::from_value(value) else {
+            continue;
+        };
+        save(&racun)?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{sample, Workspace};
+
+    fn store(invoice_number: i32, data: &str) {
+        fs::create_dir_all(invoice_dir(invoice_number)).unwrap();
+        fs::write(json_path(invoice_number), data).unwrap();
+    }
+
+    #[test]
+    fn migrate_upgrades_old_invoices_in_place() {
+        let _workspace = Workspace::new();
+        store(12, include_str!("../schema/fixtures/v1.json"));
+        store(13, "{");
+        let mut current = sample();
+        current.invoice.invoice_number = 14;
+        save(&current).unwrap();
+        let written = read_to_string(json_path(14)).unwrap();
+
+        assert_eq!(migrate().unwrap(), 1);
+        let value: serde_json::Value =
+            serde_json::from_str(&read_to_string(json_path(12)).unwrap()).unwrap();
+        assert_eq!(schema::version(&value), SCHEMA_VERSION);
+        assert_eq!(load(12).unwrap().invoice.invoice_number, 12);
+        //Written through a temporary file that is renamed over output.json
+        assert!(!json_path(12).with_extension("json.tmp").exists());
+        //Broken and current invoices are left alone
+        assert_eq!(read_to_string(json_path(13)).unwrap(), "{");
+        assert_eq!(read_to_string(json_path(14)).unwrap(), written);
+        assert_eq!(migrate().unwrap(), 0);
+    }
+}
@@ -0,0 +1,192 @@
+//Helpers shared by the tests. Invoices and config files live relative to the working
+//directory, so tests that touch them run one at a time in a folder of their own.
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+use crate::invoicer::{PaymentStatus, Racun};
+use crate::schema;
+
+static WORKING_DIRECTORY: Mutex<()> = Mutex::new(());
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+//The invoice from data.json
+pub fn sample() -> Racun {
+    let data = fs::read_to_string(manifest_dir().join("data.json")).unwrap();
+    let mut racun = schema::from_str(&data).unwrap();
+    racun.prepare().unwrap();
+    racun
+}
+
+//Unpaid sample invoice of 10 May 2024 with lines of (code, quantity, price, rate)
+pub fn invoice(number: i32, lines: &[(&str, f64, f64, f64)]) -> Racun {
+    let mut racun = sample();
+    let invoice = &mut racun.invoice;
+    invoice.invoice_number = number;
+    invoice.invoice_date = crate::dates::parse("2024-05-10").unwrap();
+    invoice.status = PaymentStatus::UNPAID;
+    invoice.paid_date = None;
+    let template = invoice.services[0].clone();
+    invoice.services = lines
+        .iter()
+        .map(|&(code, quantity, price, rate)| {
+            let mut service = template.clone();
+            service.service_code = code.to_string();
+            service.service_quantity = quantity;
+            service.service_price = price;
+            service.service_tax = rate;
+            service
+        })
+        .collect();
+    racun
+}
+
+//Empty temporary folder that is the working directory until it is dropped
+pub struct Workspace {
+    pub path: PathBuf,
+    previous: PathBuf,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        let guard = WORKING_DIRECTORY
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let path = env::temp_dir().join(format!(
+            "rust_pdf_test_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        let previous = env::current_dir().unwrap();
+        env::set_current_dir(&path).unwrap();
+        Self {
+            path,
+            previous,
+            _guard: guard,
+        }
+    }
+
+    //With the fonts the PDF is rendered with
+    pub fn with_fonts() -> Self {
+        let workspace = Self::new();
+        fs::create_dir_all("fonts").unwrap();
+        for entry in fs::read_dir(manifest_dir().join("fonts")).unwrap() {
+            let entry = entry.unwrap();
+            fs::copy(entry.path(), Path::new("fonts").join(entry.file_name())).unwrap();
+        }
+        workspace
+    }
+
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) {
+        fs::write(self.path.join(name), contents).unwrap();
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.previous);
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+//A certificate and a running mock of the FURS cash register service
+pub mod furs {
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkcs12::Pkcs12,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{X509NameBuilder, X509},
+    };
+    use serde_json::Value;
+    use std::{
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use crate::fiscal::{mock, FiscalConfig};
+
+    const PASSWORD: &str = "secret";
+
+    //Self signed PKCS#12 certificate like the one FURS issues
+    pub fn certificate() -> Vec<u8> {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("C", "SI").unwrap();
+        name.append_entry_by_text("O", "TEST").unwrap();
+        name.append_entry_by_text("CN", "12345678-1").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(365).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        Pkcs12::builder()
+            .name("test")
+            .pkey(&key)
+            .cert(&builder.build())
+            .build2(PASSWORD)
+            .unwrap()
+            .to_der()
+            .unwrap()
+    }
+
+    //Config for furs.p12 in the working directory that talks to the given address
+    pub fn config(address: &str) -> FiscalConfig {
+        FiscalConfig {
+            endpoint: format!("http://{}/v1/cash_registers", address),
+            certificate_path: "furs.p12".to_string(),
+            certificate_password: PASSWORD.to_string(),
+            tax_number: 12345678,
+            business_premise_id: "PP1".to_string(),
+            electronic_device_id: "B1".to_string(),
+            timeout_secs: 3,
+            ..Default::default()
+        }
+    }
+
+    //Address of a running mock and the payloads it received
+    pub fn mock() -> (String, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let requests = received.clone();
+        thread::spawn(move || {
+            mock::listen(listener, move |payload| {
+                requests.lock().unwrap().push(payload.clone())
+            })
+        });
+        (address, received)
+    }
+
+    //Nothing listens there, so FURS is offline
+    pub fn offline() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+}
@@ -0,0 +1,565 @@
+use std::fmt::Display;
+
+use crate::currency;
+use crate::dates;
+use crate::error::InvoiceError;
+use crate::invoicer::{vat_id_country, Discount, Invoice, PaymentMethod, Racun, Service};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,   //The invoice can't be issued
+    Warning, //Unusual but allowed
+}
+
+//One problem with a field, the path uses the JSON names like invoice.services[0].serviceQuantity
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub path: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn push(&mut self, path: impl ToString, severity: Severity, message: impl ToString) {
+        self.issues.push(Issue {
+            path: path.to_string(),
+            severity,
+            message: message.to_string(),
+        });
+    }
+
+    fn error(&mut self, path: impl ToString, message: impl ToString) {
+        self.push(path, Severity::Error, message);
+    }
+
+    fn warning(&mut self, path: impl ToString, message: impl ToString) {
+        self.push(path, Severity::Warning, message);
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Issue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Warning)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    //Errors become InvoiceError::Validation, warnings don't stop anything
+    pub fn check(&self) -> Result<(), InvoiceError> {
+        let errors: Vec<String> = self.errors().map(|issue| issue.to_string()).collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(InvoiceError::Validation(errors))
+        }
+    }
+}
+
+pub fn validate(racun: &Racun) -> Report {
+    let mut report = Report::default();
+    let invoice = &racun.invoice;
+    if invoice.invoice_number <= 0 {
+        report.error("invoice.invoiceNumber", "Invoice number must be positive");
+    }
+    if let Some(original) = invoice.credit_note_for {
+        if original <= 0 || original == invoice.invoice_number {
+            report.error(
+                "invoice.creditNoteFor",
+                "Credit note must refer to another invoice",
+            );
+        }
+    }
+    check_dates(invoice, &mut report);
+    check_currency(invoice, &mut report);
+    for issue in invoice.vat_issues() {
+        report.error("invoice.vatTreatment", issue);
+    }
+    check_company(invoice, &mut report);
+    check_partner(invoice, &mut report);
+    if invoice.services.is_empty() {
+        report.error("invoice.services", "Invoice has no services");
+    }
+    for (index, service) in invoice.services.iter().enumerate() {
+        check_service(
+            invoice,
+            service,
+            &format!("invoice.services[{}]", index),
+            &mut report,
+        );
+    }
+    if let Some(discount) = &invoice.invoice_discount {
+        let lines_net: f64 = invoice.services.iter().map(|s| s.net_amount()).sum();
+        check_discount(discount, lines_net, "invoice.invoiceDiscount", &mut report);
+    }
+    report
+}
+
+fn check_dates(invoice: &Invoice, report: &mut Report) {
+    if invoice.due_date < invoice.invoice_date {
+        report.error("invoice.dueDate", "Due date is before the invoice date");
+    }
+    if let Some(end) = invoice.service_period_end {
+        if end < invoice.service_date {
+            report.error(
+                "invoice.servicePeriodEnd",
+                "Service period ends before it starts",
+            );
+        }
+    }
+    if invoice.service_date > invoice.invoice_date {
+        report.warning(
+            "invoice.serviceDate",
+            "Service date is after the invoice date, is this an advance invoice?",
+        );
+    }
+}
+
+fn check_currency(invoice: &Invoice, report: &mut Report) {
+    if !currency::is_known(&invoice.invoice_currency) {
+        report.error(
+            "invoice.invoiceCurrency",
+            format!(
+                "{} is not an ISO 4217 currency code like EUR",
+                invoice.invoice_currency
+            ),
+        );
+        return;
+    }
+    if invoice.invoice_currency == currency::EUR {
+        return;
+    }
+    //The VAT summary has to be converted to EUR
+    match &invoice.exchange_rate {
+        None => report.error(
+            "invoice.exchangeRate",
+            format!(
+                "No EUR exchange rate for {} on {}, import the ECB rates first",
+                invoice.invoice_currency,
+                dates::format(invoice.invoice_date)
+            ),
+        ),
+        Some(rate) if rate.currency != invoice.invoice_currency => report.error(
+            "invoice.exchangeRate.currency",
+            format!(
+                "Exchange rate is for {}, the invoice is in {}",
+                rate.currency, invoice.invoice_currency
+            ),
+        ),
+        Some(rate) if rate.rate <= 0.0 => report.error(
+            "invoice.exchangeRate.rate",
+            "Exchange rate must be positive",
+        ),
+        Some(rate) if rate.date > invoice.invoice_date => report.warning(
+            "invoice.exchangeRate.date",
+            "Exchange rate is from after the invoice date",
+        ),
+        Some(_) => {}
+    }
+}
+
+fn check_company(invoice: &Invoice, report: &mut Report) {
+    let company = &invoice.company;
+    if !company.company_currency.is_empty() && !currency::is_known(&company.company_currency) {
+        report.warning(
+            "invoice.company.companyCurrency",
+            format!(
+                "{} is not an ISO 4217 currency code",
+                company.company_currency
+            ),
+        );
+    }
+    if company.company_name.trim().is_empty() {
+        report.error("invoice.company.companyName", "Missing company name");
+    }
+    if let Err(message) = check_vat_id(&company.company_vat_id) {
+        report.error("invoice.company.companyVatId", message);
+    }
+    if company.company_iban.trim().is_empty() {
+        if invoice.payment_method == PaymentMethod::BankTransfer {
+            report.error(
+                "invoice.company.companyIban",
+                "Bank transfers need the company's IBAN",
+            );
+        }
+    } else if !iban_valid(&company.company_iban) {
+        report.error("invoice.company.companyIban", "Invalid IBAN");
+    }
+    if !company.company_swift.trim().is_empty() && !bic_valid(&company.company_swift) {
+        report.warning(
+            "invoice.company.companySwift",
+            "SWIFT/BIC should have 8 or 11 characters like LJBASI2X",
+        );
+    }
+}
+
+fn check_partner(invoice: &Invoice, report: &mut Report) {
+    let partner = &invoice.partner;
+    if partner.partner_name.trim().is_empty() {
+        report.error("invoice.partner.partnerName", "Missing partner name");
+    }
+    if partner.partner_address.trim().is_empty() {
+        report.warning("invoice.partner.partnerAddress", "Missing partner address");
+    }
+    //Consumers don't have a VAT ID
+    if !partner.partner_vat_id.trim().is_empty() {
+        if let Err(message) = check_vat_id(&partner.partner_vat_id) {
+            report.error("invoice.partner.partnerVatId", message);
+        }
+    }
+}
+
+fn check_service(invoice: &Invoice, service: &Service, path: &str, report: &mut Report) {
+    if service.service_name.trim().is_empty() {
+        report.error(format!("{}.serviceName", path), "Missing description");
+    }
+    if service.service_quantity < 0.0 {
+        report.error(
+            format!("{}.serviceQuantity", path),
+            "Negative quantity, issue a credit note instead",
+        );
+    } else if service.service_quantity == 0.0 {
+        report.warning(format!("{}.serviceQuantity", path), "Quantity is 0");
+    }
+    if service.service_price < 0.0 {
+        report.warning(format!("{}.servicePrice", path), "Negative price");
+    }
+    if !(0.0..=100.0).contains(&service.service_tax) {
+        report.error(
+            format!("{}.serviceTax", path),
+            "VAT rate must be between 0 and 100%",
+        );
+    }
+    if !service.service_currency.is_empty() && service.service_currency != invoice.invoice_currency
+    {
+        report.error(
+            format!("{}.serviceCurrency", path),
+            format!(
+                "Currency {} doesn't match the invoice currency {}",
+                service.service_currency, invoice.invoice_currency
+            ),
+        );
+    }
+    if let Some(discount) = &service.service_discount {
+        check_discount(
+            discount,
+            service.amount(),
+            &format!("{}.serviceDiscount", path),
+            report,
+        );
+    }
+}
+
+fn check_discount(discount: &Discount, amount: f64, path: &str, report: &mut Report) {
+    match *discount {
+        Discount::Percent(percent) if !(0.0..=100.0).contains(&percent) => {
+            report.error(path, "Discount must be between 0 and 100%");
+        }
+        Discount::Amount(value) if value < 0.0 => {
+            report.error(path, "Discount can't be negative");
+        }
+        Discount::Amount(value) if value > amount => {
+            report.warning(path, "Discount is larger than the amount, it's capped");
+        }
+        _ => {}
+    }
+}
+
+//Spaces and dots are common when VAT IDs and IBANs are copied from documents
+fn normalize(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+//Slovenian IDs are checked with the checksum, other EU IDs by the VIES format
+fn check_vat_id(vat_id: &str) -> Result<(), String> {
+    let vat_id = normalize(vat_id);
+    if vat_id.is_empty() {
+        return Err("Missing VAT ID".to_string());
+    }
+    match vat_id_country(&vat_id) {
+        None | Some("SI") => {
+            let digits = vat_id.trim_start_matches("SI");
+            if si_vat_id_valid(digits) {
+                Ok(())
+            } else {
+                Err(format!("{} is not a valid Slovenian VAT ID", vat_id))
+            }
+        }
+        Some(country) => match vies_patterns(country) {
+            Some(patterns) => {
+                let number = &vat_id[2..];
+                if patterns
+                    .iter()
+                    .any(|pattern| matches_pattern(number, pattern))
+                {
+                    Ok(())
+                } else {
+                    Err(format!("{} doesn't look like a {} VAT ID", vat_id, country))
+                }
+            }
+            //IDs from outside of the EU can't be checked
+            None => Ok(()),
+        },
+    }
+}
+
+//8 digits, the last one is mod 11 of the first seven weighted 8 to 2
+pub fn si_vat_id_valid(digits: &str) -> bool {
+    if digits.len() != 8 || !digits.bytes().all(|b| b.is_ascii_digit()) || digits.starts_with('0') {
+        return false;
+    }
+    let digits: Vec<u32> = digits.bytes().map(|b| (b - b'0') as u32).collect();
+    let sum: u32 = digits[..7]
+        .iter()
+        .zip((2..=8).rev())
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+    let check = match 11 - sum % 11 {
+        10 => 0,
+        11 => return false,
+        check => check,
+    };
+    check == digits[7]
+}
+
+//Country code, two check digits and the account, mod 97 of the rearranged number must be 1
+pub fn iban_valid(iban: &str) -> bool {
+    let iban = normalize(iban);
+    if iban.len() < 15 || iban.len() > 34 {
+        return false;
+    }
+    let (country, check) = (&iban[..2], &iban[2..4]);
+    if !country.bytes().all(|b| b.is_ascii_uppercase())
+        || !check.bytes().all(|b| b.is_ascii_digit())
+    {
+        return false;
+    }
+    if iban_length(country).is_some_and(|length| length != iban.len()) {
+        return false;
+    }
+    let rearranged = iban[4..].chars().chain(iban[..4].chars());
+    let mut remainder = 0u32;
+    for c in rearranged {
+        let value = match c.to_digit(36) {
+            Some(value) => value,
+            None => return false,
+        };
+        remainder = if value < 10 {
+            (remainder * 10 + value) % 97
+        } else {
+            (remainder * 100 + value) % 97
+        };
+    }
+    remainder == 1
+}
+
+fn iban_length(country: &str) -> Option<usize> {
+    let length = match country {
+        "NO" => 15,
+        "BE" => 16,
+        "DK" | "FI" | "NL" => 18,
+        "SI" => 19,
+        "AT" | "EE" | "LT" | "LU" => 20,
+        "CH" | "HR" | "LI" | "LV" => 21,
+        "BG" | "DE" | "GB" | "IE" => 22,
+        "CZ" | "ES" | "RO" | "SE" | "SK" => 24,
+        "PT" => 25,
+        "IS" => 26,
+        "FR" | "GR" | "IT" => 27,
+        "CY" | "HU" | "PL" => 28,
+        "MT" => 31,
+        _ => return None,
+    };
+    Some(length)
+}
+
+//Bank code, country, location and an optional branch
+fn bic_valid(bic: &str) -> bool {
+    let bic = normalize(bic);
+    (bic.len() == 8 || bic.len() == 11) && bic[..6].bytes().all(|b| b.is_ascii_uppercase())
+}
+
+//Formats of the number after the country prefix as used by VIES,
+//9 is a digit, A a letter and X a digit or a letter
+fn vies_patterns(country: &str) -> Option<&'static [&'static str]> {
+    let patterns: &[&str] = match country {
+        "AT" => &["U99999999"],
+        "BE" => &["9999999999"],
+        "BG" => &["999999999", "9999999999"],
+        "CY" => &["99999999A"],
+        "CZ" => &["99999999", "999999999", "9999999999"],
+        "DE" | "EE" | "EL" | "PT" => &["999999999"],
+        "DK" | "FI" | "HU" | "LU" | "MT" => &["99999999"],
+        "ES" => &["X9999999X"],
+        "FR" => &["XX999999999"],
+        "HR" | "IT" | "LV" => &["99999999999"],
+        "IE" => &["9X99999A", "9999999A", "9999999AA"],
+        "LT" => &["999999999", "999999999999"],
+        "NL" => &["999999999B99"],
+        "PL" | "SK" => &["9999999999"],
+        "RO" => &[
+            "99",
+            "999",
+            "9999",
+            "99999",
+            "999999",
+            "9999999",
+            "99999999",
+            "999999999",
+            "9999999999",
+        ],
+        "SE" => &["999999999901"],
+        "XI" => &["999999999", "999999999999", "GD999", "HA999"],
+        _ => return None,
+    };
+    Some(patterns)
+}
+
+fn matches_pattern(value: &str, pattern: &str) -> bool {
+    value.len() == pattern.len()
+        && value.chars().zip(pattern.chars()).all(|(c, p)| match p {
+            '9' => c.is_ascii_digit(),
+            'A' => c.is_ascii_alphabetic(),
+            'X' => c.is_ascii_alphanumeric(),
+            literal => c == literal,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+
+    fn paths(report: &Report) -> Vec<&str> {
+        report.errors().map(|issue| issue.path.as_str()).collect()
+    }
+
+    #[test]
+    fn slovenian_vat_ids_use_mod_11() {
+        for valid in ["12345679", "11000007", "10001000"] {
+            assert!(si_vat_id_valid(valid), "{}", valid);
+        }
+        //Wrong check digit, too short, not a number and a leading zero
+        for invalid in ["12345678", "1234567", "1234567A", "02345679"] {
+            assert!(!si_vat_id_valid(invalid), "{}", invalid);
+        }
+        //A remainder of 0 leaves no valid check digit
+        for check in 0..10 {
+            assert!(!si_vat_id_valid(&format!("1000010{}", check)));
+        }
+    }
+
+    #[test]
+    fn ibans_use_mod_97() {
+        for valid in [
+            "SI56 2633 0001 2039 086",
+            "DE89 3704 0044 0532 0130 00",
+            "GB82 WEST 1234 5698 7654 32",
+            "NO93 8601 1117 947",
+            "de89370400440532013000",
+        ] {
+            assert!(iban_valid(valid), "{}", valid);
+        }
+        for invalid in [
+            "DE89 3704 0044 0532 0130 01",
+            "DE89 3704 0044 0532 0130 0",
+            "SI56 2633 0001 2039 0860",
+            "1289 3704 0044 0532 0130 00",
+            "DE8A 3704 0044 0532 0130 00",
+            "SI56",
+        ] {
+            assert!(!iban_valid(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn iban_lengths_are_known_per_country() {
+        assert_eq!(iban_length("SI"), Some(19));
+        assert_eq!(iban_length("NO"), Some(15));
+        assert_eq!(iban_length("MT"), Some(31));
+        assert_eq!(iban_length("US"), None);
+    }
+
+    #[test]
+    fn bics_have_8_or_11_characters() {
+        for valid in ["SABRSI2X", "LJBASI2XXXX", "ljba si2x"] {
+            assert!(bic_valid(valid), "{}", valid);
+        }
+        for invalid in ["LJBASI2", "LJBASI2XX", "1JBASI2X"] {
+            assert!(!bic_valid(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn foreign_vat_ids_match_the_vies_format() {
+        assert!(matches_pattern("U12345678", "U99999999"));
+        assert!(matches_pattern("123456789B01", "999999999B99"));
+        assert!(matches_pattern("X1234567X", "X9999999X"));
+        assert!(matches_pattern("1A23456B", "9X99999A"));
+        assert!(!matches_pattern("12345678", "U99999999"));
+        assert!(!matches_pattern("123456789X01", "999999999B99"));
+        assert!(!matches_pattern("1234567", "99999999"));
+        assert!(vies_patterns("US").is_none());
+
+        assert_eq!(check_vat_id("DE 123 456 789"), Ok(()));
+        assert_eq!(check_vat_id("ATU12345678"), Ok(()));
+        assert_eq!(check_vat_id("SI 1234 5679"), Ok(()));
+        assert!(check_vat_id("DE12345678").is_err());
+        assert!(check_vat_id("SI12345678").is_err());
+        assert!(check_vat_id("").is_err());
+        //IDs from outside of the EU aren't checked
+        assert_eq!(check_vat_id("US123"), Ok(()));
+    }
+
+    #[test]
+    fn the_sample_is_valid() {
+        assert!(validate(&sample()).check().is_ok());
+    }
+
+    #[test]
+    fn credit_notes_must_refer_to_another_invoice() {
+        let mut racun = sample();
+        racun.invoice.credit_note_for = Some(racun.invoice.invoice_number);
+        assert_eq!(paths(&validate(&racun)), vec!["invoice.creditNoteFor"]);
+        racun.invoice.credit_note_for = Some(0);
+        assert_eq!(paths(&validate(&racun)), vec!["invoice.creditNoteFor"]);
+        racun.invoice.credit_note_for = Some(racun.invoice.invoice_number - 1);
+        assert!(paths(&validate(&racun)).is_empty());
+    }
+
+    #[test]
+    fn invalid_ids_are_reported_on_their_field() {
+        let mut racun = sample();
+        racun.invoice.company.company_iban = "SI56 2633 0001 2039 087".to_string();
+        racun.invoice.partner.partner_vat_id = "DE12345678".to_string();
+        assert_eq!(
+            paths(&validate(&racun)),
+            vec![
+                "invoice.company.companyIban",
+                "invoice.partner.partnerVatId"
+            ]
+        );
+    }
+}